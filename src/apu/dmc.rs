@@ -0,0 +1,201 @@
+// the timer periods of the DMC in CPU cycles, for NTSC systems.
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+// DMC is the delta modulation channel at $4010-$4013. It plays 1-bit delta encoded samples read
+// from CPU memory, each bit moving the 7-bit output level up or down by 2. The samples are read
+// by the CPU on its behalf, see fetch_address and fill.
+#[derive(Debug)]
+pub(super) struct Dmc {
+    irq_enabled: bool,
+    pub(super) irq: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+    output_level: u8,
+    // the address and length of the sample, as set by $4012 and $4013.
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    pub(super) bytes_remaining: u16,
+    pub(super) sample_buffer: Option<u8>,
+    shift_register: u8,
+    bits_remaining: u8,
+    // silence is set when the sample buffer was empty at the start of an output cycle.
+    silence: bool,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Dmc {
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            timer_period: RATE_TABLE[0],
+            timer: 0,
+            output_level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+}
+
+impl Dmc {
+    // write handles a write to one of the channel's registers, reg being 0 to 3.
+    pub(super) fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.irq_enabled = val & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = val & 0x40 != 0;
+                self.timer_period = RATE_TABLE[(val & 0x0F) as usize];
+            }
+            1 => self.output_level = val & 0x7F,
+            2 => self.sample_address = 0xC000 | (val as u16) << 6,
+            3 => self.sample_length = (val as u16) << 4 | 1,
+            _ => unreachable!(),
+        }
+    }
+
+    // set_enabled handles the DMC bit of $4015, which restarts the sample if it had finished or
+    // stops it right away.
+    pub(super) fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // fetch_address returns the address of the next sample byte when the sample buffer needs
+    // refilling.
+    pub(super) fn fetch_address(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    // fill loads the sample byte read from fetch_address into the sample buffer. The address
+    // wraps around to $8000, and the end of the sample either loops it or raises an IRQ.
+    pub(super) fn fill(&mut self, val: u8) {
+        self.sample_buffer = Some(val);
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // clock_timer is called on every CPU cycle, the rate table is in CPU cycles.
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if self.shift_register & 0x01 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+#[test]
+fn test_dmc_rate() {
+    let mut dmc = Dmc::default();
+    for (rate, period) in RATE_TABLE.iter().enumerate() {
+        dmc.write(0, rate as u8);
+        assert_eq!(dmc.timer_period, *period);
+    }
+    assert_eq!(dmc.timer_period, 54);
+
+    // the timer clocks the output unit once every period CPU cycles.
+    dmc.timer = 0;
+    dmc.clock_timer();
+    let bits_remaining = dmc.bits_remaining;
+    for _ in 0..53 {
+        dmc.clock_timer();
+    }
+    assert_eq!(dmc.bits_remaining, bits_remaining);
+    dmc.clock_timer();
+    assert_eq!(dmc.bits_remaining, bits_remaining - 1);
+}
+
+#[test]
+fn test_dmc_delta_decoder() {
+    // runs the output unit through a whole sample byte.
+    let play = |dmc: &mut Dmc, sample: u8| {
+        dmc.bytes_remaining = 1;
+        dmc.fill(sample);
+        // the byte in the buffer is only picked up at the end of the current output cycle.
+        while dmc.sample_buffer.is_some() {
+            dmc.timer = 0;
+            dmc.clock_timer();
+        }
+        for _ in 0..8 {
+            dmc.timer = 0;
+            dmc.clock_timer();
+        }
+    };
+
+    let mut dmc = Dmc::default();
+    dmc.write(1, 0x40);
+    play(&mut dmc, 0xFF);
+    assert_eq!(dmc.output(), 0x40 + 16);
+    play(&mut dmc, 0x0F);
+    assert_eq!(dmc.output(), 0x40 + 16);
+    play(&mut dmc, 0x00);
+    assert_eq!(dmc.output(), 0x40);
+
+    // the output level saturates rather than wrapping.
+    dmc.write(1, 0x7C);
+    play(&mut dmc, 0xFF);
+    assert_eq!(dmc.output(), 0x7E);
+    dmc.write(1, 0x03);
+    play(&mut dmc, 0x00);
+    assert_eq!(dmc.output(), 0x01);
+}
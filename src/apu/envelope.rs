@@ -0,0 +1,51 @@
+// Envelope generates the volume of the pulse and noise channels, either a constant volume or a
+// sawtooth that decays from 15 to 0 and optionally loops.
+#[derive(Debug, Default)]
+pub(super) struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // the constant volume, or the period of the divider when decaying.
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    // write sets the envelope from the low six bits of the channel's first register.
+    pub(super) fn write(&mut self, val: u8) {
+        self.looping = val & 0x20 != 0;
+        self.constant = val & 0x10 != 0;
+        self.volume = val & 0x0F;
+    }
+
+    pub(super) fn restart(&mut self) {
+        self.start = true;
+    }
+
+    // clock is called on every quarter frame.
+    pub(super) fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// the values loaded into the length counter, indexed by the top five bits of the channel's
+// fourth register.
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// LengthCounter silences a channel once it has played for the loaded number of half frames.
+#[derive(Debug, Default)]
+pub(super) struct LengthCounter {
+    enabled: bool,
+    pub(super) halt: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    // set_enabled is driven by $4015, disabling a channel clears its counter right away.
+    pub(super) fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    // load reloads the counter from the top five bits of val, if the channel is enabled.
+    pub(super) fn load(&mut self, val: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(val >> 3) as usize];
+        }
+    }
+
+    // clock is called on every half frame.
+    pub(super) fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub(super) fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+#[test]
+fn test_length_table() {
+    let mut length = LengthCounter::default();
+    length.load(0x08);
+    assert!(!length.active());
+
+    length.set_enabled(true);
+    let cases = [(0x00, 10), (0x08, 254), (0x18, 2), (0x80, 12), (0xF8, 30)];
+    for (val, expected) in cases.iter() {
+        length.load(*val);
+        assert_eq!(length.counter, *expected, "loading {:02X}", val);
+    }
+
+    length.clock();
+    assert_eq!(length.counter, 29);
+    length.halt = true;
+    length.clock();
+    assert_eq!(length.counter, 29);
+
+    length.set_enabled(false);
+    assert!(!length.active());
+}
@@ -0,0 +1,400 @@
+mod dmc;
+mod envelope;
+mod length_counter;
+mod noise;
+mod pulse;
+mod triangle;
+
+use dmc::Dmc;
+use noise::Noise;
+use pulse::Pulse;
+use triangle::Triangle;
+
+const CPU_FREQUENCY: f64 = 1_789_773.0;
+pub const SAMPLE_RATE: i32 = 44_100;
+
+// the frame counter clocks the envelopes and the triangle's linear counter on every quarter
+// frame, and the sweeps and length counters on every half frame. It runs a 4-step sequence,
+// raising an IRQ at the end of it, or a 5-step one which never does. These are the CPU cycles at
+// which each step happens.
+const QUARTER_FRAMES: [u64; 2] = [7457, 22371];
+const HALF_FRAMES: [u64; 2] = [14913, 29829];
+const FIVE_STEP_HALF_FRAMES: [u64; 2] = [14913, 37281];
+const FOUR_STEP_LENGTH: u64 = 29830;
+const FIVE_STEP_LENGTH: u64 = 37282;
+
+// APU implements the audio processing unit of the 2A03. It is mapped to $4000-$4013 and $4015,
+// and produces samples at SAMPLE_RATE that the frontend drains with take_samples. The DMC reads
+// its samples through the CPU, which polls dmc_fetch_address after every tick.
+pub struct APU {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    // cycles counts the CPU cycles the APU has run for, it is paced against the CPU's counter.
+    cycles: u64,
+    frame_cycle: u64,
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    // sample_clock accumulates CPU cycles until there are enough for the next sample.
+    sample_clock: f64,
+    samples: Vec<f32>,
+    // volume scales the mixer output, from 0.0 to 1.0. Muting zeroes the output while the
+    // channels keep running, so the timing of the APU is unaffected.
+    volume: f32,
+    muted: bool,
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        APU {
+            pulse_1: Pulse::new(true),
+            pulse_2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::default(),
+            dmc: Dmc::default(),
+            cycles: 0,
+            frame_cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            sample_clock: 0.0,
+            samples: vec![],
+            volume: 1.0,
+            muted: false,
+        }
+    }
+}
+
+impl APU {
+    // tick runs the APU until it catches up with the given CPU cycle count.
+    pub fn tick(&mut self, cpu_cycles: u64) {
+        // the CPU counter goes back when the console is reset.
+        if cpu_cycles < self.cycles {
+            self.cycles = cpu_cycles;
+        }
+
+        let cycles_per_sample = CPU_FREQUENCY / SAMPLE_RATE as f64;
+        while self.cycles < cpu_cycles {
+            self.step();
+            self.cycles += 1;
+
+            self.sample_clock += 1.0;
+            if self.sample_clock >= cycles_per_sample {
+                self.sample_clock -= cycles_per_sample;
+                let sample = self.output();
+                self.samples.push(sample);
+            }
+        }
+    }
+
+    // step runs the APU for a single CPU cycle.
+    fn step(&mut self) {
+        self.triangle.clock_timer();
+        self.dmc.clock_timer();
+        if self.cycles % 2 == 1 {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+            self.noise.clock_timer();
+        }
+
+        self.step_frame_counter();
+    }
+
+    fn step_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        let (half_frames, length) = if self.five_step {
+            (FIVE_STEP_HALF_FRAMES, FIVE_STEP_LENGTH)
+        } else {
+            (HALF_FRAMES, FOUR_STEP_LENGTH)
+        };
+
+        if QUARTER_FRAMES.contains(&self.frame_cycle) {
+            self.clock_quarter_frame();
+        } else if half_frames.contains(&self.frame_cycle) {
+            self.clock_quarter_frame();
+            self.clock_half_frame();
+        }
+
+        if !self.five_step && !self.irq_inhibit && self.frame_cycle == HALF_FRAMES[1] {
+            self.frame_irq = true;
+        }
+        if self.frame_cycle == length {
+            self.frame_cycle = 0;
+        }
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    // returns whether the frame counter or the DMC are asserting the CPU's IRQ line.
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    // dmc_fetch_address returns the address of the DMC's next sample byte when it needs one, the
+    // CPU reads it and hands it over with dmc_fill.
+    pub fn dmc_fetch_address(&self) -> Option<u16> {
+        self.dmc.fetch_address()
+    }
+
+    pub fn dmc_fill(&mut self, val: u8) {
+        self.dmc.fill(val)
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        self.pulse_1.clock_quarter_frame();
+        self.pulse_2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    fn clock_half_frame(&mut self) {
+        self.pulse_1.clock_half_frame();
+        self.pulse_2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    // output is the mixer's output scaled by the master volume.
+    fn output(&self) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        self.mix() * self.volume
+    }
+
+    // mix mixes the channels with the non-linear approximation described in
+    // https://wiki.nesdev.com/w/index.php/APU_Mixer, the result is in the 0.0 to 1.0 range.
+    fn mix(&self) -> f32 {
+        let pulse = (self.pulse_1.output() + self.pulse_2.output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.output() as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+
+        pulse_out + tnd_out
+    }
+
+    // take_samples returns the samples produced since the last call.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse_1.write(addr - 0x4000, val),
+            0x4004..=0x4007 => self.pulse_2.write(addr - 0x4004, val),
+            0x4008..=0x400B => self.triangle.write(addr - 0x4008, val),
+            0x400C..=0x400F => self.noise.write(addr - 0x400C, val),
+            0x4010..=0x4013 => self.dmc.write(addr - 0x4010, val),
+            0x4015 => {
+                self.pulse_1.length.set_enabled(val & 0x01 != 0);
+                self.pulse_2.length.set_enabled(val & 0x02 != 0);
+                self.triangle.length.set_enabled(val & 0x04 != 0);
+                self.noise.length.set_enabled(val & 0x08 != 0);
+                self.dmc.set_enabled(val & 0x10 != 0);
+            }
+            0x4017 => {
+                self.five_step = val & 0x80 != 0;
+                self.irq_inhibit = val & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+
+                // writing restarts the sequence, the 5-step mode clocks every unit right away.
+                self.frame_cycle = 0;
+                if self.five_step {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // read_status returns the value of $4015, which reports the channels whose length counter
+    // is still running, whether the DMC has bytes left to play, and the frame and DMC IRQ flags.
+    // Only the frame IRQ flag is cleared by the read.
+    pub fn read_status(&mut self) -> u8 {
+        let channels = [
+            self.pulse_1.length.active(),
+            self.pulse_2.length.active(),
+            self.triangle.length.active(),
+            self.noise.length.active(),
+            self.dmc.bytes_remaining > 0,
+        ];
+
+        let status = channels
+            .iter()
+            .enumerate()
+            .filter(|(_, active)| **active)
+            .fold(0, |status, (bit, _)| status | 1 << bit);
+
+        let frame_irq = std::mem::replace(&mut self.frame_irq, false);
+        status | (frame_irq as u8) << 6 | (self.dmc.irq as u8) << 7
+    }
+}
+
+#[test]
+fn test_status_and_length_counters() {
+    let mut apu = APU::default();
+    apu.write(0x4003, 0x08);
+    assert_eq!(apu.read_status(), 0x00);
+
+    apu.write(0x4015, 0x0F);
+    apu.write(0x4003, 0x18); // length 2
+    apu.write(0x400F, 0x08); // length 254
+    assert_eq!(apu.read_status(), 0x09);
+
+    // two half frames silence the first pulse channel.
+    apu.write(0x4017, 0x40);
+    apu.tick(FOUR_STEP_LENGTH);
+    assert_eq!(apu.read_status(), 0x08);
+
+    // a second of audio is about SAMPLE_RATE samples.
+    apu.take_samples();
+    apu.tick(FOUR_STEP_LENGTH + CPU_FREQUENCY as u64);
+    let samples = apu.take_samples().len() as i32;
+    assert!((samples - SAMPLE_RATE).abs() < 100, "{} samples", samples);
+    assert!(apu.take_samples().is_empty());
+}
+
+#[test]
+fn test_channel_enable() {
+    let mut apu = APU::default();
+    apu.write(0x4015, 0x0F);
+    apu.write(0x4003, 0x08);
+    apu.write(0x4007, 0x08);
+    apu.write(0x400B, 0x08);
+    apu.write(0x400F, 0x08);
+    assert_eq!(apu.read_status(), 0x0F);
+
+    // disabling a channel clears its length counter right away.
+    apu.write(0x4015, 0x0A);
+    assert!(!apu.pulse_1.length.active());
+    assert!(!apu.triangle.length.active());
+    assert_eq!(apu.read_status(), 0x0A);
+
+    // and keeps it from being loaded until it is enabled again.
+    apu.write(0x4003, 0x08);
+    assert_eq!(apu.read_status(), 0x0A);
+    apu.write(0x4015, 0x0F);
+    assert_eq!(apu.read_status(), 0x0A);
+    apu.write(0x4003, 0x08);
+    assert_eq!(apu.read_status(), 0x0B);
+
+    // writing $4015 acknowledges the DMC IRQ.
+    apu.dmc.irq = true;
+    assert_eq!(apu.read_status() & 0x80, 0x80);
+    apu.write(0x4015, 0x0F);
+    assert_eq!(apu.read_status() & 0x80, 0x00);
+}
+
+#[test]
+fn test_frame_irq() {
+    // counts the cycles on which the IRQ line goes from low to high.
+    let irqs = |apu: &mut APU, cycles: u64| {
+        let mut count = 0;
+        for _ in 0..cycles {
+            let asserted = apu.irq();
+            apu.tick(apu.cycles + 1);
+            if !asserted && apu.irq() {
+                count += 1;
+            }
+        }
+        count
+    };
+
+    let mut apu = APU::default();
+    apu.write(0x4017, 0x00);
+    assert_eq!(irqs(&mut apu, FOUR_STEP_LENGTH - 2), 0);
+    assert_eq!(irqs(&mut apu, 2), 1);
+    // the line is held until $4015 is read.
+    assert_eq!(irqs(&mut apu, FOUR_STEP_LENGTH), 0);
+    assert!(apu.irq());
+    assert_eq!(apu.read_status() & 0x40, 0x40);
+    assert!(!apu.irq());
+
+    // neither the 5-step mode nor an inhibited 4-step one raise IRQs.
+    apu.write(0x4017, 0x80);
+    assert_eq!(irqs(&mut apu, 2 * FIVE_STEP_LENGTH), 0);
+    apu.write(0x4017, 0x40);
+    assert_eq!(irqs(&mut apu, 2 * FOUR_STEP_LENGTH), 0);
+}
+
+#[test]
+fn test_dmc_samples() {
+    let mut apu = APU::default();
+    apu.write(0x4010, 0x80);
+    apu.write(0x4012, 0x01);
+    apu.write(0x4013, 0x01);
+    assert_eq!(apu.dmc_fetch_address(), None);
+
+    // the sample at $C040 is 17 bytes long, and raises an IRQ once the last one is read.
+    apu.write(0x4015, 0x10);
+    assert_eq!(apu.read_status(), 0x10);
+    for i in 0..17 {
+        assert_eq!(apu.dmc_fetch_address(), Some(0xC040 + i));
+        apu.dmc_fill(0x00);
+        assert_eq!(apu.dmc_fetch_address(), None);
+        apu.dmc.sample_buffer = None;
+    }
+    assert!(apu.irq());
+    assert_eq!(apu.read_status(), 0x80);
+    apu.write(0x4015, 0x00);
+    assert!(!apu.irq());
+
+    // looping samples start over instead.
+    apu.write(0x4010, 0xC0);
+    apu.write(0x4013, 0x00);
+    apu.write(0x4015, 0x10);
+    apu.dmc_fill(0x00);
+    assert!(!apu.irq());
+    apu.dmc.sample_buffer = None;
+    assert_eq!(apu.dmc_fetch_address(), Some(0xC040));
+}
+
+#[test]
+fn test_volume() {
+    let mut apu = APU::default();
+    apu.write(0x4011, 0x40);
+    let full = apu.output();
+    assert!(full > 0.0);
+
+    for volume in [0.0, 0.25, 0.5].iter() {
+        apu.set_volume(*volume);
+        assert_eq!(apu.output(), full * volume);
+    }
+    apu.set_volume(2.0);
+    assert_eq!(apu.output(), full);
+
+    // muting zeroes the samples but the APU keeps running.
+    apu.set_muted(true);
+    apu.tick(1000);
+    assert!(apu.take_samples().iter().all(|s| *s == 0.0));
+    assert_eq!(apu.cycles, 1000);
+    assert_eq!(apu.output(), 0.0);
+}
@@ -0,0 +1,84 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+// the timer periods of the noise channel in CPU cycles, for NTSC systems.
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+// Noise is the pseudo-random noise channel at $400C-$400F, driven by a 15 bit linear feedback
+// shift register.
+#[derive(Debug)]
+pub(super) struct Noise {
+    // in short mode the feedback is taken from bit 6 instead of bit 1, producing a metallic tone.
+    short_mode: bool,
+    shift_register: u16,
+    timer_period: u16,
+    timer: u16,
+    envelope: Envelope,
+    pub(super) length: LengthCounter,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise {
+            short_mode: false,
+            shift_register: 1,
+            timer_period: PERIOD_TABLE[0],
+            timer: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+}
+
+impl Noise {
+    // write handles a write to one of the channel's registers, reg being 0 to 3.
+    pub(super) fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.length.halt = val & 0x20 != 0;
+                self.envelope.write(val);
+            }
+            1 => {}
+            2 => {
+                self.short_mode = val & 0x80 != 0;
+                self.timer_period = PERIOD_TABLE[(val & 0x0F) as usize];
+            }
+            3 => {
+                self.length.load(val);
+                self.envelope.restart();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // clock_timer is called on every APU cycle. The period table is in CPU cycles, so the timer
+    // counts down by two.
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer <= 2 {
+            self.timer = self.timer_period;
+            let bit = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift_register ^ (self.shift_register >> bit)) & 0x01;
+            self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 2;
+        }
+    }
+
+    pub(super) fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub(super) fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        if self.shift_register & 0x01 != 0 || !self.length.active() {
+            return 0;
+        }
+
+        self.envelope.output()
+    }
+}
@@ -0,0 +1,173 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// Sweep periodically bends the pitch of a pulse channel up or down.
+#[derive(Debug, Default)]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    reload: bool,
+    divider: u8,
+}
+
+// Pulse is one of the two square wave channels, at $4000-$4003 and $4004-$4007.
+#[derive(Debug, Default)]
+pub(super) struct Pulse {
+    // the first pulse channel negates the sweep change with one's complement, the second one
+    // with two's complement.
+    ones_complement: bool,
+    duty: u8,
+    step: u8,
+    // timer_period is the 11 bit period of the timer, the channel's frequency is
+    // CPU_FREQUENCY / (16 * (timer_period + 1)).
+    pub(super) timer_period: u16,
+    timer: u16,
+    sweep: Sweep,
+    envelope: Envelope,
+    pub(super) length: LengthCounter,
+}
+
+impl Pulse {
+    pub(super) fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            ..Default::default()
+        }
+    }
+
+    // write handles a write to one of the channel's four registers, reg being 0 to 3.
+    pub(super) fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.duty = val >> 6;
+                self.length.halt = val & 0x20 != 0;
+                self.envelope.write(val);
+            }
+            1 => {
+                self.sweep.enabled = val & 0x80 != 0;
+                self.sweep.period = (val >> 4) & 0x07;
+                self.sweep.negate = val & 0x08 != 0;
+                self.sweep.shift = val & 0x07;
+                self.sweep.reload = true;
+            }
+            2 => self.timer_period = (self.timer_period & 0x0700) | val as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | (val as u16 & 0x07) << 8;
+                self.length.load(val);
+                self.step = 0;
+                self.envelope.restart();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // clock_timer is called on every APU cycle, that is every other CPU cycle.
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub(super) fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub(super) fn clock_half_frame(&mut self) {
+        self.length.clock();
+
+        let sweep = &mut self.sweep;
+        if sweep.divider == 0 && sweep.enabled && sweep.shift > 0 && !self.muted() {
+            self.timer_period = self.target_period();
+        }
+
+        let sweep = &mut self.sweep;
+        if sweep.divider == 0 || sweep.reload {
+            sweep.divider = sweep.period;
+            sweep.reload = false;
+        } else {
+            sweep.divider -= 1;
+        }
+    }
+
+    // target_period is the period the sweep unit would bend the channel to.
+    fn target_period(&self) -> u16 {
+        let change = self.timer_period >> self.sweep.shift;
+        if !self.sweep.negate {
+            self.timer_period + change
+        } else if self.ones_complement {
+            self.timer_period.saturating_sub(change + 1)
+        } else {
+            self.timer_period.saturating_sub(change)
+        }
+    }
+
+    // the sweep unit mutes the channel when the period is too short, or when it would bend it
+    // past the 11 bit range. This happens even if the sweep is disabled.
+    fn muted(&self) -> bool {
+        self.timer_period < 8 || self.target_period() > 0x07FF
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        if DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+            || !self.length.active()
+            || self.muted()
+        {
+            return 0;
+        }
+
+        self.envelope.output()
+    }
+}
+
+#[test]
+fn test_pulse_timer_period() {
+    let mut pulse = Pulse::new(true);
+    pulse.write(2, 0xFD);
+    pulse.write(3, 0x01);
+    assert_eq!(pulse.timer_period, 0x01FD);
+
+    // the timer runs for period + 1 APU cycles before advancing the sequencer.
+    pulse.timer = 0;
+    pulse.clock_timer();
+    let step = pulse.step;
+    for _ in 0..0x01FD {
+        pulse.clock_timer();
+        assert_eq!(pulse.step, step);
+    }
+    pulse.clock_timer();
+    assert_eq!(pulse.step, (step + 1) % 8);
+
+    // periods under 8 and sweeps past $7FF mute the channel.
+    pulse.write(3, 0x00);
+    pulse.write(2, 0x07);
+    assert!(pulse.muted());
+    pulse.write(2, 0x08);
+    assert!(!pulse.muted());
+    pulse.write(3, 0x07);
+    pulse.write(2, 0xFF);
+    pulse.write(1, 0x01);
+    assert!(pulse.muted());
+
+    // the first channel negates with one's complement, the second one with two's complement.
+    let mut pulse_1 = Pulse::new(true);
+    let mut pulse_2 = Pulse::new(false);
+    for pulse in [&mut pulse_1, &mut pulse_2].iter_mut() {
+        pulse.write(2, 0x00);
+        pulse.write(3, 0x01);
+        pulse.write(1, 0x89);
+    }
+    assert_eq!(pulse_1.target_period(), 0x0100 - 0x80 - 1);
+    assert_eq!(pulse_2.target_period(), 0x0100 - 0x80);
+}
@@ -0,0 +1,75 @@
+use super::length_counter::LengthCounter;
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+// Triangle is the triangle wave channel at $4008-$400B. It has no volume control, but on top of
+// the length counter it has a linear counter with a finer resolution.
+#[derive(Debug, Default)]
+pub(super) struct Triangle {
+    // control both halts the length counter and stops the linear counter from being reloaded.
+    control: bool,
+    linear_reload_value: u8,
+    linear_reload: bool,
+    linear_counter: u8,
+    step: u8,
+    timer_period: u16,
+    timer: u16,
+    pub(super) length: LengthCounter,
+}
+
+impl Triangle {
+    // write handles a write to one of the channel's registers, reg being 0 to 3.
+    pub(super) fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.control = val & 0x80 != 0;
+                self.length.halt = self.control;
+                self.linear_reload_value = val & 0x7F;
+            }
+            1 => {}
+            2 => self.timer_period = (self.timer_period & 0x0700) | val as u16,
+            3 => {
+                self.timer_period = (self.timer_period & 0x00FF) | (val as u16 & 0x07) << 8;
+                self.length.load(val);
+                self.linear_reload = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // clock_timer is called on every CPU cycle, the triangle's timer runs twice as fast as the
+    // other channels'.
+    pub(super) fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub(super) fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub(super) fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    pub(super) fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+}
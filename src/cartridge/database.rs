@@ -0,0 +1,93 @@
+use super::Mirroring;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// the bundled database of known-bad headers, see games.txt for its format.
+const GAMES: &str = include_str!("games.txt");
+
+// Fixes are the header values that replace the ones in a known-bad header.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Fixes {
+    pub mapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+}
+
+// lookup returns the fixes the bundled database has for the ROM with the given CRC32, if any. The
+// database is parsed the first time it's looked up.
+pub fn lookup(crc32: u32) -> Option<Fixes> {
+    static DATABASE: OnceLock<HashMap<u32, Fixes>> = OnceLock::new();
+    let games = DATABASE.get_or_init(|| {
+        parse(GAMES).unwrap_or_else(|err| {
+            log::error!("invalid game database: {}", err);
+            HashMap::new()
+        })
+    });
+    games.get(&crc32).cloned()
+}
+
+// parse reads a game database with one game per line, see games.txt.
+fn parse(db: &str) -> Result<HashMap<u32, Fixes>, String> {
+    let mut games = HashMap::new();
+    for line in db.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let crc32 = fields.next().unwrap_or("");
+        let crc32 =
+            u32::from_str_radix(crc32, 16).map_err(|_| format!("invalid CRC32: {}", crc32))?;
+        let mut fixes = Fixes::default();
+        for field in fields {
+            let mut parts = field.splitn(2, '=');
+            let (key, val) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+            match key {
+                "mapper" => {
+                    let mapper = val
+                        .parse()
+                        .map_err(|_| format!("invalid mapper: {}", val))?;
+                    fixes.mapper = Some(mapper);
+                }
+                "mirroring" => {
+                    let mirroring = match val {
+                        "horizontal" => Mirroring::Horizontal,
+                        "vertical" => Mirroring::Vertical,
+                        "four-screen" => Mirroring::FourScreen,
+                        _ => return Err(format!("invalid mirroring: {}", val)),
+                    };
+                    fixes.mirroring = Some(mirroring);
+                }
+                _ => return Err(format!("unknown field: {}", field)),
+            }
+        }
+        games.insert(crc32, fixes);
+    }
+    Ok(games)
+}
+
+#[test]
+fn test_parse() {
+    let games = parse(
+        "# a comment\n\
+         0123ABCD mapper=2 mirroring=vertical  # Some Game\n\
+         \n\
+         DEADBEEF mirroring=four-screen\n",
+    )
+    .unwrap();
+    assert_eq!(
+        games.get(&0x0123_ABCD),
+        Some(&Fixes {
+            mapper: Some(2),
+            mirroring: Some(Mirroring::Vertical),
+        })
+    );
+    assert_eq!(games[&0xDEAD_BEEF].mapper, None);
+    assert_eq!(games.get(&0x1234_5678), None);
+
+    assert!(parse("0123ABCD mapper=x").is_err());
+    assert!(parse("0123ABCD size=2").is_err());
+    assert!(parse("game mapper=2").is_err());
+    // the bundled database must parse.
+    assert!(!parse(GAMES).unwrap().is_empty());
+}
@@ -1,10 +1,13 @@
-use super::Header;
+use super::{read_wrapped, write_wrapped, Header, Mirroring};
 
 // NROM mapper implementation
 pub struct Mapper {
     header: Header,
     prg_rom: Vec<u8>,
     chr_rom: Vec<u8>,
+    // cartridges without CHR ROM have 8kb of CHR RAM instead.
+    chr_ram: Vec<u8>,
+    prg_ram: Vec<u8>,
 }
 
 impl Mapper {
@@ -14,26 +17,51 @@ impl Mapper {
 
         let chr_rom_size = header.chr_rom_size as usize * 0x2000;
         let chr_rom = data[prg_rom_size..prg_rom_size + chr_rom_size].to_vec();
+        let chr_ram = if chr_rom.is_empty() {
+            vec![0; header.chr_ram_bytes()]
+        } else {
+            vec![]
+        };
+
+        let prg_ram = vec![0; header.prg_ram_bytes()];
 
         Mapper {
             header,
             prg_rom,
             chr_rom,
+            chr_ram,
+            prg_ram,
         }
     }
 }
 
 impl super::Mapper for Mapper {
+    fn mirroring(&self) -> Mirroring {
+        self.header.mirroring
+    }
+
+    fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
     fn readb(&self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x1FFF => {
                 if self.chr_rom.is_empty() {
-                    return 0;
+                    return read_wrapped(&self.chr_ram, addr as usize);
                 }
 
-                self.chr_rom[addr as usize]
+                read_wrapped(&self.chr_rom, addr as usize)
             }
-            0x6000..=0x7FFF => 0,
+            0x6000..=0x7FFF => read_wrapped(&self.prg_ram, addr as usize - 0x6000),
             0x8000..=0xBFFF => self.prg_rom[addr as usize - 0x8000],
             0xC000..=0xFFFF => {
                 let addr = if self.header.prg_rom_size > 1 {
@@ -49,11 +77,49 @@ impl super::Mapper for Mapper {
 
     fn writeb(&mut self, addr: u16, val: u8) {
         match addr {
-            0x6000..=0x6003 => {}
-            0x6004..=0x7FFF => {
-                print!("{}", val as char);
-            }
-            _ => unreachable!("cannot write to NROM"),
+            0x0000..=0x1FFF => write_wrapped(&mut self.chr_ram, addr as usize, val),
+            0x6000..=0x7FFF => write_wrapped(&mut self.prg_ram, addr as usize - 0x6000, val),
+            // NROM has no registers, games writing to ROM and the dummy writes of read-modify-write
+            // instructions are ignored.
+            _ => {}
         }
     }
 }
+
+#[test]
+fn test_readw() {
+    use crate::cartridge::mapper::Mapper;
+
+    let header = Header {
+        prg_rom_size: 1,
+        chr_rom_size: 1,
+        ..Default::default()
+    };
+    let mut data = vec![0; 0x4000 + 0x2000];
+    data[0x3FFC] = 0x34;
+    data[0x3FFD] = 0x12;
+    let m = super::mapper_000::Mapper::new(header, data);
+
+    assert_eq!(m.readw(0xFFFC), 0x1234);
+    // a 16kb PRG ROM is mirrored at $C000.
+    assert_eq!(m.readw(0xBFFC), 0x1234);
+}
+
+#[test]
+fn test_rom_writes_ignored() {
+    use crate::cartridge::mapper::Mapper;
+
+    let header = Header {
+        prg_rom_size: 1,
+        chr_rom_size: 1,
+        ..Default::default()
+    };
+    let mut data = vec![0; 0x4000 + 0x2000];
+    data[0] = 0x42;
+    let mut m = super::mapper_000::Mapper::new(header, data);
+
+    m.writeb(0x8000, 0xFF);
+    m.writeb(0x5000, 0xFF);
+    assert_eq!(m.readb(0x8000), 0x42);
+    assert_eq!(m.readb(0x5000), 0);
+}
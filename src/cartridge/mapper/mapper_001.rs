@@ -1,5 +1,5 @@
 #![allow(unused)]
-use super::Header;
+use super::{read_wrapped, write_wrapped, Header, Mirroring};
 
 pub struct Mapper {
     shift_register: u8,
@@ -8,6 +8,9 @@ pub struct Mapper {
     prg_rom_size: usize,
     prg_rom: Vec<u8>,
     chr_rom: Vec<u8>,
+    // cartridges without CHR ROM have 8kb of CHR RAM instead.
+    chr_ram: Vec<u8>,
+    prg_ram: Vec<u8>,
     chr_bank_1: usize,
     chr_bank_2: usize,
     prg_bank: usize,
@@ -15,26 +18,40 @@ pub struct Mapper {
     prg_offsets: [u32; 2],
     chr_offsets: [u32; 2],
     control: u8,
+    mirroring: Mirroring,
 }
 
 impl Mapper {
     pub fn new(header: Header, data: Vec<u8>) -> Mapper {
         let prg_rom_size = 0x4000 * header.prg_rom_size;
         let (prg_rom, chr_rom) = data.split_at(prg_rom_size);
-        Mapper {
+        let prg_ram_size = header.prg_ram_bytes();
+        let chr_ram_size = if chr_rom.is_empty() {
+            header.chr_ram_bytes()
+        } else {
+            0
+        };
+        let mirroring = header.mirroring;
+        let mut mapper = Mapper {
             shift_register: 0x10,
             must_write_register: false,
             header,
             prg_rom_size,
             prg_rom: prg_rom.to_vec(),
             chr_rom: chr_rom.to_vec(),
+            chr_ram: vec![0; chr_ram_size],
+            prg_ram: vec![0; prg_ram_size],
             chr_bank_1: 0,
             chr_bank_2: 0,
             prg_bank: 0,
             prg_offsets: [0; 2],
             chr_offsets: [0; 2],
-            control: 0,
-        }
+            // the PRG mode starts with the last bank fixed at $C000.
+            control: 0x0C,
+            mirroring,
+        };
+        mapper.update_offsets();
+        mapper
     }
 
     fn write_shift_register(&mut self, addr: u16, val: u8) {
@@ -49,15 +66,13 @@ impl Mapper {
             // when a 1 is pushed into the first bit the register should be written in the
             // next write attempt.
             if done {
+                let value = self.shift_register;
                 match addr {
-                    // 0x8000..=0x9FFF => m.writeControl(value),
-                    // 0x9FFF..=0xBFFF => m.writeCHRBank0(value),
-                    // 0xBFFF..=0xDFFF => m.writeCHRBank1(value),
                     0x0000..=0x7FFF => unreachable!(),
-                    0x8000..=0xDFFE => {}
-                    0xDFFF..=0xFFFF => {
-                        self.prg_bank = (val & 0x0F) as usize;
-                    }
+                    0x8000..=0x9FFF => self.write_control(value),
+                    0xA000..=0xBFFF => self.chr_bank_1 = value as usize,
+                    0xC000..=0xDFFF => self.chr_bank_2 = value as usize,
+                    0xE000..=0xFFFF => self.prg_bank = (value & 0x0F) as usize,
                 }
 
                 self.shift_register = 0x10;
@@ -66,6 +81,18 @@ impl Mapper {
         }
     }
 
+    fn write_control(&mut self, value: u8) {
+        self.control = value;
+        if self.header.mirroring != Mirroring::FourScreen {
+            self.mirroring = match value & 0x03 {
+                0 => Mirroring::SingleScreenLower,
+                1 => Mirroring::SingleScreenUpper,
+                2 => Mirroring::Vertical,
+                _ => Mirroring::Horizontal,
+            };
+        }
+    }
+
     fn update_offsets(&mut self) {
         match (self.control & 0x0C) >> 2 {
             0 | 1 => {
@@ -97,25 +124,45 @@ impl Mapper {
     }
 
     fn prg_offset(&self, index: u32) -> u32 {
-        (index % ((self.prg_rom.len() as u32) / 0x4000)) * 0x4000
+        let banks = (self.prg_rom.len() as u32) / 0x4000;
+        if banks == 0 {
+            return 0;
+        }
+
+        (index % banks) * 0x4000
     }
 
     fn chr_offset(&self, index: u32) -> u32 {
-        0
-        // (index % ((self.chr_rom.len() as u32) / 0x1000)) * 0x1000
+        let banks = (self.chr_rom.len() as u32) / 0x1000;
+        if banks == 0 {
+            return 0;
+        }
+
+        (index % banks) * 0x1000
     }
 }
 
 impl super::Mapper for Mapper {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
     fn writeb(&mut self, addr: u16, val: u8) {
         match addr {
-            0x4020..=0x5FFF => {
-                print!("{}", val as char);
-            }
-            0x6000..=0x6003 => {}
-            0x6004..=0x7FFF => {
-                print!("{}", val as char);
-            }
+            0x0000..=0x1FFF => write_wrapped(&mut self.chr_ram, addr as usize, val),
+            0x6000..=0x7FFF => write_wrapped(&mut self.prg_ram, addr as usize - 0x6000, val),
             0x8000..=0xFFFF => self.write_shift_register(addr, val),
             _ => {}
         }
@@ -125,14 +172,16 @@ impl super::Mapper for Mapper {
         match addr {
             0x0000..=0x1FFF => {
                 if self.chr_rom.is_empty() {
-                    return 0;
+                    return read_wrapped(&self.chr_ram, addr as usize);
                 }
 
-                let bank_offset = self.chr_bank_1 * 0x2000;
-                self.chr_rom[bank_offset + addr as usize]
+                let bank = addr / 0x1000;
+                let offset = addr % 0x1000;
+                let addr = self.chr_offsets[bank as usize] + (offset as u32);
+                read_wrapped(&self.chr_rom, addr as usize)
             }
             0x4020..=0x5FFF => 0,
-            0x6000..=0x7FFF => 0,
+            0x6000..=0x7FFF => read_wrapped(&self.prg_ram, addr as usize - 0x6000),
             0x8000..=0xFFFF => {
                 let addr = addr - 0x8000;
                 let bank = addr / 0x4000;
@@ -140,7 +189,10 @@ impl super::Mapper for Mapper {
                 let addr = self.prg_offsets[bank as usize] + (offset as u32);
                 self.prg_rom[addr as usize]
             }
-            _ => unimplemented!("cMMC1 read"),
+            _ => {
+                log::error!("MMC1 read of unmapped address ${:04X}", addr);
+                0
+            }
         }
     }
 }
@@ -153,6 +205,8 @@ fn test_write_shift_register() {
         prg_rom_size: 1,
         chr_rom_size: 0,
         mapper: 1,
+        mirroring: Mirroring::Horizontal,
+        ..Default::default()
     };
     let data = [0; 0x16000].to_vec();
     let mut m = super::mapper_001::Mapper::new(header, data);
@@ -172,3 +226,46 @@ fn test_write_shift_register() {
     m.writeb(0xE000, 0x01); // shift register is reset to 0x10
     assert_eq!(m.shift_register, 0b0001_0000);
 }
+
+#[cfg(test)]
+fn test_write_register(m: &mut Mapper, addr: u16, val: u8) {
+    use crate::cartridge::mapper::Mapper;
+
+    for i in 0..5 {
+        m.writeb(addr, (val >> i) & 0x01);
+    }
+}
+
+#[test]
+fn test_chr_banking() {
+    use crate::cartridge::mapper::Mapper;
+
+    let header = Header {
+        prg_rom_size: 2,
+        chr_rom_size: 2,
+        mapper: 1,
+        mirroring: Mirroring::Horizontal,
+        ..Default::default()
+    };
+    // every 4kb CHR bank is filled with its own index.
+    let mut data = vec![0; 2 * 0x4000];
+    for bank in 0..4 {
+        data.extend(vec![bank; 0x1000]);
+    }
+    let mut m = super::mapper_001::Mapper::new(header, data);
+    assert_eq!(m.readb(0x0000), 0);
+    assert_eq!(m.readb(0x1000), 1);
+
+    // 8kb mode ignores the low bit of the bank number.
+    test_write_register(&mut m, 0xA000, 3);
+    assert_eq!(m.readb(0x0000), 2);
+    assert_eq!(m.readb(0x1000), 3);
+
+    // 4kb mode switches both halves separately, and wraps around the CHR ROM size.
+    test_write_register(&mut m, 0x8000, 0x1E);
+    test_write_register(&mut m, 0xA000, 3);
+    test_write_register(&mut m, 0xC000, 5);
+    assert_eq!(m.readb(0x0000), 3);
+    assert_eq!(m.readb(0x1000), 1);
+    assert_eq!(m.mirroring(), Mirroring::Vertical);
+}
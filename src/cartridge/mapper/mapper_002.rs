@@ -0,0 +1,98 @@
+use super::{read_wrapped, write_wrapped, Header, Mirroring};
+
+// UxROM mapper implementation
+pub struct Mapper {
+    header: Header,
+    prg_rom: Vec<u8>,
+    // UxROM boards have 8kb of CHR RAM rather than CHR ROM.
+    chr_ram: Vec<u8>,
+    selected_bank: usize,
+}
+
+impl Mapper {
+    pub fn new(header: Header, data: Vec<u8>) -> Self {
+        let prg_rom_size = header.prg_rom_size * 0x4000;
+        let prg_rom = data[..prg_rom_size].to_vec();
+        let chr_ram_size = header.chr_ram_bytes();
+
+        Mapper {
+            header,
+            prg_rom,
+            chr_ram: vec![0; chr_ram_size],
+            selected_bank: 0,
+        }
+    }
+
+    fn prg_offset(&self, bank: usize) -> usize {
+        (bank % self.header.prg_rom_size) * 0x4000
+    }
+}
+
+impl super::Mapper for Mapper {
+    fn mirroring(&self) -> Mirroring {
+        self.header.mirroring
+    }
+
+    // UxROM has no PRG RAM.
+    fn is_mapped(&self, addr: u16) -> bool {
+        addr >= 0x8000
+    }
+
+    fn readb(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => read_wrapped(&self.chr_ram, addr as usize),
+            // the first window is switchable, the second one is fixed to the last bank.
+            0x8000..=0xBFFF => {
+                let offset = self.prg_offset(self.selected_bank);
+                self.prg_rom[offset + (addr as usize - 0x8000)]
+            }
+            0xC000..=0xFFFF => {
+                let offset = self.prg_offset(self.header.prg_rom_size - 1);
+                self.prg_rom[offset + (addr as usize - 0xC000)]
+            }
+            _ => 0,
+        }
+    }
+
+    fn writeb(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => write_wrapped(&mut self.chr_ram, addr as usize, val),
+            0x8000..=0xFFFF => self.selected_bank = (val & 0x0F) as usize,
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_bank_switching() {
+    use crate::cartridge::mapper::Mapper;
+
+    let header = Header {
+        prg_rom_size: 4,
+        chr_rom_size: 0,
+        mapper: 2,
+        mirroring: Mirroring::Vertical,
+        ..Default::default()
+    };
+    // the first byte of every bank holds the bank number.
+    let mut data = vec![0; 4 * 0x4000];
+    for bank in 0..4 {
+        data[bank * 0x4000] = bank as u8;
+    }
+    let mut m = super::mapper_002::Mapper::new(header, data);
+
+    assert_eq!(m.readb(0x8000), 0);
+    assert_eq!(m.readb(0xC000), 3);
+
+    m.writeb(0x8000, 2);
+    assert_eq!(m.readb(0x8000), 2);
+    assert_eq!(m.readb(0xC000), 3);
+
+    m.writeb(0xFFFF, 1);
+    assert_eq!(m.readb(0x8000), 1);
+    assert_eq!(m.readb(0xC000), 3);
+
+    // CHR RAM is writable.
+    m.writeb(0x0010, 0x42);
+    assert_eq!(m.readb(0x0010), 0x42);
+}
@@ -1,21 +1,21 @@
-use super::Header;
+use super::{read_wrapped, write_wrapped, Header, Mirroring};
 
 #[allow(unused)]
 pub struct Mapper {
     header: Header,
-    prg_rom_size: usize,
     prg_rom: Vec<u8>,
     chr_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
     selected_bank: usize,
 }
 
 impl Mapper {
     pub fn new(header: Header, data: Vec<u8>) -> Mapper {
-        let prg_rom_size = header.prg_rom_size as usize;
-        let (prg_rom, chr_rom) = data.split_at(0x4000 * prg_rom_size);
+        let (prg_rom, chr_rom) = data.split_at(0x4000 * header.prg_rom_size as usize);
+        let prg_ram = vec![0; header.prg_ram_bytes()];
         Mapper {
             header,
-            prg_rom_size,
+            prg_ram,
             prg_rom: prg_rom.to_vec(),
             chr_rom: chr_rom.to_vec(),
             selected_bank: 0,
@@ -24,32 +24,80 @@ impl Mapper {
 }
 
 impl super::Mapper for Mapper {
+    fn mirroring(&self) -> Mirroring {
+        self.header.mirroring
+    }
+
+    fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
     fn writeb(&mut self, addr: u16, val: u8) {
         match addr {
-            0x4020..=0x5FFF => {
-                print!("{}", val as char);
-            }
-            0x6000..=0x6003 => {}
-            0x6004..=0x7FFF => {
-                print!("{}", val as char);
-            }
-            0x8000..=0xFFFF => self.selected_bank = (addr & 0x03) as usize,
-            _ => panic!("not implemented"),
+            0x4020..=0x5FFF => {}
+            0x6000..=0x7FFF => write_wrapped(&mut self.prg_ram, addr as usize - 0x6000, val),
+            0x8000..=0xFFFF => self.selected_bank = (val & 0x03) as usize,
+            _ => log::error!("CNROM write of unmapped address ${:04X}", addr),
         }
     }
 
     fn readb(&self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x1FFF => {
-                let bank_offset = self.selected_bank * 0x2000;
-                self.chr_rom[bank_offset + addr as usize]
+                let banks = self.chr_rom.len() / 0x2000;
+                if banks == 0 {
+                    return 0;
+                }
+
+                let bank_offset = (self.selected_bank % banks) * 0x2000;
+                read_wrapped(&self.chr_rom, bank_offset + addr as usize)
             }
             0x4020..=0x5FFF => 0,
+            0x6000..=0x7FFF => read_wrapped(&self.prg_ram, addr as usize - 0x6000),
             0x8000..=0xFFFF => {
+                // a single 16kb bank is mirrored at $C000.
                 let addr = addr as usize - 0x8000;
-                self.prg_rom[addr % self.prg_rom_size]
+                self.prg_rom[addr % self.prg_rom.len()]
+            }
+            _ => {
+                log::error!("CNROM read of unmapped address ${:04X}", addr);
+                0
             }
-            _ => unimplemented!("cnrom read {:X}", addr),
         }
     }
 }
+
+#[test]
+fn test_prg_and_chr_banks() {
+    use crate::cartridge::mapper::Mapper;
+
+    let header = Header {
+        prg_rom_size: 2,
+        chr_rom_size: 2,
+        ..Default::default()
+    };
+    let mut data = vec![0; 2 * 0x4000];
+    data[0x0000] = 0x11;
+    data[0x4000] = 0x22;
+    data.extend(vec![0xA0; 0x2000]);
+    data.extend(vec![0xB0; 0x2000]);
+    let mut m = super::mapper_003::Mapper::new(header, data);
+
+    assert_eq!(m.readb(0x8000), 0x11);
+    assert_eq!(m.readb(0xC000), 0x22);
+
+    assert_eq!(m.readb(0x0000), 0xA0);
+    m.writeb(0x8000, 0x01);
+    assert_eq!(m.readb(0x0000), 0xB0);
+    // bank numbers wrap around the CHR ROM size.
+    m.writeb(0x8000, 0x02);
+    assert_eq!(m.readb(0x1FFF), 0xA0);
+}
@@ -0,0 +1,266 @@
+use super::{read_wrapped, write_wrapped, Header, Mirroring};
+
+// MMC3 mapper implementation. See https://wiki.nesdev.com/w/index.php/MMC3 for details on the
+// banking modes and the scanline counter.
+pub struct Mapper {
+    header: Header,
+    prg_rom: Vec<u8>,
+    // holds the CHR ROM, or 8kb of CHR RAM if the cartridge has none.
+    chr: Vec<u8>,
+    chr_ram: bool,
+    prg_ram: Vec<u8>,
+    mirroring: Mirroring,
+
+    // bank_select picks which of the registers the next bank data write goes to, and holds the
+    // PRG and CHR banking modes in bits 6 and 7.
+    bank_select: u8,
+    registers: [usize; 8],
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mapper {
+    pub fn new(header: Header, data: Vec<u8>) -> Self {
+        let prg_rom_size = header.prg_rom_size * 0x4000;
+        let prg_rom = data[..prg_rom_size].to_vec();
+
+        let prg_ram_size = header.prg_ram_bytes();
+        let chr_ram = header.chr_rom_size == 0;
+        let chr = if chr_ram {
+            vec![0; header.chr_ram_bytes()]
+        } else {
+            let chr_rom_size = header.chr_rom_size * 0x2000;
+            data[prg_rom_size..prg_rom_size + chr_rom_size].to_vec()
+        };
+
+        Mapper {
+            mirroring: header.mirroring,
+            header,
+            prg_rom,
+            chr,
+            chr_ram,
+            prg_ram: vec![0; prg_ram_size],
+            bank_select: 0,
+            registers: [0; 8],
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    // returns the offset into the PRG ROM for an address in the $8000-$FFFF range.
+    fn prg_offset(&self, addr: u16) -> usize {
+        let banks = self.prg_rom.len() / 0x2000;
+        let second_last = banks - 2;
+        let bank = match ((addr - 0x8000) / 0x2000, self.bank_select & 0x40 != 0) {
+            (0, false) | (2, true) => self.registers[6],
+            (0, true) | (2, false) => second_last,
+            (1, _) => self.registers[7],
+            _ => banks - 1,
+        };
+        (bank % banks) * 0x2000 + (addr as usize % 0x2000)
+    }
+
+    // returns the offset into the CHR for an address in the $0000-$1FFF range. R0 and R1 select
+    // 2kb banks and R2 to R5 select 1kb ones, bit 7 of bank_select swaps the two halves.
+    fn chr_offset(&self, addr: u16) -> usize {
+        let addr = if self.bank_select & 0x80 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        } as usize;
+
+        let bank = match addr / 0x0400 {
+            0 => self.registers[0] & 0xFE,
+            1 => self.registers[0] | 0x01,
+            2 => self.registers[1] & 0xFE,
+            3 => self.registers[1] | 0x01,
+            n => self.registers[n - 2],
+        };
+        // CHR smaller than a bank is wrapped by read_wrapped instead.
+        let banks = (self.chr.len() / 0x0400).max(1);
+        (bank % banks) * 0x0400 + addr % 0x0400
+    }
+}
+
+impl super::Mapper for Mapper {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn chr_rom(&self) -> &[u8] {
+        if self.chr_ram {
+            &[]
+        } else {
+            &self.chr
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    // the counter is clocked once per scanline, when the PPU switches from fetching background
+    // tiles to fetching sprites.
+    fn notify_scanline(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn readb(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => read_wrapped(&self.chr, self.chr_offset(addr)),
+            0x6000..=0x7FFF => read_wrapped(&self.prg_ram, addr as usize - 0x6000),
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+            _ => 0,
+        }
+    }
+
+    fn writeb(&mut self, addr: u16, val: u8) {
+        let even = addr & 0x01 == 0;
+        match addr {
+            0x0000..=0x1FFF if self.chr_ram => {
+                let offset = self.chr_offset(addr);
+                write_wrapped(&mut self.chr, offset, val);
+            }
+            0x6000..=0x7FFF => write_wrapped(&mut self.prg_ram, addr as usize - 0x6000, val),
+            0x8000..=0x9FFF if even => self.bank_select = val,
+            0x8000..=0x9FFF => {
+                self.registers[(self.bank_select & 0x07) as usize] = val as usize;
+            }
+            // four screen cartridges have their mirroring hardwired.
+            0xA000..=0xBFFF if even && self.header.mirroring != Mirroring::FourScreen => {
+                self.mirroring = if val & 0x01 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                };
+            }
+            // PRG RAM protection is not emulated.
+            0xA000..=0xBFFF => {}
+            0xC000..=0xDFFF if even => self.irq_latch = val,
+            0xC000..=0xDFFF => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000..=0xFFFF if even => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            0xE000..=0xFFFF => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_mapper() -> Mapper {
+    let header = Header {
+        prg_rom_size: 4,
+        chr_rom_size: 1,
+        mapper: 4,
+        mirroring: Mirroring::Vertical,
+        ..Default::default()
+    };
+    // the first byte of every 8kb PRG bank and every 1kb CHR bank holds the bank number.
+    let mut data = vec![0; 4 * 0x4000 + 0x2000];
+    for bank in 0..8 {
+        data[bank * 0x2000] = bank as u8;
+        data[4 * 0x4000 + bank * 0x0400] = bank as u8;
+    }
+    Mapper::new(header, data)
+}
+
+#[test]
+fn test_bank_select_modes() {
+    use crate::cartridge::mapper::Mapper;
+
+    let mut m = test_mapper();
+    let banks = |m: &super::mapper_004::Mapper| {
+        [0x8000, 0xA000, 0xC000, 0xE000]
+            .iter()
+            .map(|addr| m.readb(*addr))
+            .collect::<Vec<_>>()
+    };
+
+    // R6 = 1, R7 = 2.
+    m.writeb(0x8000, 0x06);
+    m.writeb(0x8001, 0x01);
+    m.writeb(0x8000, 0x07);
+    m.writeb(0x8001, 0x02);
+    assert_eq!(banks(&m), [1, 2, 6, 7]);
+
+    // the PRG mode bit swaps $8000 and $C000.
+    m.writeb(0x8000, 0x40);
+    assert_eq!(banks(&m), [6, 2, 1, 7]);
+
+    // R0 = 2 (a 2kb bank), R2 = 5.
+    m.writeb(0x8000, 0x00);
+    m.writeb(0x8001, 0x02);
+    m.writeb(0x8000, 0x02);
+    m.writeb(0x8001, 0x05);
+    assert_eq!(m.readb(0x0000), 2);
+    assert_eq!(m.readb(0x0400), 3);
+    assert_eq!(m.readb(0x1000), 5);
+
+    // the CHR mode bit swaps the two pattern tables.
+    m.writeb(0x8000, 0x80);
+    assert_eq!(m.readb(0x0000), 5);
+    assert_eq!(m.readb(0x1000), 2);
+    assert_eq!(m.readb(0x1400), 3);
+}
+
+#[test]
+fn test_irq_counter() {
+    use crate::cartridge::mapper::Mapper;
+
+    let mut m = test_mapper();
+    m.writeb(0xC000, 2); // latch
+    m.writeb(0xC001, 0); // reload
+    m.writeb(0xE001, 0); // enable
+
+    // the first clock reloads the counter, then it counts down to 0.
+    m.notify_scanline();
+    assert!(!m.irq());
+    m.notify_scanline();
+    assert!(!m.irq());
+    m.notify_scanline();
+    assert!(m.irq());
+
+    // writing $E000 acknowledges the IRQ and disables further ones.
+    m.writeb(0xE000, 0);
+    assert!(!m.irq());
+    for _ in 0..3 {
+        m.notify_scanline();
+    }
+    assert!(!m.irq());
+
+    // once enabled again, the counter reloads from the latch when it reaches 0.
+    m.writeb(0xE001, 0);
+    for _ in 0..3 {
+        m.notify_scanline();
+    }
+    assert!(m.irq());
+}
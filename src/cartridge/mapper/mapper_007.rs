@@ -0,0 +1,111 @@
+use super::{read_wrapped, write_wrapped, Header, Mirroring};
+
+// AxROM mapper implementation
+pub struct Mapper {
+    prg_rom: Vec<u8>,
+    // AxROM boards have 8kb of CHR RAM rather than CHR ROM.
+    chr_ram: Vec<u8>,
+    selected_bank: usize,
+    // both logical nametables map to the one selected by bit 4 of the bank register.
+    mirroring: Mirroring,
+}
+
+impl Mapper {
+    pub fn new(header: Header, data: Vec<u8>) -> Self {
+        let prg_rom_size = header.prg_rom_size * 0x4000;
+        let prg_rom = data[..prg_rom_size].to_vec();
+
+        Mapper {
+            prg_rom,
+            chr_ram: vec![0; header.chr_ram_bytes()],
+            selected_bank: 0,
+            mirroring: Mirroring::SingleScreenLower,
+        }
+    }
+}
+
+impl super::Mapper for Mapper {
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    // AxROM has no PRG RAM.
+    fn is_mapped(&self, addr: u16) -> bool {
+        addr >= 0x8000
+    }
+
+    fn readb(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => read_wrapped(&self.chr_ram, addr as usize),
+            // the whole $8000-$FFFF window is switched as a single 32kb bank.
+            0x8000..=0xFFFF => {
+                let offset = self.selected_bank * 0x8000 + (addr as usize - 0x8000);
+                read_wrapped(&self.prg_rom, offset)
+            }
+            _ => 0,
+        }
+    }
+
+    fn writeb(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => write_wrapped(&mut self.chr_ram, addr as usize, val),
+            0x8000..=0xFFFF => {
+                self.selected_bank = (val & 0x07) as usize;
+                self.mirroring = if val & 0x10 == 0 {
+                    Mirroring::SingleScreenLower
+                } else {
+                    Mirroring::SingleScreenUpper
+                };
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn test_bank_switching() {
+    use crate::cartridge::mapper::Mapper;
+
+    let header = Header {
+        prg_rom_size: 8,
+        mapper: 7,
+        ..Default::default()
+    };
+    // the first and last bytes of every 32kb bank hold the bank number.
+    let mut data = vec![0; 8 * 0x4000];
+    for bank in 0..4 {
+        data[bank * 0x8000] = bank as u8;
+        data[bank * 0x8000 + 0x7FFF] = bank as u8;
+    }
+    let mut m = super::mapper_007::Mapper::new(header, data);
+
+    assert_eq!(m.readb(0x8000), 0);
+    assert_eq!(m.readb(0xFFFF), 0);
+
+    m.writeb(0x8000, 2);
+    assert_eq!(m.readb(0x8000), 2);
+    assert_eq!(m.readb(0xFFFF), 2);
+
+    m.writeb(0xC000, 3);
+    assert_eq!(m.readb(0x8000), 3);
+    assert_eq!(m.readb(0xFFFF), 3);
+}
+
+#[test]
+fn test_single_screen_mirroring() {
+    use crate::cartridge::mapper::Mapper;
+
+    let header = Header {
+        prg_rom_size: 2,
+        mapper: 7,
+        ..Default::default()
+    };
+    let mut m = super::mapper_007::Mapper::new(header, vec![0; 2 * 0x4000]);
+    assert_eq!(m.mirroring(), Mirroring::SingleScreenLower);
+
+    m.writeb(0x8000, 0x10);
+    assert_eq!(m.mirroring(), Mirroring::SingleScreenUpper);
+    // the bank bits don't affect the mirroring.
+    m.writeb(0x8000, 0x01);
+    assert_eq!(m.mirroring(), Mirroring::SingleScreenLower);
+}
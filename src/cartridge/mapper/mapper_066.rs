@@ -0,0 +1,99 @@
+use super::{read_wrapped, Header, Mirroring};
+
+// GxROM mapper implementation
+pub struct Mapper {
+    header: Header,
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    // the 32kb PRG bank is selected by bits 4-5 of the bank register, the 8kb CHR bank by bits
+    // 0-1.
+    prg_bank: usize,
+    chr_bank: usize,
+}
+
+impl Mapper {
+    pub fn new(header: Header, data: Vec<u8>) -> Self {
+        let prg_rom_size = header.prg_rom_size * 0x4000;
+        let chr_rom_size = header.chr_rom_size * 0x2000;
+        let prg_rom = data[..prg_rom_size].to_vec();
+        let chr_rom = data[prg_rom_size..prg_rom_size + chr_rom_size].to_vec();
+
+        Mapper {
+            header,
+            prg_rom,
+            chr_rom,
+            prg_bank: 0,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl super::Mapper for Mapper {
+    fn mirroring(&self) -> Mirroring {
+        self.header.mirroring
+    }
+
+    fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
+
+    // GxROM has no PRG RAM.
+    fn is_mapped(&self, addr: u16) -> bool {
+        addr >= 0x8000
+    }
+
+    fn readb(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => {
+                let offset = self.chr_bank * 0x2000 + addr as usize;
+                read_wrapped(&self.chr_rom, offset)
+            }
+            0x8000..=0xFFFF => {
+                let offset = self.prg_bank * 0x8000 + (addr as usize - 0x8000);
+                read_wrapped(&self.prg_rom, offset)
+            }
+            _ => 0,
+        }
+    }
+
+    fn writeb(&mut self, addr: u16, val: u8) {
+        if let 0x8000..=0xFFFF = addr {
+            self.prg_bank = ((val >> 4) & 0x03) as usize;
+            self.chr_bank = (val & 0x03) as usize;
+        }
+    }
+}
+
+#[test]
+fn test_bank_switching() {
+    use crate::cartridge::mapper::Mapper;
+
+    let header = Header {
+        prg_rom_size: 8,
+        chr_rom_size: 4,
+        mapper: 66,
+        ..Default::default()
+    };
+    // the first byte of every bank holds the bank number.
+    let mut data = vec![0; 8 * 0x4000];
+    for bank in 0..4 {
+        data[bank * 0x8000] = bank as u8;
+    }
+    for bank in 0..4 {
+        let mut chr = vec![0; 0x2000];
+        chr[0] = 0xC0 | bank as u8;
+        data.extend(chr);
+    }
+    let mut m = super::mapper_066::Mapper::new(header, data);
+
+    assert_eq!(m.readb(0x8000), 0);
+    assert_eq!(m.readb(0x0000), 0xC0);
+
+    m.writeb(0x8000, 0x21);
+    assert_eq!(m.readb(0x8000), 2);
+    assert_eq!(m.readb(0x0000), 0xC1);
+
+    m.writeb(0xFFFF, 0x13);
+    assert_eq!(m.readb(0x8000), 1);
+    assert_eq!(m.readb(0x0000), 0xC3);
+}
@@ -1,49 +1,532 @@
 mod mapper_000;
 mod mapper_001;
+mod mapper_002;
 mod mapper_003;
+mod mapper_004;
+mod mapper_007;
+mod mapper_066;
+
+use super::database::Fixes;
+#[cfg(test)]
+use super::test_rom;
+use super::CartridgeError;
+use std::fmt;
 
 pub trait Mapper {
     fn readb(&self, addr: u16) -> u8;
     fn writeb(&mut self, addr: u16, val: u8);
 
+    // mappers with an IRQ counter override this to assert the CPU's IRQ line.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // notify_scanline is called by the PPU once per visible scanline and on the pre-render line
+    // while rendering is enabled, around where A12 rises for the sprite fetches. Mappers that
+    // count scanlines to raise IRQs override it.
+    fn notify_scanline(&mut self) {}
+
+    fn mirroring(&self) -> Mirroring;
+
+    // returns whether the cartridge drives the data bus when the CPU reads the given address,
+    // unmapped addresses read as open bus. Most mappers only map $6000-$FFFF.
+    fn is_mapped(&self, addr: u16) -> bool {
+        addr >= 0x6000
+    }
+
+    // mappers with PRG RAM at $6000-$7FFF expose it so battery backed cartridges can persist it.
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    // chr_rom returns the whole CHR ROM, which is empty for cartridges with CHR RAM.
+    fn chr_rom(&self) -> &[u8] {
+        &[]
+    }
+
+    // chr_at returns the 16 bytes of one of the 512 tiles in the pattern tables, as currently
+    // banked in.
+    fn chr_at(&self, tile: usize) -> [u8; 16] {
+        let mut chr = [0; 16];
+        let base = (tile % 512) as u16 * 16;
+        for (i, byte) in chr.iter_mut().enumerate() {
+            *byte = self.readb(base + i as u16);
+        }
+        chr
+    }
+
     fn readw(&self, addr: u16) -> u16 {
         let lo = self.readb(addr) as u16;
-        let hi = self.readb(addr) as u16;
+        let hi = self.readb(addr.wrapping_add(1)) as u16;
         (hi << 8) | lo
     }
 }
 
+// read_wrapped reads a byte of a ROM or RAM buffer. Offsets past its end wrap around, like they
+// do on hardware where the upper address lines of a smaller chip are left unconnected, and an
+// empty buffer reads as 0, so bad dumps with undersized buffers can't crash the emulator.
+fn read_wrapped(buf: &[u8], offset: usize) -> u8 {
+    if buf.is_empty() {
+        return 0;
+    }
+    buf[offset % buf.len()]
+}
+
+// write_wrapped writes a byte of a RAM buffer, wrapping around like read_wrapped does.
+fn write_wrapped(buf: &mut [u8], offset: usize, val: u8) {
+    if !buf.is_empty() {
+        let len = buf.len();
+        buf[offset % len] = val;
+    }
+}
+
+// Mirroring describes how the four logical nametables are laid out in the PPU's two physical
+// ones. Four screen cartridges carry their own VRAM for the other two, and some mappers can map
+// all four of them to a single one.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Mirroring {
+    #[default]
+    Horizontal,
+    Vertical,
+    FourScreen,
+    SingleScreenLower,
+    SingleScreenUpper,
+}
+
+impl Mirroring {
+    // reads the mirroring from the flags in byte 6 of the iNES header.
+    fn from_flags(flags: u8) -> Self {
+        if flags & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct Header {
     // prg rom size in 16kb units
     prg_rom_size: usize,
     // chr rom size in 8kb units
     chr_rom_size: usize,
     mapper: u8,
+    mirroring: Mirroring,
+    // trainer is set by bit 2 of byte 6, such ROMs have 512 bytes between the header and PRG
+    // ROM that are loaded at $7000.
+    trainer: bool,
+    battery: bool,
+    nes2: bool,
+    // the fields below are only present in NES 2.0 headers, and are 0 for iNES ones.
+    #[allow(dead_code)]
+    submapper: u8,
+    // the sizes of the volatile and battery backed RAM, in bytes.
+    prg_ram_size: usize,
+    prg_nvram_size: usize,
+    chr_ram_size: usize,
+    chr_nvram_size: usize,
 }
 
 impl Header {
-    pub fn from_bytes(data: [u8; 16]) -> Self {
-        Header {
+    pub fn from_bytes(data: [u8; 16]) -> Result<Self, CartridgeError> {
+        if data[0..4] != *b"NES\x1A" {
+            return Err(CartridgeError::BadMagic);
+        }
+
+        let mut header = Header {
             prg_rom_size: data[4] as usize,
             chr_rom_size: data[5] as usize,
-            mapper: (data[7] & 0x80) | (data[6] >> 4),
+            // the low nibble of the mapper number is in byte 6, the high one in byte 7.
+            mapper: (data[7] & 0xF0) | (data[6] >> 4),
+            mirroring: Mirroring::from_flags(data[6]),
+            trainer: data[6] & 0x04 != 0,
+            battery: data[6] & 0x02 != 0,
+            ..Default::default()
+        };
+
+        // NES 2.0 headers are identified by bits 2 and 3 of byte 7 being 0b10. Bits 8 to 11 of
+        // the mapper number in byte 8 are dropped, as no such mapper is supported.
+        if data[7] & 0x0C == 0x08 {
+            header.nes2 = true;
+            header.submapper = data[8] >> 4;
+            header.prg_rom_size = nes2_rom_size(data[4], data[9] & 0x0F, 0x4000);
+            header.chr_rom_size = nes2_rom_size(data[5], data[9] >> 4, 0x2000);
+            header.prg_ram_size = nes2_ram_size(data[10] & 0x0F);
+            header.prg_nvram_size = nes2_ram_size(data[10] >> 4);
+            header.chr_ram_size = nes2_ram_size(data[11] & 0x0F);
+            header.chr_nvram_size = nes2_ram_size(data[11] >> 4);
+        }
+
+        Ok(header)
+    }
+
+    // apply replaces the values of the header with the given fixes, for ROMs whose header is
+    // known to be wrong.
+    pub fn apply(&mut self, fixes: Option<Fixes>) {
+        if let Some(fixes) = fixes {
+            self.mapper = fixes.mapper.unwrap_or(self.mapper);
+            self.mirroring = fixes.mirroring.unwrap_or(self.mirroring);
+        }
+    }
+
+    // returns the total amount of PRG RAM, defaulting to 8kb when the header doesn't specify it.
+    fn prg_ram_bytes(&self) -> usize {
+        match self.prg_ram_size + self.prg_nvram_size {
+            0 => 0x2000,
+            n => n,
+        }
+    }
+
+    // returns the amount of CHR RAM, defaulting to 8kb when the header doesn't specify it.
+    fn chr_ram_bytes(&self) -> usize {
+        match self.chr_ram_size + self.chr_nvram_size {
+            0 => 0x2000,
+            n => n,
         }
     }
 }
 
-pub fn from(data: Vec<u8>) -> Box<dyn Mapper> {
-    let (header_data, data) = data.split_at(16);
+// the header is shown by --info as a list of its fields.
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+        let format = if self.nes2 { "NES 2.0" } else { "iNES" };
+        writeln!(f, "Format: {}", format)?;
+        writeln!(f, "Mapper: {} ({})", self.mapper, name(self.mapper))?;
+        writeln!(f, "PRG ROM: {}kb", self.prg_rom_size * 16)?;
+        match self.chr_rom_size {
+            0 => writeln!(
+                f,
+                "CHR ROM: none, {}kb of CHR RAM",
+                self.chr_ram_bytes() / 1024
+            )?,
+            n => writeln!(f, "CHR ROM: {}kb", n * 8)?,
+        }
+        writeln!(f, "Mirroring: {:?}", self.mirroring)?;
+        writeln!(f, "Battery: {}", yes_no(self.battery))?;
+        writeln!(f, "Trainer: {}", yes_no(self.trainer))
+    }
+}
+
+// name returns the common name of a mapper, or whether it is unsupported.
+fn name(mapper: u8) -> &'static str {
+    match mapper {
+        0x00 => "NROM",
+        0x01 => "MMC1",
+        0x02 => "UxROM",
+        0x03 => "CNROM",
+        0x04 => "MMC3",
+        0x07 => "AxROM",
+        0x42 => "GxROM",
+        _ => "unsupported",
+    }
+}
+
+// header parses the header at the start of an iNES ROM.
+pub fn header(data: &[u8]) -> Result<Header, CartridgeError> {
+    if data.len() <= 16 {
+        return Err(CartridgeError::NoProgramData);
+    }
+
     let mut header: [u8; 16] = [0; 16];
-    header.copy_from_slice(&header_data[0..=15]);
-    let header = Header::from_bytes(header);
+    header.copy_from_slice(&data[0..16]);
+    Header::from_bytes(header)
+}
 
-    #[cfg(feature = "debug")]
-    println!("Detected mapper {}", header.mapper);
+// decodes a NES 2.0 ROM size in units of the given size. The size is normally a 12 bit count,
+// but an msb of 0xF switches the lsb to an exponent-multiplier notation, 2^E * (MM * 2 + 1)
+// bytes, where lsb is EEEEEEMM.
+fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb != 0x0F {
+        return (msb as usize) << 8 | lsb as usize;
+    }
+
+    let multiplier = (lsb as usize & 0x03) * 2 + 1;
+    let bytes = 1usize
+        .checked_shl((lsb >> 2) as u32)
+        .unwrap_or(usize::MAX)
+        .saturating_mul(multiplier);
+    bytes / unit
+}
 
-    match header.mapper {
+// decodes a NES 2.0 RAM size, stored as a shift count of 64 bytes.
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+// from builds the mapper for an iNES ROM, replacing the values of its header with the given
+// fixes for ROMs whose header is known to be wrong.
+pub fn from(data: Vec<u8>, fixes: Option<Fixes>) -> Result<Box<dyn Mapper>, CartridgeError> {
+    let mut header = header(&data)?;
+    header.apply(fixes);
+    let data = &data[16..];
+    let (trainer, data) = if header.trainer {
+        if data.len() < 512 {
+            return Err(CartridgeError::Truncated);
+        }
+        let (trainer, data) = data.split_at(512);
+        (Some(trainer.to_vec()), data)
+    } else {
+        (None, data)
+    };
+    if header.prg_rom_size == 0 {
+        return Err(CartridgeError::NoProgramData);
+    }
+    // NES 2.0 headers can ask for sizes that don't fit in memory, which no file can hold either.
+    let size = header
+        .prg_rom_size
+        .checked_mul(0x4000)
+        .zip(header.chr_rom_size.checked_mul(0x2000))
+        .and_then(|(prg, chr)| prg.checked_add(chr));
+    match size {
+        Some(size) if data.len() >= size => {}
+        _ => return Err(CartridgeError::Truncated),
+    }
+
+    log::info!("Detected mapper {}", header.mapper);
+
+    let mut mapper: Box<dyn Mapper> = match header.mapper {
         0x00 => Box::new(mapper_000::Mapper::new(header, data.to_vec())),
         0x01 => Box::new(mapper_001::Mapper::new(header, data.to_vec())),
+        0x02 => Box::new(mapper_002::Mapper::new(header, data.to_vec())),
         0x03 => Box::new(mapper_003::Mapper::new(header, data.to_vec())),
-        n => panic!("unimeplemented mapper {}", n),
+        0x04 => Box::new(mapper_004::Mapper::new(header, data.to_vec())),
+        0x07 => Box::new(mapper_007::Mapper::new(header, data.to_vec())),
+        0x42 => Box::new(mapper_066::Mapper::new(header, data.to_vec())),
+        n => return Err(CartridgeError::UnknownMapper(n)),
+    };
+
+    // the trainer is copied to $7000-$71FF, for mappers that have PRG RAM there.
+    if let (Some(trainer), Some(prg_ram)) = (trainer, mapper.prg_ram_mut()) {
+        if prg_ram.len() >= 0x1200 {
+            prg_ram[0x1000..0x1200].copy_from_slice(&trainer);
+        }
     }
+
+    Ok(mapper)
+}
+
+#[test]
+fn test_header_mapper_number() {
+    let mut data = [0; 16];
+    data.copy_from_slice(&test_rom(66, 0, 0));
+    let header = Header::from_bytes(data).unwrap();
+    assert_eq!(header.mapper, 66);
+
+    data[0] = b'S';
+    assert_eq!(
+        Header::from_bytes(data).err(),
+        Some(CartridgeError::BadMagic)
+    );
+}
+
+#[test]
+fn test_ines_header() {
+    let mut data = [0; 16];
+    data.copy_from_slice(&test_rom(1, 2, 1)[..16]);
+    data[6] |= 0x01;
+    data[9] = 0xFF;
+    let header = Header::from_bytes(data).unwrap();
+    assert_eq!(header.prg_rom_size, 2);
+    assert_eq!(header.chr_rom_size, 1);
+    assert_eq!(header.mapper, 1);
+    assert_eq!(header.mirroring, Mirroring::Vertical);
+    assert_eq!(header.prg_ram_bytes(), 0x2000);
+}
+
+#[test]
+fn test_nes2_header() {
+    let mut data = [0; 16];
+    data.copy_from_slice(&test_rom(4, 0, 0));
+    // 1mb of PRG ROM, split across byte 4 and the low nibble of byte 9.
+    data[4] = 0x40;
+    data[7] = 0x08;
+    data[8] = 0x20;
+    data[9] = 0x00;
+    data[10] = 0x70;
+    data[11] = 0x07;
+    let header = Header::from_bytes(data).unwrap();
+    assert_eq!(header.prg_rom_size * 0x4000, 0x10_0000);
+    assert_eq!(header.chr_rom_size, 0);
+    assert_eq!(header.mapper, 4);
+    assert_eq!(header.submapper, 2);
+    assert_eq!(header.prg_ram_size, 0);
+    assert_eq!(header.prg_nvram_size, 0x2000);
+    assert_eq!(header.chr_ram_bytes(), 0x2000);
+
+    data[4] = 0x00;
+    data[9] = 0x01;
+    let header = Header::from_bytes(data).unwrap();
+    assert_eq!(header.prg_rom_size * 0x4000, 0x40_0000);
+
+    // 2^20 * 1 bytes in exponent-multiplier notation.
+    data[4] = 20 << 2;
+    data[9] = 0x0F;
+    let header = Header::from_bytes(data).unwrap();
+    assert_eq!(header.prg_rom_size * 0x4000, 0x10_0000);
+}
+
+#[test]
+fn test_invalid_roms() {
+    let mut data = test_rom(5, 1, 1);
+    assert_eq!(
+        from(data.clone(), None).err(),
+        Some(CartridgeError::UnknownMapper(5))
+    );
+
+    data[6] = 0x00;
+    data.truncate(16 + 0x4000 + 0x1000);
+    assert_eq!(from(data, None).err(), Some(CartridgeError::Truncated));
+
+    // a NES 2.0 header with the largest PRG and CHR ROM sizes the exponent-multiplier notation
+    // can express, which add up to more than the address space.
+    let mut data = test_rom(0, 1, 0);
+    data[4] = 0xFF;
+    data[5] = 0xFF;
+    data[7] = 0x08;
+    data[9] = 0xFF;
+    assert_eq!(from(data, None).err(), Some(CartridgeError::Truncated));
+}
+
+#[test]
+fn test_nrom_routing() {
+    // a 16kb NROM cartridge with CHR RAM.
+    let mut data = test_rom(0, 1, 0);
+    data[16] = 0x42;
+    data[16 + 0x3FFF] = 0x24;
+    let mut mapper = from(data, None).unwrap();
+
+    assert_eq!(mapper.readb(0x8000), 0x42);
+    assert_eq!(mapper.readb(0xBFFF), 0x24);
+    // the 16kb bank is mirrored at $C000.
+    assert_eq!(mapper.readb(0xC000), 0x42);
+    assert_eq!(mapper.readb(0xFFFF), 0x24);
+
+    mapper.writeb(0x0010, 0x99);
+    assert_eq!(mapper.readb(0x0010), 0x99);
+}
+
+#[test]
+fn test_chr_at() {
+    let mut data = test_rom(0, 1, 1);
+    for i in 0..16 {
+        data[16 + 0x4000 + i] = i as u8;
+        data[16 + 0x5000 + i] = 0xF0 | i as u8;
+    }
+    let mapper = from(data, None).unwrap();
+
+    let tile: Vec<u8> = (0..16).collect();
+    assert_eq!(mapper.chr_at(0), tile[..]);
+    // tile 256 is the first one of the second pattern table.
+    assert_eq!(mapper.chr_at(256)[15], 0xFF);
+}
+
+#[test]
+fn test_trainer() {
+    let mut data = test_rom(0, 1, 1);
+    data.splice(16..16, vec![0; 512]);
+    data[6] |= 0x04;
+    data[16] = 0x77;
+    data[16 + 511] = 0x78;
+    data[16 + 512] = 0x42;
+    data[16 + 512 + 0x4000] = 0x24;
+    let mapper = from(data, None).unwrap();
+
+    assert_eq!(mapper.readb(0x8000), 0x42);
+    assert_eq!(mapper.readb(0x0000), 0x24);
+    assert_eq!(mapper.readb(0x7000), 0x77);
+    assert_eq!(mapper.readb(0x71FF), 0x78);
+}
+
+#[test]
+fn test_header_fixes() {
+    // an NROM header with horizontal mirroring, for a game that is actually UxROM.
+    let mut data = test_rom(0, 2, 0);
+    data[16 + 0x4000] = 0x01;
+
+    let mapper = from(data.clone(), None).unwrap();
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+    let fixes = Fixes {
+        mapper: Some(2),
+        mirroring: Some(Mirroring::Vertical),
+    };
+    let mut mapper = from(data, Some(fixes)).unwrap();
+    assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    // UxROM switches the bank at $8000.
+    mapper.writeb(0x8000, 0x01);
+    assert_eq!(mapper.readb(0x8000), 0x01);
+}
+
+#[test]
+fn test_database_fixes() {
+    use super::database::lookup;
+
+    // Rainbow Islands is UxROM, but some dumps have a vertically mirrored NROM header.
+    let mut data = test_rom(0, 2, 0);
+    data[6] |= 0x01;
+    data[16 + 0x4000] = 0x01;
+    let mut mapper = from(data, lookup(0x9EA1_DC76)).unwrap();
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    mapper.writeb(0x8000, 0x01);
+    assert_eq!(mapper.readb(0x8000), 0x01);
+
+    assert_eq!(lookup(0x1234_5678), None);
+}
+
+#[test]
+fn test_undersized_buffers() {
+    // a NES 2.0 header with 16kb of PRG ROM, no CHR ROM but 100 stray bytes after the PRG ROM,
+    // and only 128 bytes of PRG and CHR RAM.
+    let mut data = test_rom(0, 1, 0);
+    data.extend(&[0; 100]);
+    data[7] = 0x08;
+    data[10] = 0x01;
+    data[11] = 0x01;
+
+    // reads the pattern tables and the cartridge space.
+    let read_all = |mapper: &dyn Mapper| {
+        for addr in (0x0000..0x2000).chain(0x4020..=0xFFFF) {
+            mapper.readb(addr);
+        }
+    };
+    for number in [0, 1, 2, 3, 4, 7, 66].iter() {
+        data[6] = (number & 0x0F) << 4;
+        data[7] = 0x08 | (number & 0xF0);
+        let mut mapper = from(data.clone(), None).unwrap();
+        read_all(&*mapper);
+
+        // select the highest banks, MMC1 takes its registers a bit at a time. NROM has no
+        // registers.
+        if *number != 0 {
+            for addr in [0x8000, 0x8001, 0xA000, 0xC000, 0xE000].iter() {
+                for _ in 0..5 {
+                    mapper.writeb(*addr, 0x7F);
+                }
+            }
+        }
+        mapper.writeb(0x7FFF, 0x42);
+        read_all(&*mapper);
+    }
+}
+
+#[test]
+fn test_unmapped_access() {
+    // MMC1 and CNROM don't map the PPU registers, accesses are logged rather than aborting.
+    let mapper = from(test_rom(1, 1, 0), None).unwrap();
+    assert_eq!(mapper.readb(0x2002), 0);
+
+    let mut mapper = from(test_rom(3, 1, 1), None).unwrap();
+    assert_eq!(mapper.readb(0x2002), 0);
+    mapper.writeb(0x2002, 0x42);
 }
@@ -1,10 +1,70 @@
+mod database;
 mod mapper;
 
-use mapper::Mapper;
+use crate::md5;
+use crate::png::crc32;
+pub(crate) use mapper::Mapper;
+pub use mapper::Mirroring;
+use std::fmt;
 use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+pub enum CartridgeError {
+    // the file is too short to contain any PRG ROM, usually a truncated download.
+    NoProgramData,
+    // the file doesn't start with the iNES magic number, so it's not a ROM file.
+    BadMagic,
+    // the file is shorter than the ROM sizes in its header.
+    Truncated,
+    // the header asks for a mapper that isn't implemented.
+    UnknownMapper(u8),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::NoProgramData => write!(f, "ROM file contains no program data"),
+            CartridgeError::BadMagic => write!(f, "not an iNES ROM file"),
+            CartridgeError::Truncated => write!(f, "ROM file is truncated"),
+            CartridgeError::UnknownMapper(n) => write!(f, "unsupported mapper {}", n),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+// ConsoleType is read from the lower two bits of byte 7 of the iNES header. Note that the header
+// makes no distinction between a NES and a Famicom cartridge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice,
+    Extended,
+}
+
+impl ConsoleType {
+    fn from_flags(flags: u8) -> Self {
+        match flags & 0x03 {
+            0x00 => ConsoleType::Nes,
+            0x01 => ConsoleType::VsSystem,
+            0x02 => ConsoleType::Playchoice,
+            _ => ConsoleType::Extended,
+        }
+    }
+}
 
 pub struct Cartridge {
     mapper: Box<dyn Mapper>,
+    console_type: ConsoleType,
+    // battery is set by bit 1 of byte 6 of the iNES header, battery backed cartridges keep their
+    // PRG RAM in a .sav file next to the ROM.
+    battery: bool,
+    save_path: Option<PathBuf>,
+    // the hashes of the ROM's PRG and CHR data, which identify it in ROM databases.
+    crc32: u32,
+    md5: [u8; 16],
 }
 
 impl Cartridge {
@@ -16,10 +76,88 @@ impl Cartridge {
         self.mapper.writeb(addr, val)
     }
 
+    // returns whether the cartridge is asserting the IRQ line.
+    pub fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    pub fn notify_scanline(&mut self) {
+        self.mapper.notify_scanline()
+    }
+
+    pub fn chr_at(&self, tile: usize) -> [u8; 16] {
+        self.mapper.chr_at(tile)
+    }
+
+    pub fn chr_rom(&self) -> &[u8] {
+        self.mapper.chr_rom()
+    }
+
+    pub fn is_mapped(&self, addr: u16) -> bool {
+        self.mapper.is_mapped(addr)
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+
+    pub fn console_type(&self) -> ConsoleType {
+        self.console_type
+    }
+
+    // prg_ram returns the cartridge's PRG RAM, which is empty if it has none.
+    pub fn prg_ram(&self) -> &[u8] {
+        self.mapper.prg_ram().unwrap_or(&[])
+    }
+
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        self.mapper.prg_ram_mut().unwrap_or(&mut [])
+    }
+
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    pub fn md5(&self) -> [u8; 16] {
+        self.md5
+    }
+
+    // from_mapper wraps a mapper in a cartridge, so tests can stub one out.
     #[cfg(test)]
-    pub(crate) fn from_data(data: Vec<u8>) -> Cartridge {
-        let mapper = mapper::from(data);
-        Cartridge { mapper }
+    pub fn from_mapper(mapper: Box<dyn Mapper>) -> Self {
+        Cartridge {
+            mapper,
+            console_type: ConsoleType::Nes,
+            battery: false,
+            save_path: None,
+            crc32: 0,
+            md5: [0; 16],
+        }
+    }
+
+    // from_data loads an iNES ROM, fixing its header if the game database knows it to be wrong.
+    pub fn from_data(data: Vec<u8>) -> Result<Self, CartridgeError> {
+        let console_type = ConsoleType::from_flags(data.get(7).cloned().unwrap_or(0));
+        let battery = data.get(6).cloned().unwrap_or(0) & 0x02 != 0;
+        let rom = rom_data(&data);
+        let (crc32, md5) = (crc32(rom), md5::md5(rom));
+        let mapper = mapper::from(data, database::lookup(crc32))?;
+        Ok(Cartridge {
+            mapper,
+            console_type,
+            battery,
+            save_path: None,
+            crc32,
+            md5,
+        })
+    }
+
+    // info describes the header of an iNES ROM, as fixed by the game database, and its CRC-32.
+    pub fn info(data: &[u8]) -> Result<String, CartridgeError> {
+        let crc32 = crc32(rom_data(data));
+        let mut header = mapper::header(data)?;
+        header.apply(database::lookup(crc32));
+        Ok(format!("{}CRC32: {:08X}", header, crc32))
     }
 
     pub fn from_path(path: impl AsRef<str>) -> Result<Self, Box<dyn std::error::Error>> {
@@ -27,7 +165,210 @@ impl Cartridge {
         let mut data = Vec::new();
         file.read_to_end(&mut data)?;
 
-        let mapper = mapper::from(data);
-        Ok(Cartridge { mapper })
+        let mut cartridge = Cartridge::from_data(data)?;
+        if cartridge.battery && cartridge.mapper.prg_ram().is_some() {
+            let save_path = Path::new(path.as_ref()).with_extension("sav");
+            if let (Ok(save), Some(ram)) =
+                (std::fs::read(&save_path), cartridge.mapper.prg_ram_mut())
+            {
+                let len = save.len().min(ram.len());
+                ram[..len].copy_from_slice(&save[..len]);
+            }
+            cartridge.save_path = Some(save_path);
+        }
+
+        Ok(cartridge)
+    }
+
+    // save writes the PRG RAM of battery backed cartridges to their .sav file.
+    pub fn save(&self) -> std::io::Result<()> {
+        match (&self.save_path, self.mapper.prg_ram()) {
+            (Some(path), Some(ram)) => std::fs::write(path, ram),
+            _ => Ok(()),
+        }
+    }
+}
+
+// rom_data returns the PRG and CHR data of an iNES ROM, leaving out its header and trainer.
+fn rom_data(data: &[u8]) -> &[u8] {
+    let trainer = data.get(6).cloned().unwrap_or(0) & 0x04 != 0;
+    let start = if trainer { 16 + 512 } else { 16 };
+    data.get(start..).unwrap_or(&[])
+}
+
+// test_rom builds an iNES image for the given mapper with the given number of 16kb PRG and 8kb CHR
+// banks, all of them zeroed.
+pub fn test_rom(mapper: u8, prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+    let len = 16 + prg_banks as usize * 0x4000 + chr_banks as usize * 0x2000;
+    let mut data = vec![0; len];
+    data[..4].copy_from_slice(b"NES\x1A");
+    data[4] = prg_banks;
+    data[5] = chr_banks;
+    data[6] = mapper << 4;
+    data[7] = mapper & 0xF0;
+    data
+}
+
+// test_program builds an NROM image with a single 16kb PRG bank holding the given program at
+// $8000, which is where the reset vector points to.
+pub fn test_program(program: &[u8]) -> Vec<u8> {
+    let mut data = test_rom(0, 1, 1);
+    data[16..16 + program.len()].copy_from_slice(program);
+    data[16 + 0x3FFC] = 0x00;
+    data[16 + 0x3FFD] = 0x80;
+    data
+}
+
+impl Drop for Cartridge {
+    fn drop(&mut self) {
+        if let Err(err) = self.save() {
+            log::error!("failed to save PRG RAM: {}", err);
+        }
+    }
+}
+
+#[test]
+fn test_header_only_rom() {
+    let mut data = test_rom(0, 1, 1);
+    data.truncate(16);
+    assert_eq!(
+        Cartridge::from_data(data).err(),
+        Some(CartridgeError::NoProgramData)
+    );
+
+    assert_eq!(
+        Cartridge::from_data(vec![]).err(),
+        Some(CartridgeError::NoProgramData)
+    );
+}
+
+#[test]
+fn test_rom_hashes() {
+    let mut data = test_rom(0, 1, 0);
+    data[16] = 0x42;
+    let cartridge = Cartridge::from_data(data.clone()).unwrap();
+    assert_eq!(cartridge.crc32(), crc32(&data[16..]));
+    assert_eq!(cartridge.md5(), md5::md5(&data[16..]));
+
+    // the header is left out of the hashes, so the same ROM with another header hashes the same.
+    data[6] |= 0x01;
+    data[7] = 0x01;
+    let other = Cartridge::from_data(data.clone()).unwrap();
+    assert_eq!(other.crc32(), cartridge.crc32());
+    assert_eq!(other.md5(), cartridge.md5());
+
+    // neither is the trainer.
+    data[6] |= 0x04;
+    data.splice(16..16, vec![0xFF; 512]);
+    let trained = Cartridge::from_data(data).unwrap();
+    assert_eq!(trained.crc32(), cartridge.crc32());
+    assert_eq!(trained.md5(), cartridge.md5());
+}
+
+#[test]
+fn test_info() {
+    // a NES 2.0 MMC3 ROM with 32kb of PRG ROM, no CHR ROM, 32kb of CHR RAM, a battery and a
+    // trainer.
+    let mut data = test_rom(4, 2, 0);
+    data.splice(16..16, vec![0; 512]);
+    data[6] |= 0x07;
+    data[7] = 0x08;
+    data[11] = 0x09;
+    let info = Cartridge::info(&data).unwrap();
+    let lines: Vec<&str> = info.lines().collect();
+    assert_eq!(
+        lines[..7],
+        [
+            "Format: NES 2.0",
+            "Mapper: 4 (MMC3)",
+            "PRG ROM: 32kb",
+            "CHR ROM: none, 32kb of CHR RAM",
+            "Mirroring: Vertical",
+            "Battery: yes",
+            "Trainer: yes",
+        ]
+    );
+    assert_eq!(lines[7], format!("CRC32: {:08X}", crc32(&data[16 + 512..])));
+
+    // an iNES header for a mapper that isn't supported.
+    data[6] = 0xF0;
+    data[7] = 0x00;
+    data[5] = 1;
+    let info = Cartridge::info(&data).unwrap();
+    assert!(info.starts_with("Format: iNES\nMapper: 15 (unsupported)\n"));
+    assert!(info.contains("CHR ROM: 8kb\nMirroring: Horizontal\nBattery: no\nTrainer: no\n"));
+
+    assert_eq!(
+        Cartridge::info(b"NESM").err(),
+        Some(CartridgeError::NoProgramData)
+    );
+}
+
+#[test]
+fn test_battery_backed_prg_ram() {
+    // an MMC1 cartridge with a battery.
+    let mut data = test_rom(1, 1, 1);
+    data[6] |= 0x02;
+
+    let dir = std::env::temp_dir().join(format!("shrimp-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let rom = dir.join("battery.nes");
+    std::fs::write(&rom, data).unwrap();
+    let rom = rom.to_str().unwrap();
+
+    let mut cartridge = Cartridge::from_path(rom).unwrap();
+    assert_eq!(cartridge.read(0x6000), 0x00);
+    cartridge.write(0x6000, 0x42);
+    drop(cartridge);
+
+    let cartridge = Cartridge::from_path(rom).unwrap();
+    assert_eq!(cartridge.read(0x6000), 0x42);
+    drop(cartridge);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_notify_scanline() {
+    use crate::ppu::{Palette, PPU};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    // a mapper counting the scanlines it is notified of.
+    struct Counter(Rc<Cell<usize>>);
+    impl Mapper for Counter {
+        fn readb(&self, _: u16) -> u8 {
+            0
+        }
+        fn writeb(&mut self, _: u16, _: u8) {}
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Horizontal
+        }
+        fn notify_scanline(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
     }
+
+    let count = Rc::new(Cell::new(0));
+    let cartridge = Rc::new(RefCell::new(Cartridge::from_mapper(Box::new(Counter(
+        count.clone(),
+    )))));
+    let mut ppu = PPU::new(cartridge, Palette::default());
+    let mut cycles = 0;
+
+    // nothing is notified while rendering is disabled.
+    let mut run_frame = |ppu: &mut PPU| loop {
+        cycles += 100;
+        ppu.step(cycles);
+        if ppu.frame_complete {
+            break;
+        }
+    };
+    run_frame(&mut ppu);
+    assert_eq!(count.get(), 0);
+
+    ppu.write(1, 0x18);
+    run_frame(&mut ppu);
+    // the 240 visible scanlines and the pre-render one.
+    assert_eq!(count.get(), 241);
 }
@@ -0,0 +1,274 @@
+#[cfg(test)]
+use crate::cartridge::test_program;
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::cpu::CPU;
+use crate::joypad::{Button, Player};
+use crate::patch::{Memory, RamPatch};
+use crate::png;
+use crate::ppu::{Palette, PixelFormat, PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Console is the emulator core, the CPU, PPU and cartridge wired together without any of the SDL
+// frontend, so it can be driven by any frontend a frame at a time.
+pub struct Console {
+    pub cpu: CPU,
+    pub ppu: Rc<RefCell<PPU>>,
+    pub cartridge: Rc<RefCell<Cartridge>>,
+    // the last frame, as rows of R, G, B bytes.
+    frame: Vec<u8>,
+}
+
+impl Console {
+    pub fn new(cartridge: Cartridge, palette: Palette) -> Self {
+        let cartridge = Rc::new(RefCell::new(cartridge));
+        let ppu = Rc::new(RefCell::new(PPU::new(cartridge.clone(), palette)));
+        let cpu = CPU::new(cartridge.clone(), ppu.clone());
+        Console {
+            cpu,
+            ppu,
+            cartridge,
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+        }
+    }
+
+    // load_rom builds a console running the iNES ROM in data, with the default palette. The SDL
+    // frontend loads ROMs from a path instead, to find their save files.
+    pub fn load_rom(data: &[u8]) -> Result<Self, CartridgeError> {
+        let cartridge = Cartridge::from_data(data.to_vec())?;
+        Ok(Console::new(cartridge, Palette::default()))
+    }
+
+    // reset behaves like the console's reset button, the CPU jumps to the reset vector and the PPU
+    // goes back to its power-on state, while RAM and the cartridge are left intact.
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.ppu.borrow_mut().reset();
+    }
+
+    // step runs a single instruction and keeps the PPU in sync with it, returning whether that
+    // completed a frame. The PPU is only borrowed after the instruction has run, so it's never
+    // borrowed while the CPU accesses it through the bus.
+    pub fn step(&mut self) -> bool {
+        self.cpu.tick();
+        let mut ppu = self.ppu.borrow_mut();
+        if ppu.step(self.cpu.cycles) {
+            self.cpu.nmi();
+        }
+        if ppu.frame_complete {
+            self.cpu.joypad_1.next_frame();
+            self.cpu.joypad_2.next_frame();
+            self.cpu.joypad_3.next_frame();
+            self.cpu.joypad_4.next_frame();
+        }
+        ppu.frame_complete
+    }
+
+    // step_frame runs until the next frame is complete and returns it as rows of R, G, B bytes.
+    // A watchpoint halting the CPU stops it early, returning the last complete frame.
+    pub fn step_frame(&mut self) -> &[u8] {
+        while self.cpu.halted().is_none() {
+            if self.step() {
+                let row_len = SCREEN_WIDTH * 3;
+                self.ppu
+                    .borrow()
+                    .blit_into(&mut self.frame, row_len, PixelFormat::Rgb24);
+                break;
+            }
+        }
+        &self.frame
+    }
+
+    // screenshot encodes the PPU's current frame as a PNG, each pixel scaled up to a square of
+    // scale by scale pixels.
+    pub fn screenshot(&self, scale: usize) -> Vec<u8> {
+        let row_len = SCREEN_WIDTH * 3;
+        let mut frame = vec![0; row_len * SCREEN_HEIGHT];
+        self.ppu
+            .borrow()
+            .blit_into(&mut frame, row_len, PixelFormat::Rgb24);
+
+        let mut rgb = Vec::with_capacity(frame.len() * scale * scale);
+        for row in frame.chunks(row_len) {
+            let mut scaled = Vec::with_capacity(row_len * scale);
+            for pixel in row.chunks(3) {
+                for _ in 0..scale {
+                    scaled.extend(pixel);
+                }
+            }
+            for _ in 0..scale {
+                rgb.extend(&scaled);
+            }
+        }
+        png::encode(SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale, &rgb)
+    }
+
+    // memory returns a copy of the RAM and PRG RAM, which RAM patches are diffed against.
+    pub fn memory(&self) -> Memory {
+        Memory {
+            ram: self.cpu.ram().to_vec(),
+            prg_ram: self.cartridge.borrow().prg_ram().to_vec(),
+        }
+    }
+
+    pub fn apply_patch(&mut self, patch: &RamPatch) {
+        patch.apply(
+            self.cpu.ram_mut(),
+            self.cartridge.borrow_mut().prg_ram_mut(),
+        );
+    }
+
+    pub fn set_button(&mut self, player: Player, button: Button, pressed: bool) {
+        let joypad = match player {
+            Player::One => &mut self.cpu.joypad_1,
+            Player::Two => &mut self.cpu.joypad_2,
+            Player::Three => &mut self.cpu.joypad_3,
+            Player::Four => &mut self.cpu.joypad_4,
+        };
+        button.set(joypad, pressed);
+    }
+}
+
+#[test]
+fn test_step_frame() {
+    let mut console = Console::load_rom(&test_program(&[
+        0xA9, 0x3F, // LDA #$3F
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x00, // LDA #$00
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x30, // LDA #$30
+        0x8D, 0x07, 0x20, // STA $2007
+        0xA9, 0x0A, // LDA #$0A
+        0x8D, 0x01, 0x20, // STA $2001
+        0xE6, 0x10, // INC $10
+        0x4C, 0x14, 0x80, // JMP $8014
+    ]))
+    .unwrap();
+
+    let frame = console.step_frame().to_vec();
+    assert_eq!(frame.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+    let frame = console.step_frame();
+    // the backdrop is color $30, white.
+    assert_eq!(frame[..3], [252, 252, 252]);
+    assert!(console.cpu.ram()[0x10] > 0);
+
+    assert!(Console::load_rom(b"not a ROM").is_err());
+}
+
+#[test]
+fn test_set_button() {
+    let mut console = Console::load_rom(&test_program(&[])).unwrap();
+    console.set_button(Player::Two, Button::Start, true);
+    assert!(console.cpu.joypad_2.start);
+    assert!(!console.cpu.joypad_1.start);
+    console.set_button(Player::Two, Button::Start, false);
+    assert!(!console.cpu.joypad_2.start);
+}
+
+#[test]
+fn test_nmi_bus_access() {
+    let mut rom = test_program(&[
+        0xA9, 0x80, // LDA #$80
+        0x8D, 0x00, 0x20, // STA $2000
+        0x4C, 0x05, 0x80, // JMP $8005
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // padding
+        // the NMI handler reads the PPU and copies page 2 to OAM, both of which borrow the PPU
+        // that raised the NMI.
+        0xAD, 0x02, 0x20, // LDA $2002
+        0xE6, 0x10, // INC $10
+        0xA9, 0x02, // LDA #$02
+        0x8D, 0x14, 0x40, // STA $4014
+        0x40, // RTI
+    ]);
+    rom[16 + 0x3FFA] = 0x10;
+    rom[16 + 0x3FFB] = 0x80;
+    let mut console = Console::load_rom(&rom).unwrap();
+    console.cpu.ram_mut()[0x200] = 0x55;
+
+    console.step_frame();
+    console.step_frame();
+    assert!(console.cpu.ram()[0x10] > 0);
+    assert_eq!(console.ppu.borrow().oam()[0], 0x55);
+}
+
+#[test]
+fn test_screenshot() {
+    let console = Console::load_rom(&test_program(&[])).unwrap();
+    // the PPU stores pixels as B, G, R.
+    console.ppu.borrow_mut().screen[..6].copy_from_slice(&[0x30, 0x20, 0x10, 0x60, 0x50, 0x40]);
+
+    let (width, height, rgb) = png::test_decode(&console.screenshot(2));
+    assert_eq!((width, height), (SCREEN_WIDTH * 2, SCREEN_HEIGHT * 2));
+    let pixel = |x: usize, y: usize| &rgb[(y * width + x) * 3..(y * width + x) * 3 + 3];
+    assert_eq!(pixel(0, 0), [0x10, 0x20, 0x30]);
+    assert_eq!(pixel(1, 1), [0x10, 0x20, 0x30]);
+    assert_eq!(pixel(2, 0), [0x40, 0x50, 0x60]);
+    assert_eq!(pixel(3, 1), [0x40, 0x50, 0x60]);
+    assert_eq!(pixel(4, 0), [0, 0, 0]);
+}
+
+#[test]
+fn test_nmi_hijacks_brk() {
+    let mut rom = test_program(&[
+        0xAD, 0x02, 0x20, // LDA $2002
+        0xA9, 0x80, // LDA #$80
+        0x8D, 0x00, 0x20, // STA $2000
+        0x00, 0x00, // BRK
+        0x4C, 0x08, 0x80, // JMP $8008
+        0x00, 0x00, 0x00, // padding
+        // the NMI handler counts the NMIs that interrupted a BRK, which find the status BRK pushed,
+        // B flag included, on top of the stack.
+        0x68, // PLA
+        0x48, // PHA
+        0x29, 0x10, // AND #$10
+        0xF0, 0x02, // BEQ +2
+        0xE6, 0x10, // INC $10
+        0x40, // RTI
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // padding
+        // the IRQ handler returns to the next BRK.
+        0x40, // RTI
+    ]);
+    rom[16 + 0x3FFA] = 0x10;
+    rom[16 + 0x3FFB] = 0x80;
+    rom[16 + 0x3FFE] = 0x20;
+    rom[16 + 0x3FFF] = 0x80;
+    let mut console = Console::load_rom(&rom).unwrap();
+
+    // the loop takes 16 cycles and a frame isn't a multiple of them, so within a few frames an
+    // NMI fires during the first cycles of a BRK.
+    for _ in 0..10 {
+        console.step_frame();
+    }
+    assert!(console.cpu.ram()[0x10] > 0);
+}
+
+#[test]
+fn test_single_ppuaddr_write() {
+    // like the cpu_dummy_writes_ppumem test ROM, a single write to $2006 during vblank must not
+    // change the address $2007 uses.
+    let rom = test_program(&[
+        0x2C, 0x02, 0x20, // BIT $2002
+        0x10, 0xFB, // BPL $8000
+        0xA9, 0x21, // LDA #$21
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x00, // LDA #$00
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x42, // LDA #$42
+        0x8D, 0x07, 0x20, // STA $2007
+        0xA9, 0x21, // LDA #$21
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x00, // LDA #$00
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x05, // LDA #$05
+        0x8D, 0x06, 0x20, // STA $2006
+        0xAD, 0x07, 0x20, // LDA $2007
+        0xAD, 0x07, 0x20, // LDA $2007
+        0x85, 0x10, // STA $10
+        0x4C, 0x2B, 0x80, // JMP $802B
+    ]);
+    let mut console = Console::load_rom(&rom).unwrap();
+    for _ in 0..2 {
+        console.step_frame();
+    }
+    assert_eq!(console.cpu.ram()[0x10], 0x42);
+}
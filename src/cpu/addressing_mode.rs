@@ -18,76 +18,73 @@ pub(super) enum AddressingMode {
 }
 
 impl AddressingMode {
-    // debump rolls back the program counter bump performed in the load operation of an
-    // AddressingMode. This should be used in any instruction that uses both am.load and am.store
-    // in the same block.
-    pub(super) fn debump(&self, cpu: &mut CPU) {
-        match self {
-            AddressingMode::Implied => {}
-            AddressingMode::Accumulator => {}
-            AddressingMode::Immediate => cpu.reg.pc = cpu.reg.pc.wrapping_sub(1),
-            AddressingMode::Absolute => cpu.reg.pc = cpu.reg.pc.wrapping_sub(2),
-            AddressingMode::AbsoluteX => cpu.reg.pc = cpu.reg.pc.wrapping_sub(2),
-            AddressingMode::AbsoluteY => cpu.reg.pc = cpu.reg.pc.wrapping_sub(2),
-            AddressingMode::Indirect => cpu.reg.pc = cpu.reg.pc.wrapping_sub(2),
-            AddressingMode::IndirectX => cpu.reg.pc = cpu.reg.pc.wrapping_sub(1),
-            AddressingMode::IndirectY => cpu.reg.pc = cpu.reg.pc.wrapping_sub(1),
-            AddressingMode::ZeroPage => cpu.reg.pc = cpu.reg.pc.wrapping_sub(1),
-            AddressingMode::ZeroPageX => cpu.reg.pc = cpu.reg.pc.wrapping_sub(1),
-            AddressingMode::ZeroPageY => cpu.reg.pc = cpu.reg.pc.wrapping_sub(1),
-            AddressingMode::Relative => cpu.reg.pc = cpu.reg.pc.wrapping_sub(1),
-        }
-    }
-
-    pub(super) fn load(&self, cpu: &mut CPU) -> u8 {
+    // address reads the operand of the instruction, advancing the program counter, and returns
+    // the effective address it points to. This is only valid for the modes that address memory.
+    pub(super) fn address(&self, cpu: &mut CPU) -> u16 {
+        cpu.page_crossed = false;
         match self {
             AddressingMode::Implied => panic!("invalid use of AddressingMode::Implied"),
-            AddressingMode::Accumulator => cpu.reg.a,
-            AddressingMode::Immediate => cpu.loadb_bump(),
+            AddressingMode::Accumulator => panic!("AddressingMode::Accumulator has no address"),
+            AddressingMode::Immediate => panic!("AddressingMode::Immediate has no address"),
             AddressingMode::Relative => {
                 let offset = cpu.loadb_bump() as i8;
-                let addr = (cpu.reg.pc as i16).wrapping_add(offset as i16);
-                cpu.readb(addr as u16)
-            }
-            AddressingMode::ZeroPage => {
-                let addr = cpu.loadb_bump() as u16;
-                cpu.readb(addr)
-            }
-            AddressingMode::ZeroPageX => {
-                let addr = (cpu.loadb_bump().wrapping_add(cpu.reg.x)) as u16;
-                cpu.readb(addr)
-            }
-            AddressingMode::ZeroPageY => {
-                let addr = (cpu.loadb_bump().wrapping_add(cpu.reg.y)) as u16;
-                cpu.readb(addr)
-            }
-            AddressingMode::Absolute => {
-                let addr = cpu.loadw_bump();
-                cpu.readb(addr)
+                (cpu.reg.pc as i16).wrapping_add(offset as i16) as u16
             }
+            AddressingMode::ZeroPage => cpu.loadb_bump() as u16,
+            AddressingMode::ZeroPageX => (cpu.loadb_bump().wrapping_add(cpu.reg.x)) as u16,
+            AddressingMode::ZeroPageY => (cpu.loadb_bump().wrapping_add(cpu.reg.y)) as u16,
+            AddressingMode::Absolute => cpu.loadw_bump(),
             AddressingMode::AbsoluteX => {
-                let addr = cpu.loadw_bump().wrapping_add(cpu.reg.x as u16);
-                cpu.readb(addr)
+                let base = cpu.loadw_bump();
+                cpu.indexed(base, cpu.reg.x)
             }
             AddressingMode::AbsoluteY => {
-                let addr = cpu.loadw_bump().wrapping_add(cpu.reg.y as u16);
-                cpu.readb(addr)
+                let base = cpu.loadw_bump();
+                cpu.indexed(base, cpu.reg.y)
             }
             AddressingMode::Indirect => {
                 let addr = cpu.loadw_bump();
-                let addr = cpu.readw(addr);
-                cpu.readb(addr)
+                cpu.readw(addr)
             }
             AddressingMode::IndirectX => {
                 let val = cpu.loadb_bump();
                 let x = cpu.reg.x;
-                let addr = cpu.readw_zp(val.wrapping_add(x));
-                cpu.readb(addr)
+                cpu.readw_zp(val.wrapping_add(x))
             }
             AddressingMode::IndirectY => {
                 let val = cpu.loadb_bump();
-                let y = cpu.reg.y;
-                let addr = cpu.readw_zp(val).wrapping_add(y as u16);
+                let base = cpu.readw_zp(val);
+                cpu.indexed(base, cpu.reg.y)
+            }
+        }
+    }
+
+    // write_address is address for instructions that write to memory. The indexed modes don't
+    // know whether adding the index carries into the high byte until they have read from the
+    // uncarried address, so writes always issue that dummy read first, which mappers and
+    // registers with read side effects can see.
+    pub(super) fn write_address(&self, cpu: &mut CPU) -> u16 {
+        let addr = self.address(cpu);
+        if let AddressingMode::AbsoluteX | AddressingMode::AbsoluteY | AddressingMode::IndirectY =
+            self
+        {
+            let uncarried = if cpu.page_crossed {
+                addr.wrapping_sub(0x0100)
+            } else {
+                addr
+            };
+            cpu.readb(uncarried);
+        }
+        addr
+    }
+
+    pub(super) fn load(&self, cpu: &mut CPU) -> u8 {
+        match self {
+            AddressingMode::Implied => panic!("invalid use of AddressingMode::Implied"),
+            AddressingMode::Accumulator => cpu.reg.a,
+            AddressingMode::Immediate => cpu.loadb_bump(),
+            _ => {
+                let addr = self.address(cpu);
                 cpu.readb(addr)
             }
         }
@@ -98,50 +95,8 @@ impl AddressingMode {
             AddressingMode::Implied => panic!("invalid use of AddressingMode::Implied"),
             AddressingMode::Accumulator => cpu.reg.a = val,
             AddressingMode::Immediate => panic!("cannot store in AddressingMode::Immediate mode"),
-            AddressingMode::Relative => {
-                let offset = cpu.loadb_bump() as i8;
-                let addr = (cpu.reg.pc as i16).wrapping_add(offset as i16);
-                cpu.writeb(addr as u16, val);
-            }
-            AddressingMode::ZeroPage => {
-                let addr = cpu.loadb_bump();
-                cpu.writeb(addr as u16, val);
-            }
-            AddressingMode::ZeroPageX => {
-                let addr = (cpu.loadb_bump().wrapping_add(cpu.reg.x)) as u16;
-                cpu.writeb(addr, val);
-            }
-            AddressingMode::ZeroPageY => {
-                let addr = (cpu.loadb_bump().wrapping_add(cpu.reg.y)) as u16;
-                cpu.writeb(addr, val);
-            }
-            AddressingMode::Absolute => {
-                let addr = cpu.loadw_bump();
-                cpu.writeb(addr, val);
-            }
-            AddressingMode::AbsoluteX => {
-                let addr = cpu.loadw_bump().wrapping_add(cpu.reg.x as u16);
-                cpu.writeb(addr, val);
-            }
-            AddressingMode::AbsoluteY => {
-                let addr = cpu.loadw_bump().wrapping_add(cpu.reg.y as u16);
-                cpu.writeb(addr, val);
-            }
-            AddressingMode::Indirect => {
-                let addr = cpu.loadw_bump();
-                let addr = cpu.readw(addr);
-                cpu.writeb(addr, val);
-            }
-            AddressingMode::IndirectX => {
-                let x = cpu.reg.x;
-                let addr = cpu.loadb_bump();
-                let addr = cpu.readw_zp(addr.wrapping_add(x));
-                cpu.writeb(addr, val);
-            }
-            AddressingMode::IndirectY => {
-                let y = cpu.reg.y;
-                let addr = cpu.loadb_bump();
-                let addr = cpu.readw_zp(addr).wrapping_add(y as u16);
+            _ => {
+                let addr = self.write_address(cpu);
                 cpu.writeb(addr, val);
             }
         };
@@ -0,0 +1,238 @@
+use crate::cpu::addressing_mode::AddressingMode;
+use crate::cpu::opcodes::decode;
+use crate::cpu::CPU;
+
+// length returns the size in bytes of an instruction using the given addressing mode.
+fn length(mode: &AddressingMode) -> u8 {
+    match mode {
+        AddressingMode::Implied | AddressingMode::Accumulator => 1,
+        AddressingMode::Immediate
+        | AddressingMode::Relative
+        | AddressingMode::ZeroPage
+        | AddressingMode::ZeroPageX
+        | AddressingMode::ZeroPageY
+        | AddressingMode::IndirectX
+        | AddressingMode::IndirectY => 2,
+        AddressingMode::Absolute
+        | AddressingMode::AbsoluteX
+        | AddressingMode::AbsoluteY
+        | AddressingMode::Indirect => 3,
+    }
+}
+
+// disassemble returns the instruction at addr in assembly, and its length in bytes. Memory is read
+// without side effects, so it is safe to use on a running CPU. Opcodes the CPU doesn't implement
+// are shown as data bytes.
+pub fn disassemble(cpu: &CPU, addr: u16) -> (String, u8) {
+    let opcode = cpu.peekb(addr);
+    let (mnemonic, mode) = match decode(opcode) {
+        Some(decoded) => decoded,
+        None => return (format!(".DB ${:02X}", opcode), 1),
+    };
+
+    let b = cpu.peekb(addr.wrapping_add(1));
+    let w = (cpu.peekb(addr.wrapping_add(2)) as u16) << 8 | b as u16;
+    let operand = match mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${:02X}", b),
+        AddressingMode::Relative => {
+            let target = addr.wrapping_add(2).wrapping_add(b as i8 as u16);
+            format!("${:04X}", target)
+        }
+        AddressingMode::ZeroPage => format!("${:02X}", b),
+        AddressingMode::ZeroPageX => format!("${:02X},X", b),
+        AddressingMode::ZeroPageY => format!("${:02X},Y", b),
+        AddressingMode::Absolute => format!("${:04X}", w),
+        AddressingMode::AbsoluteX => format!("${:04X},X", w),
+        AddressingMode::AbsoluteY => format!("${:04X},Y", w),
+        AddressingMode::Indirect => format!("(${:04X})", w),
+        AddressingMode::IndirectX => format!("(${:02X},X)", b),
+        AddressingMode::IndirectY => format!("(${:02X}),Y", b),
+    };
+
+    let text = if operand.is_empty() {
+        mnemonic.to_string()
+    } else {
+        format!("{} {}", mnemonic, operand)
+    };
+    (text, length(&mode))
+}
+
+// trace returns the instruction about to be executed in the format of Nintendulator's logs, as
+// used by nestest.log, e.g.
+//
+//   C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD CYC:12
+//
+// The PPU columns of those logs are left out.
+pub fn trace(cpu: &CPU) -> String {
+    let pc = cpu.reg.pc;
+    let (text, length) = disassemble(cpu, pc);
+    let bytes: Vec<String> = (0..length as u16)
+        .map(|i| format!("{:02X}", cpu.peekb(pc.wrapping_add(i))))
+        .collect();
+
+    // official instructions are preceded by a space, unofficial ones by a *.
+    let text = if text.starts_with('*') {
+        text
+    } else {
+        format!(" {}", text)
+    };
+    let text = match decode(cpu.peekb(pc)) {
+        Some((mnemonic, mode)) => format!("{}{}", text, effective_value(cpu, mnemonic, &mode)),
+        None => text,
+    };
+
+    format!(
+        "{:04X}  {:<9}{:<33}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        pc,
+        bytes.join(" "),
+        text,
+        cpu.reg.a,
+        cpu.reg.x,
+        cpu.reg.y,
+        cpu.reg.p(),
+        cpu.reg.s,
+        cpu.cycles
+    )
+}
+
+// effective_value describes the memory an instruction operates on, the way Nintendulator's logs do:
+// the effective address of indexed and indirect modes, and the value stored there.
+fn effective_value(cpu: &CPU, mnemonic: &str, mode: &AddressingMode) -> String {
+    let pc = cpu.reg.pc;
+    let b = cpu.peekb(pc.wrapping_add(1));
+    let w = (cpu.peekb(pc.wrapping_add(2)) as u16) << 8 | b as u16;
+    let peekw_zp = |addr: u8| {
+        cpu.peekb(addr as u16) as u16 | (cpu.peekb(addr.wrapping_add(1) as u16) as u16) << 8
+    };
+
+    match mode {
+        AddressingMode::ZeroPage => format!(" = {:02X}", cpu.peekb(b as u16)),
+        AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+            let index = match mode {
+                AddressingMode::ZeroPageX => cpu.reg.x,
+                _ => cpu.reg.y,
+            };
+            let addr = b.wrapping_add(index);
+            format!(" @ {:02X} = {:02X}", addr, cpu.peekb(addr as u16))
+        }
+        // jumps don't access the memory they point to.
+        AddressingMode::Absolute if mnemonic == "JMP" || mnemonic == "JSR" => String::new(),
+        AddressingMode::Absolute => format!(" = {:02X}", cpu.peekb(w)),
+        AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+            let index = match mode {
+                AddressingMode::AbsoluteX => cpu.reg.x,
+                _ => cpu.reg.y,
+            };
+            let addr = w.wrapping_add(index as u16);
+            format!(" @ {:04X} = {:02X}", addr, cpu.peekb(addr))
+        }
+        AddressingMode::Indirect => {
+            // the pointer's high byte is read without carrying into the page.
+            let hi = (w & 0xFF00) | (w.wrapping_add(1) & 0x00FF);
+            let target = cpu.peekb(w) as u16 | (cpu.peekb(hi) as u16) << 8;
+            format!(" = {:04X}", target)
+        }
+        AddressingMode::IndirectX => {
+            let ptr = b.wrapping_add(cpu.reg.x);
+            let addr = peekw_zp(ptr);
+            format!(" @ {:02X} = {:04X} = {:02X}", ptr, addr, cpu.peekb(addr))
+        }
+        AddressingMode::IndirectY => {
+            let base = peekw_zp(b);
+            let addr = base.wrapping_add(cpu.reg.y as u16);
+            format!(" = {:04X} @ {:04X} = {:02X}", base, addr, cpu.peekb(addr))
+        }
+        _ => String::new(),
+    }
+}
+
+#[test]
+fn test_disassemble() {
+    let cpu = super::test_cpu(&[
+        0xA9, 0x05, // LDA #$05
+        0x20, 0x00, 0x80, // JSR $8000
+        0xD0, 0xF9, // BNE $8000
+        0xB1, 0x10, // LDA ($10),Y
+        0x9D, 0x34, 0x12, // STA $1234,X
+        0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+        0x0A, // ASL A
+        0xE8, // INX
+        0xA7, 0x20, // LAX $20
+        0x02, // JAM
+        0x8B, // XAA, which isn't implemented
+    ]);
+
+    let expected = [
+        ("LDA #$05", 2),
+        ("JSR $8000", 3),
+        ("BNE $8000", 2),
+        ("LDA ($10),Y", 2),
+        ("STA $1234,X", 3),
+        ("JMP ($FFFC)", 3),
+        ("ASL A", 1),
+        ("INX", 1),
+        ("*LAX $20", 2),
+        ("*JAM", 1),
+        (".DB $8B", 1),
+    ];
+
+    let mut addr = 0x8000;
+    for (text, len) in expected.iter() {
+        let (disassembled, length) = disassemble(&cpu, addr);
+        assert_eq!((disassembled.as_str(), length), (*text, *len));
+        addr += length as u16;
+    }
+}
+
+#[test]
+fn test_trace_nestest() {
+    use crate::cartridge::{test_rom, Cartridge};
+    use crate::ppu::PPU;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // the first instructions nestest runs in its automated mode, which starts at $C000.
+    let mut data = test_rom(0, 1, 1);
+    let mut load = |addr: u16, bytes: &[u8]| {
+        let start = 16 + (addr - 0xC000) as usize;
+        data[start..start + bytes.len()].copy_from_slice(bytes);
+    };
+    load(0xC000, &[0x4C, 0xF5, 0xC5]);
+    load(
+        0xC5F5,
+        &[
+            0xA2, 0x00, 0x86, 0x00, 0x86, 0x10, 0x86, 0x11, 0x20, 0x2D, 0xC7,
+        ],
+    );
+    load(
+        0xC72D,
+        &[0xEA, 0x38, 0xB0, 0x04, 0xA2, 0x00, 0xEA, 0xEA, 0xEA],
+    );
+    load(0xFFFC, &[0x00, 0xC0]);
+
+    let cartridge = Rc::new(RefCell::new(Cartridge::from_data(data).unwrap()));
+    let ppu = Rc::new(RefCell::new(PPU::new(
+        cartridge.clone(),
+        crate::ppu::Palette::default(),
+    )));
+    let mut cpu = CPU::new(cartridge, ppu);
+
+    let expected = [
+        "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7",
+        "C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD CYC:10",
+        "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD CYC:12",
+        "C5F9  86 10     STX $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD CYC:15",
+        "C5FB  86 11     STX $11 = 00                    A:00 X:00 Y:00 P:26 SP:FD CYC:18",
+        "C5FD  20 2D C7  JSR $C72D                       A:00 X:00 Y:00 P:26 SP:FD CYC:21",
+        "C72D  EA        NOP                             A:00 X:00 Y:00 P:26 SP:FB CYC:27",
+        "C72E  38        SEC                             A:00 X:00 Y:00 P:26 SP:FB CYC:29",
+        "C72F  B0 04     BCS $C735                       A:00 X:00 Y:00 P:27 SP:FB CYC:31",
+        "C735  EA        NOP                             A:00 X:00 Y:00 P:27 SP:FB CYC:34",
+    ];
+    for line in expected.iter() {
+        assert_eq!(trace(&cpu), *line);
+        cpu.tick();
+    }
+}
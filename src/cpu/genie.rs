@@ -0,0 +1,101 @@
+use std::str::FromStr;
+
+// the letters of Game Genie codes, each one standing for the nibble of its index.
+const LETTERS: &str = "APZLGITYEOXUKSVN";
+
+// GenieCode replaces the value read from a ROM address, like the Game Genie cartridge did by
+// sitting between the console and the game. Codes are 6 letters long, or 8 letters long with a
+// compare value, in which case the value is only replaced when the ROM holds the compare value.
+// See https://wiki.nesdev.com/w/index.php/Game_Genie.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GenieCode {
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl GenieCode {
+    // apply returns the value read from addr with the code applied to it.
+    pub fn apply(&self, addr: u16, val: u8) -> u8 {
+        if addr != self.address {
+            return val;
+        }
+        match self.compare {
+            Some(compare) if compare != val => val,
+            _ => self.value,
+        }
+    }
+}
+
+impl FromStr for GenieCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let n = s
+            .chars()
+            .map(|c| LETTERS.find(c.to_ascii_uppercase()).map(|n| n as u16))
+            .collect::<Option<Vec<u16>>>()
+            .ok_or_else(|| format!("invalid Game Genie code: {}", s))?;
+        if n.len() != 6 && n.len() != 8 {
+            return Err(format!("Game Genie codes are 6 or 8 letters long: {}", s));
+        }
+
+        let address = 0x8000
+            | (n[3] & 7) << 12
+            | (n[5] & 7) << 8
+            | (n[4] & 8) << 8
+            | (n[2] & 7) << 4
+            | (n[1] & 8) << 4
+            | (n[4] & 7)
+            | (n[3] & 8);
+        // the last letter holds the top bit of the low nibble of the value.
+        let last = n[n.len() - 1];
+        let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7) | (last & 8);
+        let compare = if n.len() == 8 {
+            Some((n[7] & 7) << 4 | (n[6] & 8) << 4 | (n[6] & 7) | (n[5] & 8))
+        } else {
+            None
+        };
+
+        Ok(GenieCode {
+            address,
+            value: value as u8,
+            compare: compare.map(|compare| compare as u8),
+        })
+    }
+}
+
+#[test]
+fn test_parse_genie_code() {
+    // infinite lives in Super Mario Bros.
+    let code: GenieCode = "SXIOPO".parse().unwrap();
+    assert_eq!(
+        code,
+        GenieCode {
+            address: 0x91D9,
+            value: 0xAD,
+            compare: None,
+        }
+    );
+    assert_eq!(
+        "gossip".parse(),
+        Ok(GenieCode {
+            address: 0xD1DD,
+            value: 0x14,
+            compare: None,
+        })
+    );
+
+    let code: GenieCode = "ZEXPYGLA".parse().unwrap();
+    assert_eq!(
+        code,
+        GenieCode {
+            address: 0x94A7,
+            value: 0x02,
+            compare: Some(0x03),
+        }
+    );
+
+    assert!("SXIOP".parse::<GenieCode>().is_err());
+    assert!("SXIOPB".parse::<GenieCode>().is_err());
+}
@@ -1,15 +1,26 @@
 mod addressing_mode;
+mod disasm;
+mod genie;
+mod opcodes;
+mod ram_init;
 mod register;
+mod watchpoint;
 
+use crate::apu::APU;
 use crate::cartridge::Cartridge;
 use crate::cpu::addressing_mode::AddressingMode;
-use crate::joypad::Joypad;
+use crate::joypad::{FourScore, Joypad};
 use crate::ppu::PPU;
+use crate::zapper::Zapper;
+pub use disasm::{disassemble, trace};
+pub use genie::GenieCode;
+pub use ram_init::RamInit;
 use register::{Flag, Registers};
-use std::cell::RefCell;
-#[cfg(feature = "debug")]
+use std::cell::{RefCell, RefMut};
 use std::io::Write;
 use std::rc::Rc;
+use watchpoint::Access;
+pub use watchpoint::Watchpoint;
 
 const NMI_VECTOR: u16 = 0xfffa;
 const RESET_VECTOR: u16 = 0xfffc;
@@ -18,273 +29,279 @@ const BRK_VECTOR: u16 = 0xfffe;
 pub struct CPU {
     reg: Registers,
     ram: [u8; 0x0800],
-    apu: [u8; 0x0018],
+    pub apu: APU,
+    // the PPU and cartridge are shared with the rest of the console. They are only borrowed for
+    // the duration of a single bus access, and the PPU never calls back into the CPU while it's
+    // borrowed, so these borrows can't conflict. See ppu() and cartridge().
     ppu: Rc<RefCell<PPU>>,
     cartridge: Rc<RefCell<Cartridge>>,
-    #[cfg(feature = "debug")]
-    logger: std::fs::File,
+    // trace receives a line per instruction in Nintendulator's format, see disasm::trace.
+    pub trace: Option<Box<dyn Write>>,
+    // test_output collects the text test ROMs write from $6004 on, when enabled with
+    // --test-output.
+    pub test_output: Option<String>,
+    // cycles counts the CPU cycles elapsed since the last reset, the PPU is paced against it.
     pub cycles: u64,
+    // instructions counts the instructions executed since the last reset.
+    pub instructions: u64,
     pub joypad_1: Joypad,
     pub joypad_2: Joypad,
+    // joypad_3 and joypad_4 are only read through the Four Score, when one is plugged in.
+    pub joypad_3: Joypad,
+    pub joypad_4: Joypad,
+    pub four_score: Option<FourScore>,
+    // zapper takes the place of the second controller when plugged in.
+    pub zapper: Option<Zapper>,
+    // famicom enables the Famicom specific bits of the controller ports.
+    pub famicom: bool,
+    nmi_pending: bool,
+    // page_crossed is set when the last indexed address computed crossed a page boundary, which
+    // costs an extra cycle in most read instructions.
+    page_crossed: bool,
+    // decimal enables BCD arithmetic in ADC and SBC when the D flag is set. The 2A03 has its
+    // decimal mode circuitry disconnected, so this is off unless emulating a stock 6502.
+    pub decimal: bool,
+
+    watchpoints: Vec<Watchpoint>,
+    // genie_codes patch the values read from ROM.
+    genie_codes: Vec<GenieCode>,
+    // halted holds the watchpoint that stopped the CPU, if any. No instructions are executed
+    // until the CPU is resumed.
+    halted: Option<Watchpoint>,
+    // access_cycle is the cycle of the next bus access, every access taking a cycle from the start
+    // of the instruction. It times PPU accesses more precisely than cycles, which is only updated
+    // after each instruction.
+    access_cycle: u64,
+    // jammed is set by the JAM opcodes, which lock the CPU up until it's reset. The rest of the
+    // console keeps running, so the cycles still go by.
+    jammed: bool,
+    // logged_opcodes marks the unimplemented opcodes that have been logged, each is only logged
+    // the first time it runs.
+    logged_opcodes: [bool; 256],
+    // open_bus is the last value on the data bus, which reads of unmapped addresses return as
+    // nothing drives the bus.
+    open_bus: u8,
+    // bus_log records every memory access so tests can assert on them.
+    #[cfg(test)]
+    bus_log: Vec<(Access, u16)>,
 }
 
 impl CPU {
     pub fn new(cartridge: Rc<RefCell<Cartridge>>, ppu: Rc<RefCell<PPU>>) -> Self {
         #[cfg(feature = "debug")]
-        let file = std::fs::File::create("log.txt").unwrap();
+        let trace: Option<Box<dyn Write>> =
+            Some(Box::new(std::fs::File::create("log.txt").unwrap()));
+        #[cfg(not(feature = "debug"))]
+        let trace = None;
         let mut cpu = CPU {
             reg: Registers::default(),
             ram: [0; 0x0800],
-            apu: [0; 0x0018],
+            apu: APU::default(),
             ppu,
             cartridge,
-            #[cfg(feature = "debug")]
-            logger: file,
-            cycles: 7,
+            trace,
+            test_output: None,
+            cycles: 0,
+            instructions: 0,
             joypad_1: Joypad::default(),
             joypad_2: Joypad::default(),
+            joypad_3: Joypad::default(),
+            joypad_4: Joypad::default(),
+            four_score: None,
+            zapper: None,
+            famicom: false,
+            nmi_pending: false,
+            page_crossed: false,
+            decimal: false,
+            watchpoints: vec![],
+            genie_codes: vec![],
+            halted: None,
+            access_cycle: 0,
+            jammed: false,
+            logged_opcodes: [false; 256],
+            open_bus: 0,
+            #[cfg(test)]
+            bus_log: vec![],
         };
         cpu.reset();
         cpu
     }
 
+    // nmi latches a non-maskable interrupt, which is serviced before the next instruction.
     pub fn nmi(&mut self) {
-        let (pc, flags) = (self.reg.pc, self.reg.p);
-        self.pushw(pc);
-        self.pushb(flags);
-        self.reg.pc = self.readw(NMI_VECTOR);
+        self.nmi_pending = true;
     }
 
-    #[allow(unused)]
+    // irq services a maskable interrupt request, which shares its vector with BRK. The status
+    // pushed to the stack has the B flag clear so the handler can tell both apart.
     pub fn irq(&mut self) {
         if self.reg.get_flag(Flag::I) {
             return;
         }
 
-        let (pc, flags) = (self.reg.pc, self.reg.p);
+        self.interrupt(BRK_VECTOR, false);
+    }
+
+    // interrupt pushes the program counter and status, then jumps through the given vector. An
+    // NMI asserted before the vector is fetched hijacks the sequence, so the NMI vector is taken
+    // instead while the pushed status is left untouched.
+    // See https://wiki.nesdev.com/w/index.php/CPU_interrupts#Interrupt_hijacking
+    fn interrupt(&mut self, vector: u16, brk: bool) {
+        let flags = self.reg.pushed_p(brk);
+        let pc = self.reg.pc;
         self.pushw(pc);
         self.pushb(flags);
-        self.reg.pc = self.readw(BRK_VECTOR);
+        self.reg.set_flag(Flag::I, true);
+
+        // the NMI line is polled during the first four cycles of the sequence, so the PPU is
+        // caught up to the cycle before the vector fetch.
+        if !self.nmi_pending {
+            let cycle = self.access_cycle.saturating_sub(1);
+            let nmi = self.ppu().poll_nmi(cycle);
+            self.nmi_pending = nmi;
+        }
+        let vector = if self.nmi_pending {
+            self.nmi_pending = false;
+            NMI_VECTOR
+        } else {
+            vector
+        };
+        self.reg.pc = self.readw(vector);
     }
 
     pub fn reset(&mut self) {
         self.reg.pc = self.readw(RESET_VECTOR);
-        self.reg.p = 0x24;
+        self.reg.set_p(0x24);
+        // the reset sequence takes 7 cycles.
+        self.cycles = 7;
+        self.instructions = 0;
+        self.nmi_pending = false;
+        self.jammed = false;
+    }
+
+    // ppu borrows the PPU for a bus access, panicking with a descriptive message if the access
+    // discipline above was broken.
+    fn ppu(&self) -> RefMut<'_, PPU> {
+        self.ppu
+            .try_borrow_mut()
+            .expect("the PPU was accessed by the CPU while borrowed")
+    }
+
+    fn cartridge(&self) -> RefMut<'_, Cartridge> {
+        self.cartridge
+            .try_borrow_mut()
+            .expect("the cartridge was accessed by the CPU while borrowed")
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.reg.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.reg.pc = pc;
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    // init_ram sets what RAM holds at power-on, it starts out zeroed.
+    pub fn init_ram(&mut self, init: RamInit) {
+        init.fill(&mut self.ram);
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    pub fn add_watchpoint(&mut self, wp: Watchpoint) {
+        self.watchpoints.push(wp);
+    }
+
+    pub fn add_genie_code(&mut self, code: GenieCode) {
+        self.genie_codes.push(code);
+    }
+
+    pub fn halted(&self) -> Option<&Watchpoint> {
+        self.halted.as_ref()
+    }
+
+    pub fn resume(&mut self) {
+        self.halted = None;
+    }
+
+    // watchpoints are one-shot, once one is hit it is removed and the CPU is halted after the
+    // current instruction completes.
+    fn check_watchpoints(&mut self, addr: u16, access: Access) {
+        if let Some(idx) = self
+            .watchpoints
+            .iter()
+            .position(|wp| wp.matches(addr, access))
+        {
+            self.halted = Some(self.watchpoints.remove(idx));
+        }
     }
 
     pub fn tick(&mut self) -> u8 {
-        #[cfg(feature = "debug")]
-        let pc = self.reg.pc;
+        if self.halted.is_some() {
+            return 0;
+        }
+        self.apu.tick(self.cycles);
+        // the DMC reads its samples through the bus, stalling the CPU for 4 cycles.
+        if let Some(addr) = self.apu.dmc_fetch_address() {
+            let val = self.readb(addr);
+            self.apu.dmc_fill(val);
+            self.cycles = self.cycles.wrapping_add(4);
+        }
+
+        if self.jammed {
+            self.cycles = self.cycles.wrapping_add(1);
+            return 1;
+        }
+
+        // interrupts take 7 cycles to be serviced, and the handler's first instruction runs on the
+        // next tick. The IRQ line is level triggered, so it is serviced for as long as it is held.
+        // The sequence starts with 2 reads that are thrown away, the pushes follow them.
+        self.access_cycle = self.cycles + 2;
+        if self.nmi_pending {
+            self.interrupt(NMI_VECTOR, false);
+            self.cycles = self.cycles.wrapping_add(7);
+            return 7;
+        } else if !self.reg.get_flag(Flag::I) && (self.cartridge().irq() || self.apu.irq()) {
+            self.irq();
+            self.cycles = self.cycles.wrapping_add(7);
+            return 7;
+        }
+
+        if self.trace.is_some() {
+            let line = disasm::trace(self);
+            // a trace that can't be written to, e.g. on a full disk, is turned off rather than
+            // stopping emulation.
+            if let Some(Err(err)) = self.trace.as_mut().map(|t| writeln!(t, "{}", line)) {
+                log::error!("tracing stopped: {}", err);
+                self.trace = None;
+            }
+        }
 
+        self.access_cycle = self.cycles;
+        self.instructions = self.instructions.wrapping_add(1);
         let opcode = self.loadb_bump();
-
-        #[cfg(feature = "debug")]
-        write!(
-            &mut self.logger,
-            "{:04X} {:02X} \t\t A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
-            pc, opcode, self.reg.a, self.reg.x, self.reg.y, self.reg.p, self.reg.s,
-        )
-        .unwrap();
-        let cycles = match opcode {
-            0x69 => self.adc(AddressingMode::Immediate),
-            0x65 => self.adc(AddressingMode::ZeroPage),
-            0x75 => self.adc(AddressingMode::ZeroPageX),
-            0x6D => self.adc(AddressingMode::Absolute),
-            0x7D => self.adc(AddressingMode::AbsoluteX),
-            0x79 => self.adc(AddressingMode::AbsoluteY),
-            0x61 => self.adc(AddressingMode::IndirectX),
-            0x71 => self.adc(AddressingMode::IndirectY),
-
-            0x29 => self.and(AddressingMode::Immediate),
-            0x25 => self.and(AddressingMode::ZeroPage),
-            0x35 => self.and(AddressingMode::ZeroPageX),
-            0x2D => self.and(AddressingMode::Absolute),
-            0x3D => self.and(AddressingMode::AbsoluteX),
-            0x39 => self.and(AddressingMode::AbsoluteY),
-            0x21 => self.and(AddressingMode::IndirectX),
-            0x31 => self.and(AddressingMode::IndirectY),
-
-            0x0A => self.asl(AddressingMode::Accumulator),
-            0x06 => self.asl(AddressingMode::ZeroPage),
-            0x16 => self.asl(AddressingMode::ZeroPageX),
-            0x0E => self.asl(AddressingMode::Absolute),
-            0x1E => self.asl(AddressingMode::AbsoluteX),
-
-            0x24 => self.bit(AddressingMode::ZeroPage),
-            0x2C => self.bit(AddressingMode::Absolute),
-
-            0x90 => self.bcc(AddressingMode::Relative),
-            0xB0 => self.bcs(AddressingMode::Relative),
-            0xF0 => self.beq(AddressingMode::Relative),
-            0x30 => self.bmi(AddressingMode::Relative),
-            0xD0 => self.bne(AddressingMode::Relative),
-            0x10 => self.bpl(AddressingMode::Relative),
-            0x00 => self.brk(AddressingMode::Implied),
-            0x50 => self.bvc(AddressingMode::Relative),
-            0x70 => self.bvs(AddressingMode::Relative),
-
-            0x18 => self.clc(AddressingMode::Implied),
-            0xD8 => self.cld(AddressingMode::Implied),
-            0x58 => self.cli(AddressingMode::Implied),
-            0xB8 => self.clv(AddressingMode::Implied),
-
-            0xC9 => self.cmp(AddressingMode::Immediate),
-            0xC5 => self.cmp(AddressingMode::ZeroPage),
-            0xD5 => self.cmp(AddressingMode::ZeroPageX),
-            0xCD => self.cmp(AddressingMode::Absolute),
-            0xDD => self.cmp(AddressingMode::AbsoluteX),
-            0xD9 => self.cmp(AddressingMode::AbsoluteY),
-            0xC1 => self.cmp(AddressingMode::IndirectX),
-            0xD1 => self.cmp(AddressingMode::IndirectY),
-
-            0xE0 => self.cpx(AddressingMode::Immediate),
-            0xE4 => self.cpx(AddressingMode::ZeroPage),
-            0xEC => self.cpx(AddressingMode::Absolute),
-            0xC0 => self.cpy(AddressingMode::Immediate),
-            0xC4 => self.cpy(AddressingMode::ZeroPage),
-            0xCC => self.cpy(AddressingMode::Absolute),
-
-            0xC6 => self.dec(AddressingMode::ZeroPage),
-            0xD6 => self.dec(AddressingMode::ZeroPageX),
-            0xCE => self.dec(AddressingMode::Absolute),
-            0xDE => self.dec(AddressingMode::AbsoluteX),
-            0xCA => self.dex(AddressingMode::Implied),
-            0x88 => self.dey(AddressingMode::Implied),
-
-            0x49 => self.eor(AddressingMode::Immediate),
-            0x45 => self.eor(AddressingMode::ZeroPage),
-            0x55 => self.eor(AddressingMode::ZeroPageX),
-            0x4D => self.eor(AddressingMode::Absolute),
-            0x5D => self.eor(AddressingMode::AbsoluteX),
-            0x59 => self.eor(AddressingMode::AbsoluteY),
-            0x41 => self.eor(AddressingMode::IndirectX),
-            0x51 => self.eor(AddressingMode::IndirectY),
-
-            0xE6 => self.inc(AddressingMode::ZeroPage),
-            0xF6 => self.inc(AddressingMode::ZeroPageX),
-            0xEE => self.inc(AddressingMode::Absolute),
-            0xFE => self.inc(AddressingMode::AbsoluteX),
-            0xE8 => self.inx(AddressingMode::Implied),
-            0xC8 => self.iny(AddressingMode::Implied),
-
-            0x4C => self.jmp(AddressingMode::Absolute),
-            0x6C => self.jmp(AddressingMode::Indirect),
-            0x20 => self.jsr(AddressingMode::Absolute),
-
-            0xA9 => self.lda(AddressingMode::Immediate),
-            0xA5 => self.lda(AddressingMode::ZeroPage),
-            0xB5 => self.lda(AddressingMode::ZeroPageX),
-            0xAD => self.lda(AddressingMode::Absolute),
-            0xBD => self.lda(AddressingMode::AbsoluteX),
-            0xB9 => self.lda(AddressingMode::AbsoluteY),
-            0xA1 => self.lda(AddressingMode::IndirectX),
-            0xB1 => self.lda(AddressingMode::IndirectY),
-
-            0xA2 => self.ldx(AddressingMode::Immediate),
-            0xA6 => self.ldx(AddressingMode::ZeroPage),
-            0xB6 => self.ldx(AddressingMode::ZeroPageY),
-            0xAE => self.ldx(AddressingMode::Absolute),
-            0xBE => self.ldx(AddressingMode::AbsoluteY),
-
-            0xA0 => self.ldy(AddressingMode::Immediate),
-            0xA4 => self.ldy(AddressingMode::ZeroPage),
-            0xB4 => self.ldy(AddressingMode::ZeroPageX),
-            0xAC => self.ldy(AddressingMode::Absolute),
-            0xBC => self.ldy(AddressingMode::AbsoluteX),
-
-            0x4A => self.lsr(AddressingMode::Accumulator),
-            0x46 => self.lsr(AddressingMode::ZeroPage),
-            0x56 => self.lsr(AddressingMode::ZeroPageX),
-            0x4E => self.lsr(AddressingMode::Absolute),
-            0x5E => self.lsr(AddressingMode::AbsoluteX),
-
-            0xEA => self.nop(AddressingMode::Implied),
-
-            0x09 => self.ora(AddressingMode::Immediate),
-            0x05 => self.ora(AddressingMode::ZeroPage),
-            0x15 => self.ora(AddressingMode::ZeroPageX),
-            0x0D => self.ora(AddressingMode::Absolute),
-            0x1D => self.ora(AddressingMode::AbsoluteX),
-            0x19 => self.ora(AddressingMode::AbsoluteY),
-            0x01 => self.ora(AddressingMode::IndirectX),
-            0x11 => self.ora(AddressingMode::IndirectY),
-
-            0x48 => self.pha(AddressingMode::Implied),
-            0x08 => self.php(AddressingMode::Implied),
-            0x68 => self.pla(AddressingMode::Implied),
-            0x28 => self.plp(AddressingMode::Implied),
-
-            0x2A => self.rol(AddressingMode::Accumulator),
-            0x26 => self.rol(AddressingMode::ZeroPage),
-            0x36 => self.rol(AddressingMode::ZeroPageX),
-            0x2E => self.rol(AddressingMode::Absolute),
-            0x3E => self.rol(AddressingMode::AbsoluteX),
-
-            0x6A => self.ror(AddressingMode::Accumulator),
-            0x66 => self.ror(AddressingMode::ZeroPage),
-            0x76 => self.ror(AddressingMode::ZeroPageX),
-            0x6E => self.ror(AddressingMode::Absolute),
-            0x7E => self.ror(AddressingMode::AbsoluteX),
-
-            0x40 => self.rti(AddressingMode::Implied),
-            0x60 => self.rts(AddressingMode::Implied),
-
-            0xE9 => self.sbc(AddressingMode::Immediate),
-            0xE5 => self.sbc(AddressingMode::ZeroPage),
-            0xF5 => self.sbc(AddressingMode::ZeroPageX),
-            0xED => self.sbc(AddressingMode::Absolute),
-            0xFD => self.sbc(AddressingMode::AbsoluteX),
-            0xF9 => self.sbc(AddressingMode::AbsoluteY),
-            0xE1 => self.sbc(AddressingMode::IndirectX),
-            0xF1 => self.sbc(AddressingMode::IndirectY),
-
-            0x38 => self.sec(AddressingMode::Implied),
-            0xF8 => self.sed(AddressingMode::Implied),
-            0x78 => self.sei(AddressingMode::Implied),
-
-            0x85 => self.sta(AddressingMode::ZeroPage),
-            0x95 => self.sta(AddressingMode::ZeroPageX),
-            0x8D => self.sta(AddressingMode::Absolute),
-            0x9D => self.sta(AddressingMode::AbsoluteX),
-            0x99 => self.sta(AddressingMode::AbsoluteY),
-            0x81 => self.sta(AddressingMode::IndirectX),
-            0x91 => self.sta(AddressingMode::IndirectY),
-
-            0x86 => self.stx(AddressingMode::ZeroPage),
-            0x96 => self.stx(AddressingMode::ZeroPageY),
-            0x8E => self.stx(AddressingMode::Absolute),
-
-            0x84 => self.sty(AddressingMode::ZeroPage),
-            0x94 => self.sty(AddressingMode::ZeroPageX),
-            0x8C => self.sty(AddressingMode::Absolute),
-
-            0xAA => self.tax(AddressingMode::Implied),
-            0xA8 => self.tay(AddressingMode::Implied),
-            0xBA => self.tsx(AddressingMode::Implied),
-            0x8A => self.txa(AddressingMode::Implied),
-            0x9A => self.txs(AddressingMode::Implied),
-            0x98 => self.tya(AddressingMode::Implied),
-
-            // unofficial opcodes
-            0x0C => self.ign(AddressingMode::Absolute),
-            0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => self.ign(AddressingMode::AbsoluteX),
-            0x04 | 0x44 | 0x64 => self.ign(AddressingMode::Indirect),
-            0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => self.ign(AddressingMode::IndirectX),
-            0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => self.nop(AddressingMode::Implied),
-            0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => self.skb(AddressingMode::Immediate),
-
-            0x4B => self.alr(AddressingMode::Immediate),
-            0x0B | 0x2B => self.anc(AddressingMode::Immediate),
-            0x6B => self.arr(AddressingMode::Immediate),
-            0xCB => self.axs(AddressingMode::Immediate),
-
-            n => panic!("opcode {:X} not implemented", n),
+        let cycles = match self.execute(opcode) {
+            Some(cycles) => cycles,
+            // the remaining opcodes are unstable on real hardware, they run as NOPs instead.
+            None => {
+                if !std::mem::replace(&mut self.logged_opcodes[opcode as usize], true) {
+                    log::error!(
+                        "opcode {:02X} at ${:04X} not implemented",
+                        opcode,
+                        self.reg.pc.wrapping_sub(1)
+                    );
+                }
+                2
+            }
         };
 
-        #[cfg(feature = "debug")]
-        writeln!(&mut self.logger, "\t CYC:{}", self.cycles).unwrap();
-
-        self.cycles += cycles as u64;
+        self.cycles = self.cycles.wrapping_add(cycles as u64);
         cycles
     }
 
@@ -303,17 +320,81 @@ impl CPU {
     }
 
     fn readb(&mut self, addr: u16) -> u8 {
+        let cycle = self.access_cycle;
+        self.access_cycle += 1;
+        self.check_watchpoints(addr, Access::Read);
+        #[cfg(test)]
+        self.bus_log.push((Access::Read, addr));
+        let val = match addr {
+            0x0000..=0x1FFF => self.ram[addr as usize % 0x0800],
+            0x2000..=0x3FFF => self.ppu().read_at(addr % 0x08, cycle),
+            // bit 5 of $4015 isn't driven by the APU.
+            0x4015 => self.apu.read_status() | self.open_bus & 0x20,
+            0x4000..=0x4014 => self.open_bus,
+            // only the low bits are driven by the controllers, the upper three are open bus and
+            // usually hold the high byte of the address.
+            0x4016 => {
+                let state = match self.four_score.as_mut() {
+                    Some(four_score) => four_score.state(0, &mut self.joypad_1, &mut self.joypad_3),
+                    None => self.joypad_1.state(),
+                };
+                let mut val = (self.open_bus & 0xE0) | state as u8;
+                // the Famicom's microphone is reported on bit 2 of $4016.
+                if self.famicom && self.joypad_2.microphone {
+                    val |= 0x04;
+                }
+                val
+            }
+            0x4017 => match self.zapper.as_ref() {
+                Some(zapper) => {
+                    let ppu = self.ppu();
+                    (self.open_bus & 0xE0) | zapper.state(&ppu.screen, ppu.scanline())
+                }
+                None => {
+                    let state = match self.four_score.as_mut() {
+                        Some(four_score) => {
+                            four_score.state(1, &mut self.joypad_2, &mut self.joypad_4)
+                        }
+                        None => self.joypad_2.state(),
+                    };
+                    (self.open_bus & 0xE0) | state as u8
+                }
+            },
+            0x4018..=0x401F => self.open_bus,
+            0x4020..=0xFFFF => {
+                let cartridge = self.cartridge();
+                let val = if cartridge.is_mapped(addr) {
+                    cartridge.read(addr)
+                } else {
+                    self.open_bus
+                };
+                self.genie_codes
+                    .iter()
+                    .fold(val, |val, code| code.apply(addr, val))
+            }
+        };
+        self.open_bus = val;
+        val
+    }
+
+    // peekb reads memory without any of the side effects of a CPU read. The PPU registers read
+    // as they would without clearing anything, the registers in $4000-$401F read as 0.
+    pub fn peekb(&self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x1FFF => self.ram[addr as usize % 0x0800],
-            0x2000..=0x3FFF => self.ppu.borrow_mut().read(addr % 0x08),
-            0x4000..=0x4015 => self.apu[addr as usize % 0x0018],
-            0x4016 => self.joypad_1.state() as u8,
-            0x4017 => self.joypad_2.state() as u8,
-            0x4018..=0x401F => 0,
+            0x2000..=0x3FFF => self.ppu.borrow().peek_register(addr % 0x08),
+            0x4000..=0x401F => 0,
             0x4020..=0xFFFF => self.cartridge.borrow().read(addr),
         }
     }
 
+    // adds an index to a base address, keeping track of whether it crossed a page boundary.
+    fn indexed(&mut self, base: u16, index: u8) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        self.page_crossed = base & 0xFF00 != addr & 0xFF00;
+        addr
+    }
+
     fn readw_zp(&mut self, addr: u8) -> u16 {
         self.readb(addr as u16) as u16 | (self.readb((addr.wrapping_add(1)) as u16) as u16) << 8
     }
@@ -325,18 +406,58 @@ impl CPU {
     }
 
     fn writeb(&mut self, addr: u16, val: u8) {
+        let cycle = self.access_cycle;
+        self.access_cycle += 1;
+        self.check_watchpoints(addr, Access::Write);
+        #[cfg(test)]
+        self.bus_log.push((Access::Write, addr));
+        self.open_bus = val;
         match addr {
             0x0000..=0x1FFF => self.ram[addr as usize % 0x0800] = val,
-            0x2000..=0x3FFF => self.ppu.borrow_mut().write(addr % 0x08, val),
+            0x2000..=0x3FFF => self.ppu().write_at(addr % 0x08, val, cycle),
             0x4014 => self.dma(val),
-            // ignore this range until sound is implemented.
-            0x4000..=0x4015 => self.apu[addr as usize % 0x0018] = val,
-            0x4016..=0x4017 => {
-                self.joypad_1.reset();
-                self.joypad_2.reset();
+            0x4000..=0x4015 => self.apu.write(addr, val),
+            0x4016 => {
+                self.joypad_1.set_strobe(val & 0x01 != 0);
+                self.joypad_2.set_strobe(val & 0x01 != 0);
+                self.joypad_3.set_strobe(val & 0x01 != 0);
+                self.joypad_4.set_strobe(val & 0x01 != 0);
+                if let Some(four_score) = self.four_score.as_mut() {
+                    four_score.set_strobe(val & 0x01 != 0);
+                }
             }
+            0x4017 => self.apu.write(addr, val),
             0x4018..=0x401F => {}
-            0x4020..=0xFFFF => self.cartridge.borrow_mut().write(addr, val),
+            0x4020..=0xFFFF => {
+                if let (0x6004..=0x7FFF, Some(output)) = (addr, self.test_output.as_mut()) {
+                    if val != 0 {
+                        output.push(val as char);
+                    }
+                }
+                self.cartridge().write(addr, val)
+            }
+        }
+    }
+
+    // read-modify-write instructions compute the effective address once and, like the real
+    // hardware, write the unmodified value back before writing the result. rmw_load returns the
+    // effective address, or None when operating on the accumulator, along with the operand.
+    fn rmw_load(&mut self, am: &AddressingMode) -> (Option<u16>, u8) {
+        match am {
+            AddressingMode::Accumulator => (None, self.reg.a),
+            _ => {
+                let addr = am.write_address(self);
+                let val = self.readb(addr);
+                self.writeb(addr, val);
+                (Some(addr), val)
+            }
+        }
+    }
+
+    fn rmw_store(&mut self, addr: Option<u16>, val: u8) {
+        match addr {
+            Some(addr) => self.writeb(addr, val),
+            None => self.reg.a = val,
         }
     }
 
@@ -345,13 +466,18 @@ impl CPU {
         self.reg.set_flag(Flag::N, res & 0x80 == 0x80);
     }
 
+    // dma copies a page of CPU memory into the PPU OAM. The CPU is stalled for 513 cycles while
+    // it happens, plus one more if the transfer starts on an odd cycle.
     fn dma(&mut self, hi: u8) {
         let page = (hi as u16) << 8;
-        for idx in page..page + 256 {
-            let val = self.readb(page | idx as u16);
-            self.writeb(0x2004, val);
-            self.cycles += 2;
+        let mut data = [0; 0x100];
+        for (idx, byte) in data.iter_mut().enumerate() {
+            *byte = self.readb(page | idx as u16);
         }
+        self.ppu().set_oam(&data);
+
+        let stall = 513 + self.cycles % 2;
+        self.cycles = self.cycles.wrapping_add(stall);
     }
 }
 
@@ -367,10 +493,10 @@ impl CPU {
         let _ = am.load(self);
 
         match am {
-            AddressingMode::Absolute => 3,
-            AddressingMode::AbsoluteX => 4,
-            AddressingMode::Indirect => 3,
-            AddressingMode::IndirectX => 4,
+            AddressingMode::ZeroPage => 3,
+            AddressingMode::ZeroPageX => 4,
+            AddressingMode::Absolute => 4,
+            AddressingMode::AbsoluteX => 4 + self.page_crossed as u8,
             _ => unreachable!(),
         }
     }
@@ -434,13 +560,157 @@ impl CPU {
     // byte, where the 6502 architecture usually prefers a structure of arrays. For example, TXA
     // AXS #$FC could step to the next OAM entry or to the next APU channel, saving one byte and
     // four cycles over four INXs. Also called SBX.
-    fn axs(&mut self, _: AddressingMode) -> u8 {
-        unimplemented!();
+    fn axs(&mut self, am: AddressingMode) -> u8 {
+        let mem = am.load(self);
+        let and = self.reg.a & self.reg.x;
+        let res = and.wrapping_sub(mem);
+        self.reg.x = res;
+        self.reg.set_flag(Flag::C, and >= mem);
+        self.set_zn(res);
+
+        match am {
+            AddressingMode::Immediate => 2,
+            _ => unreachable!(),
+        }
+    }
+
+    // Stops the CPU, which only comes back to life when it is reset. Also called KIL or HLT.
+    fn jam(&mut self, am: AddressingMode) -> u8 {
+        log::error!("CPU jammed at ${:04X}", self.reg.pc.wrapping_sub(1));
+        self.jammed = true;
+
+        match am {
+            AddressingMode::Implied => 2,
+            _ => unreachable!(),
+        }
+    }
+
+    // Shortcut for LDA value then TAX. Saves a byte and two cycles and allows use of the X
+    // register with the (d),Y addressing mode. Notice that the immediate is missing; the opcode
+    // that would have been LAX is affected by line noise on the data bus.
+    fn lax(&mut self, am: AddressingMode) -> u8 {
+        let mem = am.load(self);
+        self.reg.a = mem;
+        self.reg.x = mem;
+        self.set_zn(mem);
+
+        match am {
+            AddressingMode::ZeroPage => 3,
+            AddressingMode::ZeroPageY => 4,
+            AddressingMode::Absolute => 4,
+            AddressingMode::AbsoluteY => 4 + self.page_crossed as u8,
+            AddressingMode::IndirectX => 6,
+            AddressingMode::IndirectY => 5 + self.page_crossed as u8,
+            _ => unreachable!(),
+        }
+    }
+
+    // Stores the bitwise AND of A and X. As with STA and STX, no flags are affected.
+    fn sax(&mut self, am: AddressingMode) -> u8 {
+        let val = self.reg.a & self.reg.x;
+        am.store(self, val);
+
+        match am {
+            AddressingMode::ZeroPage => 3,
+            AddressingMode::ZeroPageY => 4,
+            AddressingMode::Absolute => 4,
+            AddressingMode::IndirectX => 6,
+            _ => unreachable!(),
+        }
+    }
+
+    // Equivalent to DEC value then CMP value, except supporting more addressing modes. LDA #$FF
+    // followed by DCP can be used to check if the decrement underflows, which is useful for
+    // multi-byte decrements.
+    fn dcp(&mut self, am: AddressingMode) -> u8 {
+        let (addr, mem) = self.rmw_load(&am);
+        let res = mem.wrapping_sub(1);
+        self.rmw_store(addr, res);
+        self.compare(self.reg.a, res);
+        rmw_cycles(&am)
+    }
+
+    // Equivalent to INC value then SBC value, except supporting more addressing modes.
+    fn isc(&mut self, am: AddressingMode) -> u8 {
+        let (addr, mem) = self.rmw_load(&am);
+        let res = mem.wrapping_add(1);
+        self.rmw_store(addr, res);
+        self.subtract_with_borrow(res);
+        rmw_cycles(&am)
+    }
+
+    // Equivalent to ASL value then ORA value, except supporting more addressing modes. LDA #0
+    // followed by SLO is an efficient way to shift a variable while also loading it in A.
+    fn slo(&mut self, am: AddressingMode) -> u8 {
+        let (addr, mem) = self.rmw_load(&am);
+        let res = mem << 1;
+        self.rmw_store(addr, res);
+        self.reg.set_flag(Flag::C, mem & 0x80 == 0x80);
+        let acc = self.reg.a | res;
+        self.reg.a = acc;
+        self.set_zn(acc);
+        rmw_cycles(&am)
+    }
+
+    // Equivalent to ROL value then AND value, except supporting more addressing modes. LDA #$FF
+    // followed by RLA is an efficient way to rotate a variable while also loading it in A.
+    fn rla(&mut self, am: AddressingMode) -> u8 {
+        let (addr, mem) = self.rmw_load(&am);
+        let c = if self.reg.get_flag(Flag::C) {
+            0x01
+        } else {
+            0x00
+        };
+        let res = (mem << 1) | c;
+        self.rmw_store(addr, res);
+        self.reg.set_flag(Flag::C, mem & 0x80 == 0x80);
+        let acc = self.reg.a & res;
+        self.reg.a = acc;
+        self.set_zn(acc);
+        rmw_cycles(&am)
+    }
+
+    // Equivalent to LSR value then EOR value, except supporting more addressing modes. LDA #0
+    // followed by SRE is an efficient way to shift a variable while also loading it in A.
+    fn sre(&mut self, am: AddressingMode) -> u8 {
+        let (addr, mem) = self.rmw_load(&am);
+        let res = mem >> 1;
+        self.rmw_store(addr, res);
+        self.reg.set_flag(Flag::C, mem & 0x01 == 0x01);
+        let acc = self.reg.a ^ res;
+        self.reg.a = acc;
+        self.set_zn(acc);
+        rmw_cycles(&am)
+    }
 
-        // match am {
-        //     AddressingMode::Immediate => 2,
-        //     _ => unreachable!(),
-        // }
+    // Equivalent to ROR value then ADC value, except supporting more addressing modes. Essentially
+    // this computes A + value / 2, where value is 9-bit and the division is rounded up.
+    fn rra(&mut self, am: AddressingMode) -> u8 {
+        let (addr, mem) = self.rmw_load(&am);
+        let c = if self.reg.get_flag(Flag::C) {
+            0x80
+        } else {
+            0x00
+        };
+        let res = (mem >> 1) | c;
+        self.rmw_store(addr, res);
+        self.reg.set_flag(Flag::C, mem & 0x01 == 0x01);
+        self.add_with_carry(res);
+        rmw_cycles(&am)
+    }
+}
+
+// the unofficial read-modify-write opcodes all share the same timings.
+fn rmw_cycles(am: &AddressingMode) -> u8 {
+    match am {
+        AddressingMode::ZeroPage => 5,
+        AddressingMode::ZeroPageX => 6,
+        AddressingMode::Absolute => 6,
+        AddressingMode::AbsoluteX => 7,
+        AddressingMode::AbsoluteY => 7,
+        AddressingMode::IndirectX => 8,
+        AddressingMode::IndirectY => 8,
+        _ => unreachable!(),
     }
 }
 
@@ -463,30 +733,17 @@ impl CPU {
     //  (indirect),Y  ADC (oper),Y  71    2     5*
     fn adc(&mut self, am: AddressingMode) -> u8 {
         let mem = am.load(self);
-        let acc = self.reg.a;
-        let mut res = mem as u16 + acc as u16;
-        if self.reg.get_flag(Flag::C) {
-            res += 1;
-        }
-
-        self.reg.set_flag(Flag::C, res > 0xFF);
-        let res = res as u8;
-        self.reg.set_flag(
-            Flag::V,
-            (acc ^ mem) & 0x80 == 0 && (acc ^ res) & 0x80 == 0x80,
-        );
-        self.set_zn(res as u8);
-        self.reg.a = res;
+        self.add_with_carry(mem);
 
         match am {
             AddressingMode::Immediate => 2,
             AddressingMode::ZeroPage => 3,
             AddressingMode::ZeroPageX => 4,
             AddressingMode::Absolute => 4,
-            AddressingMode::AbsoluteX => 4,
-            AddressingMode::AbsoluteY => 4,
+            AddressingMode::AbsoluteX => 4 + self.page_crossed as u8,
+            AddressingMode::AbsoluteY => 4 + self.page_crossed as u8,
             AddressingMode::IndirectX => 6,
-            AddressingMode::IndirectY => 5,
+            AddressingMode::IndirectY => 5 + self.page_crossed as u8,
             _ => unreachable!(),
         }
     }
@@ -517,10 +774,10 @@ impl CPU {
             AddressingMode::ZeroPage => 3,
             AddressingMode::ZeroPageX => 4,
             AddressingMode::Absolute => 4,
-            AddressingMode::AbsoluteX => 4,
-            AddressingMode::AbsoluteY => 4,
+            AddressingMode::AbsoluteX => 4 + self.page_crossed as u8,
+            AddressingMode::AbsoluteY => 4 + self.page_crossed as u8,
             AddressingMode::IndirectX => 6,
-            AddressingMode::IndirectY => 5,
+            AddressingMode::IndirectY => 5 + self.page_crossed as u8,
             _ => unreachable!(),
         }
     }
@@ -537,10 +794,9 @@ impl CPU {
     //  absolute      ASL oper      0E    3     6
     //  absolute,X    ASL oper,X    1E    3     7
     fn asl(&mut self, am: AddressingMode) -> u8 {
-        let val = am.load(self);
+        let (addr, val) = self.rmw_load(&am);
         let res = (val as u16) << 1;
-        am.debump(self);
-        am.store(self, res as u8);
+        self.rmw_store(addr, res as u8);
         self.reg.set_flag(Flag::C, res > 0xFF);
         self.set_zn(res as u8);
 
@@ -681,12 +937,10 @@ impl CPU {
     //  --------------------------------------------
     //  implied       BRK           00    1     7
     fn brk(&mut self, am: AddressingMode) -> u8 {
-        let pc = self.reg.pc;
-        self.pushw(pc + 1);
-        let flags = self.reg.p;
-        self.pushb(flags);
-        self.reg.set_flag(Flag::I, true);
-        self.reg.pc = self.readw(BRK_VECTOR);
+        // BRK fetches the signature byte following the opcode and discards it, so the return
+        // address is the opcode's plus 2, and RTI returns to it as is.
+        self.loadb_bump();
+        self.interrupt(BRK_VECTOR, true);
 
         match am {
             AddressingMode::Implied => 7,
@@ -813,10 +1067,10 @@ impl CPU {
             AddressingMode::ZeroPage => 3,
             AddressingMode::ZeroPageX => 4,
             AddressingMode::Absolute => 4,
-            AddressingMode::AbsoluteX => 4,
-            AddressingMode::AbsoluteY => 4,
+            AddressingMode::AbsoluteX => 4 + self.page_crossed as u8,
+            AddressingMode::AbsoluteY => 4 + self.page_crossed as u8,
             AddressingMode::IndirectX => 6,
-            AddressingMode::IndirectY => 7,
+            AddressingMode::IndirectY => 5 + self.page_crossed as u8,
             _ => unreachable!(),
         }
     }
@@ -874,10 +1128,9 @@ impl CPU {
     //  absolute      DEC oper      CE    3     6
     //  absolute,X    DEC oper,X    DE    3     7
     fn dec(&mut self, am: AddressingMode) -> u8 {
-        let mem = am.load(self);
+        let (addr, mem) = self.rmw_load(&am);
         let res = mem.wrapping_sub(1);
-        am.debump(self);
-        am.store(self, res);
+        self.rmw_store(addr, res);
         self.set_zn(res);
 
         match am {
@@ -953,10 +1206,10 @@ impl CPU {
             AddressingMode::ZeroPage => 3,
             AddressingMode::ZeroPageX => 4,
             AddressingMode::Absolute => 4,
-            AddressingMode::AbsoluteX => 4,
-            AddressingMode::AbsoluteY => 4,
+            AddressingMode::AbsoluteX => 4 + self.page_crossed as u8,
+            AddressingMode::AbsoluteY => 4 + self.page_crossed as u8,
             AddressingMode::IndirectX => 6,
-            AddressingMode::IndirectY => 5,
+            AddressingMode::IndirectY => 5 + self.page_crossed as u8,
             _ => unreachable!(),
         }
     }
@@ -972,10 +1225,9 @@ impl CPU {
     //  absolute      INC oper      EE    3     6
     //  absolute,X    INC oper,X    FE    3     7
     fn inc(&mut self, am: AddressingMode) -> u8 {
-        let mem = am.load(self);
+        let (addr, mem) = self.rmw_load(&am);
         let res = mem.wrapping_add(1);
-        am.debump(self);
-        am.store(self, res);
+        self.rmw_store(addr, res);
         self.set_zn(res);
 
         match am {
@@ -1063,8 +1315,9 @@ impl CPU {
     //  absolute      JSR oper      20    3     6
     fn jsr(&mut self, am: AddressingMode) -> u8 {
         let res = self.loadw_bump();
+        // the return address pushed is the last byte of the JSR, RTS adds 1 to it.
         let pc = self.reg.pc;
-        self.pushw(pc - 1);
+        self.pushw(pc.wrapping_sub(1));
         self.reg.pc = res;
 
         match am {
@@ -1097,10 +1350,10 @@ impl CPU {
             AddressingMode::ZeroPage => 3,
             AddressingMode::ZeroPageX => 4,
             AddressingMode::Absolute => 4,
-            AddressingMode::AbsoluteX => 4,
-            AddressingMode::AbsoluteY => 4,
+            AddressingMode::AbsoluteX => 4 + self.page_crossed as u8,
+            AddressingMode::AbsoluteY => 4 + self.page_crossed as u8,
             AddressingMode::IndirectX => 6,
-            AddressingMode::IndirectY => 5,
+            AddressingMode::IndirectY => 5 + self.page_crossed as u8,
             _ => unreachable!(),
         }
     }
@@ -1126,7 +1379,7 @@ impl CPU {
             AddressingMode::ZeroPage => 3,
             AddressingMode::ZeroPageY => 4,
             AddressingMode::Absolute => 4,
-            AddressingMode::AbsoluteY => 4,
+            AddressingMode::AbsoluteY => 4 + self.page_crossed as u8,
             _ => unreachable!(),
         }
     }
@@ -1152,7 +1405,7 @@ impl CPU {
             AddressingMode::ZeroPage => 3,
             AddressingMode::ZeroPageX => 4,
             AddressingMode::Absolute => 4,
-            AddressingMode::AbsoluteX => 4,
+            AddressingMode::AbsoluteX => 4 + self.page_crossed as u8,
             _ => unreachable!(),
         }
     }
@@ -1169,11 +1422,10 @@ impl CPU {
     //  absolute      LSR oper      4E    3     6
     //  absolute,X    LSR oper,X    5E    3     7
     fn lsr(&mut self, am: AddressingMode) -> u8 {
-        let val = am.load(self);
+        let (addr, val) = self.rmw_load(&am);
         let c = val & 0x01;
         let res = val >> 1;
-        am.debump(self);
-        am.store(self, res as u8);
+        self.rmw_store(addr, res as u8);
         self.reg.set_flag(Flag::C, c == 0x01);
         self.set_zn(res);
 
@@ -1227,10 +1479,10 @@ impl CPU {
             AddressingMode::ZeroPage => 3,
             AddressingMode::ZeroPageX => 4,
             AddressingMode::Absolute => 4,
-            AddressingMode::AbsoluteX => 4,
-            AddressingMode::AbsoluteY => 4,
+            AddressingMode::AbsoluteX => 4 + self.page_crossed as u8,
+            AddressingMode::AbsoluteY => 4 + self.page_crossed as u8,
             AddressingMode::IndirectX => 6,
-            AddressingMode::IndirectY => 5,
+            AddressingMode::IndirectY => 5 + self.page_crossed as u8,
             _ => unreachable!(),
         }
     }
@@ -1260,7 +1512,7 @@ impl CPU {
     //  --------------------------------------------
     //  implied       PHP           08    1     3
     fn php(&mut self, am: AddressingMode) -> u8 {
-        let sr = self.reg.p | 0b0011_0000;
+        let sr = self.reg.pushed_p(true);
         self.pushb(sr);
 
         match am {
@@ -1296,7 +1548,7 @@ impl CPU {
     //  implied       PLP           28    1     4
     fn plp(&mut self, am: AddressingMode) -> u8 {
         let val = self.popb();
-        self.set_p(val);
+        self.reg.set_p(val);
 
         match am {
             AddressingMode::Implied => 4,
@@ -1316,13 +1568,12 @@ impl CPU {
     //  absolute      ROL oper      2E    3     6
     //  absolute,X    ROL oper,X    3E    3     7
     fn rol(&mut self, am: AddressingMode) -> u8 {
-        let val = am.load(self);
+        let (addr, val) = self.rmw_load(&am);
         let msb = val & 0x80;
         let c = self.reg.get_flag(Flag::C);
         let c = if c { 0x01 } else { 0x00 };
         let res = (val << 1) | c;
-        am.debump(self);
-        am.store(self, res);
+        self.rmw_store(addr, res);
         self.reg.set_flag(Flag::C, msb == 0x80);
         self.set_zn(res);
 
@@ -1348,13 +1599,12 @@ impl CPU {
     //  absolute      ROR oper      6E    3     6
     //  absolute,X    ROR oper,X    7E    3     7
     fn ror(&mut self, am: AddressingMode) -> u8 {
-        let val = am.load(self);
+        let (addr, val) = self.rmw_load(&am);
         let new_carry = val & 0x01;
         let c = self.reg.get_flag(Flag::C);
         let c = if c { 0x80 } else { 0x00 };
         let res = (val >> 1) | c;
-        am.debump(self);
-        am.store(self, res);
+        self.rmw_store(addr, res);
         self.reg.set_flag(Flag::C, new_carry == 0x01);
         self.set_zn(res);
 
@@ -1377,7 +1627,7 @@ impl CPU {
     //  implied       RTI           40    1     6
     fn rti(&mut self, am: AddressingMode) -> u8 {
         let sr = self.popb();
-        self.set_p(sr);
+        self.reg.set_p(sr);
         let pc = self.popw();
         self.reg.pc = pc;
 
@@ -1395,7 +1645,7 @@ impl CPU {
     //  --------------------------------------------
     //  implied       RTS           60    1     6
     fn rts(&mut self, am: AddressingMode) -> u8 {
-        self.reg.pc = self.popw() + 1;
+        self.reg.pc = self.popw().wrapping_add(1);
 
         match am {
             AddressingMode::Implied => 6,
@@ -1419,28 +1669,17 @@ impl CPU {
     //  (indirect),Y  SBC (oper),Y  F1    2     5*
     fn sbc(&mut self, am: AddressingMode) -> u8 {
         let mem = am.load(self);
-        let acc = self.reg.a;
-        let c = self.reg.get_flag(Flag::C);
-        let c = if c { 0x00 } else { 0x01 };
-        let res = (acc as u16).wrapping_sub(mem as u16).wrapping_sub(c as u16);
-        self.reg.set_flag(Flag::C, res & 0x100 == 0);
-        let res = res as u8;
-        self.reg.set_flag(
-            Flag::V,
-            (acc ^ res) & 0x80 != 0 && (acc ^ mem) & 0x80 == 0x80,
-        );
-        self.set_zn(res);
-        self.reg.a = res;
+        self.subtract_with_borrow(mem);
 
         match am {
             AddressingMode::Immediate => 2,
             AddressingMode::ZeroPage => 3,
             AddressingMode::ZeroPageX => 4,
             AddressingMode::Absolute => 4,
-            AddressingMode::AbsoluteX => 4,
-            AddressingMode::AbsoluteY => 4,
+            AddressingMode::AbsoluteX => 4 + self.page_crossed as u8,
+            AddressingMode::AbsoluteY => 4 + self.page_crossed as u8,
             AddressingMode::IndirectX => 6,
-            AddressingMode::IndirectY => 5,
+            AddressingMode::IndirectY => 5 + self.page_crossed as u8,
             _ => unreachable!(),
         }
     }
@@ -1557,7 +1796,7 @@ impl CPU {
         am.store(self, reg);
 
         match am {
-            AddressingMode::ZeroPage => 2,
+            AddressingMode::ZeroPage => 3,
             AddressingMode::ZeroPageX => 4,
             AddressingMode::Absolute => 4,
             _ => unreachable!(),
@@ -1671,12 +1910,6 @@ impl CPU {
         }
     }
 
-    // https://wiki.nesdev.com/w/index.php/Status_flags#The_B_flag
-    fn set_p(&mut self, val: u8) {
-        let b = self.reg.p & 0b0011_0000;
-        self.reg.p = val & 0b1100_1111 | b;
-    }
-
     fn popb(&mut self) -> u8 {
         self.reg.s = self.reg.s.wrapping_add(1);
         let sp = self.reg.s as u16;
@@ -1710,16 +1943,115 @@ impl CPU {
             self.reg.pc = (self.reg.pc as i32 + val as i32) as u16;
         }
 
-        let mut cycles = 0;
-        if cond {
-            cycles += 1;
+        // a taken branch costs an extra cycle, and one more if it lands on a different page.
+        if !cond {
+            0
+        } else if self.reg.pc & 0xFF00 != old_pc & 0xFF00 {
+            2
+        } else {
+            1
         }
+    }
 
-        if self.reg.pc & 0xFF00 > old_pc & 0xFF00 {
-            cycles += 2;
+    // adds mem and the carry to the accumulator and sets the appropiate flags.
+    fn add_with_carry(&mut self, mem: u8) {
+        if self.decimal && self.reg.get_flag(Flag::D) {
+            self.add_with_carry_decimal(mem);
+            return;
         }
 
-        cycles
+        let acc = self.reg.a;
+        let mut res = mem as u16 + acc as u16;
+        if self.reg.get_flag(Flag::C) {
+            res += 1;
+        }
+
+        self.reg.set_flag(Flag::C, res > 0xFF);
+        let res = res as u8;
+        self.reg.set_flag(
+            Flag::V,
+            (acc ^ mem) & 0x80 == 0 && (acc ^ res) & 0x80 == 0x80,
+        );
+        self.set_zn(res as u8);
+        self.reg.a = res;
+    }
+
+    // subtracts mem and the borrow from the accumulator and sets the appropiate flags.
+    fn subtract_with_borrow(&mut self, mem: u8) {
+        let acc = self.reg.a;
+        let c = self.reg.get_flag(Flag::C);
+        let c = if c { 0x00 } else { 0x01 };
+        if self.decimal && self.reg.get_flag(Flag::D) {
+            self.subtract_with_borrow_decimal(acc, mem, c);
+            return;
+        }
+
+        let res = (acc as u16).wrapping_sub(mem as u16).wrapping_sub(c as u16);
+        self.reg.set_flag(Flag::C, res & 0x100 == 0);
+        let res = res as u8;
+        self.reg.set_flag(
+            Flag::V,
+            (acc ^ res) & 0x80 != 0 && (acc ^ mem) & 0x80 == 0x80,
+        );
+        self.set_zn(res);
+        self.reg.a = res;
+    }
+
+    // BCD addition as performed by the NMOS 6502. Z is set from the binary result, while N and V
+    // are set from the result before the high nibble is adjusted.
+    // See http://www.6502.org/tutorials/decimal_mode.html#A for more information.
+    fn add_with_carry_decimal(&mut self, mem: u8) {
+        let acc = self.reg.a;
+        let c = if self.reg.get_flag(Flag::C) { 1 } else { 0 };
+        let binary = acc.wrapping_add(mem).wrapping_add(c);
+
+        let mut lo = (acc & 0x0F) as u16 + (mem & 0x0F) as u16 + c as u16;
+        let mut hi = (acc & 0xF0) as u16 + (mem & 0xF0) as u16;
+        if lo > 0x09 {
+            lo += 0x06;
+        }
+        if lo > 0x0F {
+            hi += 0x10;
+        }
+
+        self.reg.set_flag(Flag::Z, binary == 0);
+        self.reg.set_flag(Flag::N, hi & 0x80 == 0x80);
+        self.reg.set_flag(
+            Flag::V,
+            (acc ^ mem) & 0x80 == 0 && (acc as u16 ^ hi) & 0x80 == 0x80,
+        );
+
+        if hi > 0x90 {
+            hi += 0x60;
+        }
+        self.reg.set_flag(Flag::C, hi > 0xFF);
+        self.reg.a = (hi as u8 & 0xF0) | (lo as u8 & 0x0F);
+    }
+
+    // BCD subtraction as performed by the NMOS 6502. All flags are set as they would be by a
+    // binary subtraction, only the result is adjusted.
+    fn subtract_with_borrow_decimal(&mut self, acc: u8, mem: u8, borrow: u8) {
+        let binary = (acc as u16)
+            .wrapping_sub(mem as u16)
+            .wrapping_sub(borrow as u16);
+        self.reg.set_flag(Flag::C, binary & 0x100 == 0);
+        let binary = binary as u8;
+        self.reg.set_flag(
+            Flag::V,
+            (acc ^ binary) & 0x80 != 0 && (acc ^ mem) & 0x80 == 0x80,
+        );
+        self.set_zn(binary);
+
+        let mut lo = (acc & 0x0F) as i16 - (mem & 0x0F) as i16 - borrow as i16;
+        let mut hi = (acc & 0xF0) as i16 - (mem & 0xF0) as i16;
+        if lo < 0 {
+            lo -= 0x06;
+            hi -= 0x10;
+        }
+        if hi < 0 {
+            hi -= 0x60;
+        }
+        self.reg.a = (hi as u8 & 0xF0) | (lo as u8 & 0x0F);
     }
 
     // performs x - y and set the appropiate flags.
@@ -1729,3 +2061,785 @@ impl CPU {
         self.reg.set_flag(Flag::C, x >= y);
     }
 }
+
+#[cfg(test)]
+fn test_cpu(program: &[u8]) -> CPU {
+    // builds an NROM image with a single 16kb PRG bank holding the program at $8000, and the
+    // reset vector pointing to it. The IRQ/BRK vector points to $9000 and the NMI vector points
+    // to $9100.
+    let mut data = crate::cartridge::test_program(program);
+    data[16 + 0x3FFA] = 0x00;
+    data[16 + 0x3FFB] = 0x91;
+    data[16 + 0x3FFE] = 0x00;
+    data[16 + 0x3FFF] = 0x90;
+
+    let cartridge = Rc::new(RefCell::new(Cartridge::from_data(data).unwrap()));
+    let ppu = Rc::new(RefCell::new(PPU::new(
+        cartridge.clone(),
+        crate::ppu::Palette::default(),
+    )));
+    CPU::new(cartridge, ppu)
+}
+
+#[test]
+fn test_write_watchpoint_halts() {
+    let mut cpu = test_cpu(&[
+        0xA9, 0x20, // LDA #$20
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x00, // LDA #$00
+    ]);
+    cpu.add_watchpoint("break-write $2006".parse().unwrap());
+
+    cpu.tick();
+    assert!(cpu.halted().is_none());
+
+    cpu.tick();
+    assert_eq!(cpu.halted().map(|wp| wp.addr), Some(0x2006));
+
+    // the CPU does not advance while halted.
+    let pc = cpu.reg.pc;
+    assert_eq!(cpu.tick(), 0);
+    assert_eq!(cpu.reg.pc, pc);
+
+    cpu.resume();
+    cpu.tick();
+    assert_eq!(cpu.reg.a, 0x00);
+}
+
+#[test]
+fn test_famicom_microphone() {
+    let mut cpu = test_cpu(&[]);
+    cpu.joypad_2.microphone = true;
+    assert_eq!(cpu.readb(0x4016) & 0x04, 0x00);
+
+    cpu.famicom = true;
+    assert_eq!(cpu.readb(0x4016) & 0x04, 0x04);
+    assert_eq!(cpu.readb(0x4017) & 0x04, 0x00);
+
+    cpu.joypad_2.microphone = false;
+    assert_eq!(cpu.readb(0x4016) & 0x04, 0x00);
+}
+
+#[test]
+fn test_rmw_computes_address_once() {
+    let mut cpu = test_cpu(&[
+        0xEE, 0x00, 0x03, // INC $0300
+    ]);
+    cpu.ram[0x0300] = 0x41;
+    cpu.bus_log.clear();
+    cpu.tick();
+
+    assert_eq!(
+        cpu.bus_log,
+        vec![
+            (Access::Read, 0x8000),
+            (Access::Read, 0x8001),
+            (Access::Read, 0x8002),
+            (Access::Read, 0x0300),
+            (Access::Write, 0x0300),
+            (Access::Write, 0x0300),
+        ]
+    );
+    assert_eq!(cpu.ram[0x0300], 0x42);
+    assert_eq!(cpu.reg.pc, 0x8003);
+}
+
+#[test]
+fn test_init_ram() {
+    let seeded = || {
+        let mut cpu = test_cpu(&[]);
+        cpu.init_ram(RamInit::Seed(1234));
+        cpu.ram
+    };
+    assert_eq!(seeded()[..], seeded()[..]);
+    assert!(seeded().iter().any(|&byte| byte != 0));
+
+    let mut cpu = test_cpu(&[]);
+    cpu.init_ram(RamInit::Seed(4321));
+    assert_ne!(cpu.ram[..], seeded()[..]);
+    cpu.init_ram(RamInit::Zero);
+    assert!(cpu.ram.iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn test_indexed_write_dummy_read() {
+    use crate::cartridge::{Mapper, Mirroring};
+
+    // a mapper serving a program from $C000 and logging every access to PRG ROM.
+    struct Logger {
+        program: Vec<u8>,
+        log: Rc<RefCell<Vec<(Access, u16)>>>,
+    }
+    impl Mapper for Logger {
+        fn readb(&self, addr: u16) -> u8 {
+            if addr >= 0x8000 {
+                self.log.borrow_mut().push((Access::Read, addr));
+            }
+            match addr {
+                0xFFFC => 0x00,
+                0xFFFD => 0xC0,
+                0xC000..=0xFFFF => self
+                    .program
+                    .get(addr as usize - 0xC000)
+                    .cloned()
+                    .unwrap_or(0),
+                _ => 0,
+            }
+        }
+        fn writeb(&mut self, addr: u16, _: u8) {
+            self.log.borrow_mut().push((Access::Write, addr));
+        }
+        fn mirroring(&self) -> Mirroring {
+            Mirroring::Horizontal
+        }
+    }
+
+    let log = Rc::new(RefCell::new(vec![]));
+    let cartridge = Rc::new(RefCell::new(Cartridge::from_mapper(Box::new(Logger {
+        program: vec![
+            0xA2, 0x20, // LDX #$20
+            0x9D, 0xF0, 0x80, // STA $80F0,X
+            0x9D, 0x00, 0x80, // STA $8000,X
+        ],
+        log: log.clone(),
+    }))));
+    let ppu = Rc::new(RefCell::new(PPU::new(
+        cartridge.clone(),
+        crate::ppu::Palette::default(),
+    )));
+    let mut cpu = CPU::new(cartridge, ppu);
+    cpu.tick();
+
+    // the index carries into the high byte, so the mapper sees the dummy read at the uncarried
+    // address before the write.
+    log.borrow_mut().clear();
+    cpu.tick();
+    assert_eq!(
+        *log.borrow(),
+        vec![
+            (Access::Read, 0xC002),
+            (Access::Read, 0xC003),
+            (Access::Read, 0xC004),
+            (Access::Read, 0x8010),
+            (Access::Write, 0x8110),
+        ]
+    );
+
+    // without a carry the dummy read hits the effective address.
+    log.borrow_mut().clear();
+    cpu.tick();
+    assert_eq!(
+        *log.borrow(),
+        vec![
+            (Access::Read, 0xC005),
+            (Access::Read, 0xC006),
+            (Access::Read, 0xC007),
+            (Access::Read, 0x8020),
+            (Access::Write, 0x8020),
+        ]
+    );
+}
+
+#[test]
+fn test_plp_sets_unused_bit() {
+    let mut cpu = test_cpu(&[
+        0xA9, 0xD3, // LDA #$D3
+        0x48, // PHA
+        0x28, // PLP
+        0xA9, 0x80, // LDA #$80
+        0x48, // PHA
+        0xA9, 0x00, // LDA #$00
+        0x48, // PHA
+        0x48, // PHA
+        0x40, // RTI
+    ]);
+    for _ in 0..3 {
+        cpu.tick();
+    }
+
+    // bit 5 is set and the B flag is dropped.
+    assert_eq!(cpu.reg.p(), 0b1110_0011);
+
+    // RTI pulls a status with bit 5 clear, and bit 5 still reads as 1.
+    for _ in 0..6 {
+        cpu.tick();
+    }
+    assert_eq!(cpu.reg.p(), 0b0010_0000);
+    assert_eq!(cpu.pc(), 0x8000);
+}
+
+#[test]
+fn test_php_plp_flags() {
+    let mut cpu = test_cpu(&[
+        0x08, // PHP
+        0xA9, 0x54, // LDA #$54
+        0x48, // PHA
+        0x28, // PLP
+        0xA9, 0x80, // LDA #$80
+        0x48, // PHA
+        0xA9, 0x20, // LDA #$20
+        0x48, // PHA
+        0xA9, 0xD5, // LDA #$D5
+        0x48, // PHA
+        0x40, // RTI
+    ]);
+    cpu.reg.set_flag(Flag::N, true);
+    cpu.reg.set_flag(Flag::C, true);
+    cpu.tick();
+    // the pushed copy has the typed flags, bit 5 and the B flag set.
+    assert_eq!(cpu.ram[0x01FD], 0b1011_0101);
+
+    for _ in 0..3 {
+        cpu.tick();
+    }
+    // the pulled B flag is dropped.
+    assert_eq!(cpu.reg.p(), 0b0110_0100);
+    assert!(cpu.reg.get_flag(Flag::V));
+    assert!(cpu.reg.get_flag(Flag::I));
+    assert!(!cpu.reg.get_flag(Flag::N));
+    assert!(!cpu.reg.get_flag(Flag::D));
+    assert!(!cpu.reg.get_flag(Flag::Z));
+    assert!(!cpu.reg.get_flag(Flag::C));
+
+    // RTI drops the pulled B flag too.
+    for _ in 0..7 {
+        cpu.tick();
+    }
+    assert_eq!(cpu.reg.p(), 0b1110_0101);
+    assert_eq!(cpu.pc(), 0x8020);
+}
+
+#[test]
+fn test_genie_codes() {
+    let mut cpu = test_cpu(&[]);
+    let rom = cpu.readb(0x8010);
+    cpu.add_genie_code(GenieCode {
+        address: 0x8010,
+        value: 0x42,
+        compare: None,
+    });
+    assert_eq!(cpu.readb(0x8010), 0x42);
+    assert_eq!(cpu.readb(0x8011), rom);
+
+    // a code with a compare value only applies when the ROM holds it.
+    let mut cpu = test_cpu(&[0xEA, 0xEA]);
+    cpu.add_genie_code(GenieCode {
+        address: 0x8000,
+        value: 0x42,
+        compare: Some(0xEA),
+    });
+    cpu.add_genie_code(GenieCode {
+        address: 0x8001,
+        value: 0x42,
+        compare: Some(0x00),
+    });
+    assert_eq!(cpu.readb(0x8000), 0x42);
+    assert_eq!(cpu.readb(0x8001), 0xEA);
+}
+
+#[test]
+fn test_lax_sax() {
+    let mut cpu = test_cpu(&[
+        0xA7, 0x10, // LAX $10
+        0xA9, 0x0F, // LDA #$0F
+        0x87, 0x11, // SAX $11
+    ]);
+    cpu.ram[0x10] = 0x93;
+    cpu.tick();
+    assert_eq!((cpu.reg.a, cpu.reg.x), (0x93, 0x93));
+    assert!(cpu.reg.get_flag(Flag::N));
+    assert!(!cpu.reg.get_flag(Flag::Z));
+
+    cpu.tick();
+    cpu.tick();
+    assert_eq!(cpu.ram[0x11], 0x03);
+}
+
+#[test]
+fn test_trace_write_error() {
+    struct Full;
+    impl Write for Full {
+        fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::WriteZero.into())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut cpu = test_cpu(&[
+        0xE8, // INX
+        0xE8, // INX
+    ]);
+    cpu.trace = Some(Box::new(Full));
+    cpu.tick();
+    assert!(cpu.trace.is_none());
+    cpu.tick();
+    assert_eq!(cpu.reg.x, 2);
+}
+
+#[test]
+fn test_ign() {
+    let mut cpu = test_cpu(&[
+        0x04, 0x10, // IGN $10
+        0x14, 0x10, // IGN $10,X
+        0x0C, 0x00, 0x02, // IGN $0200
+        0xE8, // INX
+    ]);
+    assert_eq!(cpu.tick(), 3);
+    assert_eq!(cpu.tick(), 4);
+    assert_eq!(cpu.tick(), 4);
+    assert_eq!(cpu.reg.pc, 0x8007);
+    assert_eq!(
+        disasm::disassemble(&cpu, 0x8000),
+        ("*NOP $10".to_string(), 2)
+    );
+    assert_eq!(
+        disasm::disassemble(&cpu, 0x8002),
+        ("*NOP $10,X".to_string(), 2)
+    );
+}
+
+#[test]
+fn test_axs() {
+    let mut cpu = test_cpu(&[
+        0xA9, 0x3C, // LDA #$3C
+        0xA2, 0x0F, // LDX #$0F
+        0xCB, 0x04, // AXS #$04
+        0xCB, 0x09, // AXS #$09
+    ]);
+    for _ in 0..3 {
+        cpu.tick();
+    }
+    assert_eq!(cpu.reg.x, 0x08);
+    assert_eq!(cpu.reg.a, 0x3C);
+    assert!(cpu.reg.get_flag(Flag::C));
+    assert!(!cpu.reg.get_flag(Flag::Z));
+
+    cpu.tick();
+    assert_eq!(cpu.reg.x, 0xFF);
+    assert!(!cpu.reg.get_flag(Flag::C));
+    assert!(cpu.reg.get_flag(Flag::N));
+}
+
+#[test]
+fn test_jam() {
+    let mut cpu = test_cpu(&[
+        0x02, // JAM
+        0xE8, // INX
+    ]);
+    cpu.tick();
+    // a jammed CPU runs no more instructions, but time still passes.
+    let cycles = cpu.cycles;
+    cpu.tick();
+    assert_eq!(cpu.reg.x, 0);
+    assert!(cpu.cycles > cycles);
+
+    cpu.reset();
+    cpu.set_pc(0x8001);
+    cpu.tick();
+    assert_eq!(cpu.reg.x, 1);
+}
+
+#[test]
+fn test_dcp_isc() {
+    let mut cpu = test_cpu(&[
+        0xA9, 0x41, // LDA #$41
+        0xC7, 0x10, // DCP $10
+        0x38, // SEC
+        0xE7, 0x11, // ISC $11
+    ]);
+    cpu.ram[0x10] = 0x42;
+    cpu.ram[0x11] = 0x40;
+    cpu.tick();
+    cpu.tick();
+    assert_eq!(cpu.ram[0x10], 0x41);
+    assert!(cpu.reg.get_flag(Flag::Z));
+    assert!(cpu.reg.get_flag(Flag::C));
+
+    cpu.tick();
+    cpu.tick();
+    assert_eq!(cpu.ram[0x11], 0x41);
+    assert_eq!(cpu.reg.a, 0x00);
+    assert!(cpu.reg.get_flag(Flag::Z));
+    assert!(cpu.reg.get_flag(Flag::C));
+    assert!(!cpu.reg.get_flag(Flag::V));
+}
+
+#[test]
+fn test_slo_rla() {
+    let mut cpu = test_cpu(&[
+        0xA9, 0x01, // LDA #$01
+        0x07, 0x10, // SLO $10
+        0x27, 0x11, // RLA $11
+    ]);
+    cpu.ram[0x10] = 0xC0;
+    cpu.ram[0x11] = 0x40;
+    cpu.tick();
+    assert_eq!(cpu.tick(), 5);
+    assert_eq!(cpu.ram[0x10], 0x80);
+    assert_eq!(cpu.reg.a, 0x81);
+    assert!(cpu.reg.get_flag(Flag::C));
+    assert!(cpu.reg.get_flag(Flag::N));
+
+    cpu.tick();
+    assert_eq!(cpu.ram[0x11], 0x81);
+    assert_eq!(cpu.reg.a, 0x81);
+    assert!(!cpu.reg.get_flag(Flag::C));
+    assert!(cpu.reg.get_flag(Flag::N));
+}
+
+#[test]
+fn test_sre_rra() {
+    let mut cpu = test_cpu(&[
+        0xA9, 0xFF, // LDA #$FF
+        0x47, 0x10, // SRE $10
+        0x67, 0x11, // RRA $11
+    ]);
+    cpu.ram[0x10] = 0x03;
+    cpu.ram[0x11] = 0x02;
+    cpu.tick();
+    cpu.tick();
+    assert_eq!(cpu.ram[0x10], 0x01);
+    assert_eq!(cpu.reg.a, 0xFE);
+    assert!(cpu.reg.get_flag(Flag::C));
+    assert!(cpu.reg.get_flag(Flag::N));
+
+    // the carry out of SRE is rotated into bit 7, and the carry out of ROR is added to A.
+    cpu.tick();
+    assert_eq!(cpu.ram[0x11], 0x81);
+    assert_eq!(cpu.reg.a, 0x7F);
+    assert!(cpu.reg.get_flag(Flag::C));
+    assert!(cpu.reg.get_flag(Flag::V));
+    assert!(!cpu.reg.get_flag(Flag::N));
+}
+
+#[test]
+fn test_decimal_adc() {
+    let mut cpu = test_cpu(&[
+        0xF8, // SED
+        0xA9, 0x09, // LDA #$09
+        0x69, 0x01, // ADC #$01
+        0xA9, 0x99, // LDA #$99
+        0x69, 0x01, // ADC #$01
+    ]);
+    cpu.decimal = true;
+    for _ in 0..3 {
+        cpu.tick();
+    }
+    assert_eq!(cpu.reg.a, 0x10);
+    assert!(!cpu.reg.get_flag(Flag::C));
+
+    cpu.tick();
+    cpu.tick();
+    assert_eq!(cpu.reg.a, 0x00);
+    assert!(cpu.reg.get_flag(Flag::C));
+}
+
+#[test]
+fn test_decimal_sbc() {
+    let mut cpu = test_cpu(&[
+        0xF8, // SED
+        0x38, // SEC
+        0xA9, 0x10, // LDA #$10
+        0xE9, 0x01, // SBC #$01
+        0xA9, 0x00, // LDA #$00
+        0xE9, 0x01, // SBC #$01
+    ]);
+    cpu.decimal = true;
+    for _ in 0..4 {
+        cpu.tick();
+    }
+    assert_eq!(cpu.reg.a, 0x09);
+    assert!(cpu.reg.get_flag(Flag::C));
+
+    cpu.tick();
+    cpu.tick();
+    assert_eq!(cpu.reg.a, 0x99);
+    assert!(!cpu.reg.get_flag(Flag::C));
+}
+
+#[test]
+fn test_decimal_disabled_on_2a03() {
+    let mut cpu = test_cpu(&[
+        0xF8, // SED
+        0xA9, 0x09, // LDA #$09
+        0x69, 0x01, // ADC #$01
+    ]);
+    for _ in 0..3 {
+        cpu.tick();
+    }
+    assert_eq!(cpu.reg.a, 0x0A);
+}
+
+#[test]
+fn test_irq() {
+    let mut cpu = test_cpu(&[]);
+    cpu.reg.set_flag(Flag::I, true);
+    cpu.irq();
+    assert_eq!(cpu.reg.pc, 0x8000);
+
+    cpu.reg.set_flag(Flag::I, false);
+    cpu.irq();
+    assert_eq!(cpu.reg.pc, 0x9000);
+    assert!(cpu.reg.get_flag(Flag::I));
+    assert_eq!(cpu.popb() & 0b0011_0000, 0b0010_0000);
+    assert_eq!(cpu.popw(), 0x8000);
+}
+
+#[test]
+fn test_brk_rti_return_address() {
+    let mut program = vec![0; 0x1001];
+    program[..4].copy_from_slice(&[
+        0x00, 0xFF, // BRK, with a signature byte
+        0xA9, 0x01, // LDA #$01
+    ]);
+    // the BRK handler at $9000.
+    program[0x1000] = 0x40; // RTI
+    let mut cpu = test_cpu(&program);
+
+    cpu.tick();
+    assert_eq!(cpu.reg.pc, 0x9000);
+    cpu.tick();
+    assert_eq!(cpu.reg.pc, 0x8002);
+    assert_eq!(cpu.reg.s, 0xFD);
+    cpu.tick();
+    assert_eq!(cpu.reg.a, 0x01);
+}
+
+#[test]
+fn test_nested_jsr_rts() {
+    let mut program = vec![0; 0x21];
+    program[..5].copy_from_slice(&[
+        0x20, 0x10, 0x80, // JSR $8010
+        0xA2, 0x01, // LDX #$01
+    ]);
+    program[0x10..0x14].copy_from_slice(&[
+        0x20, 0x20, 0x80, // JSR $8020
+        0x60, // RTS
+    ]);
+    program[0x20] = 0x60; // RTS
+    let mut cpu = test_cpu(&program);
+
+    let pcs: Vec<u16> = (0..5)
+        .map(|_| {
+            cpu.tick();
+            cpu.reg.pc
+        })
+        .collect();
+    assert_eq!(pcs, [0x8010, 0x8020, 0x8013, 0x8003, 0x8005]);
+    assert_eq!(cpu.reg.s, 0xFD);
+    assert_eq!(cpu.reg.x, 0x01);
+}
+
+#[test]
+fn test_brk_sets_b_flag() {
+    let mut cpu = test_cpu(&[
+        0x00, // BRK
+    ]);
+    cpu.tick();
+    assert_eq!(cpu.reg.pc, 0x9000);
+    assert_eq!(cpu.popb() & 0b0011_0000, 0b0011_0000);
+    assert_eq!(cpu.popw(), 0x8002);
+}
+
+#[test]
+fn test_nmi_hijacks_brk() {
+    let mut cpu = test_cpu(&[
+        0x00, // BRK
+    ]);
+
+    // fetch the opcode and let the NMI arrive before the vector is fetched.
+    cpu.loadb_bump();
+    cpu.nmi();
+    cpu.brk(AddressingMode::Implied);
+
+    assert_eq!(cpu.reg.pc, 0x9100);
+    assert!(!cpu.nmi_pending);
+    // the status pushed by BRK is kept, so the handler can still see the B flag.
+    assert_eq!(cpu.popb() & 0b0011_0000, 0b0011_0000);
+    assert_eq!(cpu.popw(), 0x8002);
+}
+
+#[test]
+fn test_nmi_is_serviced_before_next_instruction() {
+    let mut cpu = test_cpu(&[
+        0xEA, // NOP
+    ]);
+    cpu.nmi();
+    assert_eq!(cpu.tick(), 7);
+    assert_eq!(cpu.reg.pc, 0x9100);
+    assert!(cpu.reg.get_flag(Flag::I));
+}
+
+#[test]
+fn test_page_crossing_penalty() {
+    let mut cpu = test_cpu(&[
+        0xBD, 0xFF, 0x20, // LDA $20FF,X
+        0xBD, 0xFF, 0x20, // LDA $20FF,X
+        0x9D, 0xFF, 0x02, // STA $02FF,X
+    ]);
+    assert_eq!(cpu.tick(), 4);
+
+    cpu.reg.x = 1;
+    assert_eq!(cpu.tick(), 5);
+
+    // stores always take the extra cycle, crossing a page or not.
+    assert_eq!(cpu.tick(), 5);
+}
+
+#[test]
+fn test_indirect_y_page_crossing_penalty() {
+    let mut cpu = test_cpu(&[
+        0xB1, 0x10, // LDA ($10),Y
+        0xB1, 0x10, // LDA ($10),Y
+    ]);
+    cpu.ram[0x10] = 0xFF;
+    cpu.ram[0x11] = 0x20;
+    assert_eq!(cpu.tick(), 5);
+
+    cpu.reg.y = 1;
+    assert_eq!(cpu.tick(), 6);
+}
+
+#[test]
+fn test_sty_cmp_cycles() {
+    let mut cpu = test_cpu(&[
+        0x84, 0x10, // STY $10
+        0xD1, 0x20, // CMP ($20),Y
+        0xD1, 0x20, // CMP ($20),Y
+    ]);
+    cpu.ram[0x20] = 0xFF;
+    cpu.ram[0x21] = 0x02;
+    assert_eq!(cpu.tick(), 3);
+    assert_eq!(cpu.tick(), 5);
+
+    cpu.reg.y = 1;
+    assert_eq!(cpu.tick(), 6);
+}
+
+#[test]
+fn test_cycles() {
+    let mut cpu = test_cpu(&[0xEA, 0xEA, 0xEA, 0xEA]);
+    assert_eq!(cpu.cycles, 7);
+    for _ in 0..4 {
+        cpu.tick();
+    }
+    assert_eq!(cpu.cycles, 7 + 4 * 2);
+
+    cpu.reset();
+    assert_eq!(cpu.cycles, 7);
+}
+
+#[test]
+fn test_branch_cycles() {
+    let mut program = vec![0xEA; 0x100];
+    program[..4].copy_from_slice(&[
+        0xB0, 0x02, // BCS +2
+        0x90, 0x7C, // BCC $8080
+    ]);
+    program[0x80..0x82].copy_from_slice(&[
+        0x90, 0x7E, // BCC $8100
+    ]);
+    let mut cpu = test_cpu(&program);
+
+    // not taken.
+    assert_eq!(cpu.tick(), 2);
+    assert_eq!(cpu.reg.pc, 0x8002);
+
+    // taken, same page.
+    assert_eq!(cpu.tick(), 3);
+    assert_eq!(cpu.reg.pc, 0x8080);
+
+    // taken, crossing into the next page.
+    assert_eq!(cpu.tick(), 4);
+    assert_eq!(cpu.reg.pc, 0x8100);
+}
+
+#[test]
+fn test_oam_dma() {
+    let mut cpu = test_cpu(&[
+        0xA9, 0x02, // LDA #$02
+        0x8D, 0x14, 0x40, // STA $4014
+    ]);
+    for i in 0..0x100 {
+        cpu.ram[0x200 + i] = i as u8;
+    }
+
+    cpu.tick();
+    let cycles = cpu.cycles;
+    cpu.tick();
+    // 4 cycles for the store, plus the stall which started on an odd cycle.
+    assert_eq!(cpu.cycles - cycles, 4 + 514);
+    assert_eq!(cpu.ppu.borrow().oam()[..], cpu.ram[0x200..0x300]);
+}
+
+#[test]
+fn test_joypad_read_order() {
+    let buttons: [fn(&mut Joypad); 8] = [
+        |j| j.a = true,
+        |j| j.b = true,
+        |j| j.select = true,
+        |j| j.start = true,
+        |j| j.up = true,
+        |j| j.down = true,
+        |j| j.left = true,
+        |j| j.right = true,
+    ];
+
+    let mut cpu = test_cpu(&[]);
+    for (i, press) in buttons.iter().enumerate() {
+        cpu.joypad_1 = Joypad::default();
+        press(&mut cpu.joypad_1);
+
+        cpu.writeb(0x4016, 0x01);
+        // while the strobe is high every read reports A.
+        assert_eq!(cpu.readb(0x4016) & 0x01, (i == 0) as u8);
+        assert_eq!(cpu.readb(0x4016) & 0x01, (i == 0) as u8);
+        cpu.writeb(0x4016, 0x00);
+
+        for bit in 0..8 {
+            let expected = (bit == i) as u8;
+            assert_eq!(
+                cpu.readb(0x4016) & 0x1F,
+                expected,
+                "button {} read {}",
+                i,
+                bit
+            );
+        }
+    }
+}
+
+#[test]
+fn test_open_bus() {
+    let mut cpu = test_cpu(&[
+        0xA5, 0x10, // LDA $10
+        0xAD, 0x18, 0x40, // LDA $4018
+        0xAD, 0x16, 0x40, // LDA $4016
+    ]);
+    cpu.ram[0x10] = 0x42;
+    cpu.readb(0x0010);
+    assert_eq!(cpu.readb(0x4018), 0x42);
+    // NROM has nothing mapped below $6000.
+    assert_eq!(cpu.readb(0x5000), 0x42);
+    cpu.writeb(0x0000, 0x17);
+    assert_eq!(cpu.readb(0x401F), 0x17);
+    cpu.writeb(0x0000, 0x20);
+    assert_eq!(cpu.readb(0x4015), 0x20);
+
+    // the last value on the bus before reading $4018 is the high byte of its address.
+    cpu.tick();
+    cpu.tick();
+    assert_eq!(cpu.reg.a, 0x40);
+
+    // the controllers only drive the low bits, the upper three are open bus too.
+    cpu.joypad_1.a = true;
+    cpu.writeb(0x4016, 0x01);
+    cpu.writeb(0x4016, 0x00);
+    cpu.tick();
+    assert_eq!(cpu.reg.a, 0x41);
+    cpu.ram[0x10] = 0xFF;
+    cpu.readb(0x0010);
+    assert_eq!(cpu.readb(0x4017), 0xE0);
+}
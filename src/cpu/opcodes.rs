@@ -0,0 +1,286 @@
+use super::addressing_mode::AddressingMode;
+use super::CPU;
+
+// opcodes! takes the instruction set as rows of opcodes => method("MNEMONIC", AddressingMode), and
+// generates from them both CPU::execute, which runs instructions, and decode, which disassembles
+// them, so the two can't disagree on what an opcode does.
+macro_rules! opcodes {
+    ($($($opcode:literal)|+ => $method:ident($mnemonic:literal, $mode:ident),)*) => {
+        impl CPU {
+            // execute runs the instruction with the given opcode, which has already been fetched,
+            // and returns the cycles it took, or None if the opcode isn't implemented.
+            pub(super) fn execute(&mut self, opcode: u8) -> Option<u8> {
+                match opcode {
+                    $($($opcode)|+ => Some(self.$method(AddressingMode::$mode)),)*
+                    _ => None,
+                }
+            }
+        }
+
+        // decode returns the mnemonic and addressing mode of an opcode, or None if the opcode isn't
+        // implemented. Unofficial opcodes are prefixed with a *, like nestest's logs do.
+        pub(super) fn decode(opcode: u8) -> Option<(&'static str, AddressingMode)> {
+            match opcode {
+                $($($opcode)|+ => Some(($mnemonic, AddressingMode::$mode)),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+opcodes! {
+    0x69 => adc("ADC", Immediate),
+    0x65 => adc("ADC", ZeroPage),
+    0x75 => adc("ADC", ZeroPageX),
+    0x6D => adc("ADC", Absolute),
+    0x7D => adc("ADC", AbsoluteX),
+    0x79 => adc("ADC", AbsoluteY),
+    0x61 => adc("ADC", IndirectX),
+    0x71 => adc("ADC", IndirectY),
+
+    0x29 => and("AND", Immediate),
+    0x25 => and("AND", ZeroPage),
+    0x35 => and("AND", ZeroPageX),
+    0x2D => and("AND", Absolute),
+    0x3D => and("AND", AbsoluteX),
+    0x39 => and("AND", AbsoluteY),
+    0x21 => and("AND", IndirectX),
+    0x31 => and("AND", IndirectY),
+
+    0x0A => asl("ASL", Accumulator),
+    0x06 => asl("ASL", ZeroPage),
+    0x16 => asl("ASL", ZeroPageX),
+    0x0E => asl("ASL", Absolute),
+    0x1E => asl("ASL", AbsoluteX),
+
+    0x24 => bit("BIT", ZeroPage),
+    0x2C => bit("BIT", Absolute),
+
+    0x90 => bcc("BCC", Relative),
+    0xB0 => bcs("BCS", Relative),
+    0xF0 => beq("BEQ", Relative),
+    0x30 => bmi("BMI", Relative),
+    0xD0 => bne("BNE", Relative),
+    0x10 => bpl("BPL", Relative),
+    0x00 => brk("BRK", Implied),
+    0x50 => bvc("BVC", Relative),
+    0x70 => bvs("BVS", Relative),
+
+    0x18 => clc("CLC", Implied),
+    0xD8 => cld("CLD", Implied),
+    0x58 => cli("CLI", Implied),
+    0xB8 => clv("CLV", Implied),
+
+    0xC9 => cmp("CMP", Immediate),
+    0xC5 => cmp("CMP", ZeroPage),
+    0xD5 => cmp("CMP", ZeroPageX),
+    0xCD => cmp("CMP", Absolute),
+    0xDD => cmp("CMP", AbsoluteX),
+    0xD9 => cmp("CMP", AbsoluteY),
+    0xC1 => cmp("CMP", IndirectX),
+    0xD1 => cmp("CMP", IndirectY),
+
+    0xE0 => cpx("CPX", Immediate),
+    0xE4 => cpx("CPX", ZeroPage),
+    0xEC => cpx("CPX", Absolute),
+    0xC0 => cpy("CPY", Immediate),
+    0xC4 => cpy("CPY", ZeroPage),
+    0xCC => cpy("CPY", Absolute),
+
+    0xC6 => dec("DEC", ZeroPage),
+    0xD6 => dec("DEC", ZeroPageX),
+    0xCE => dec("DEC", Absolute),
+    0xDE => dec("DEC", AbsoluteX),
+    0xCA => dex("DEX", Implied),
+    0x88 => dey("DEY", Implied),
+
+    0x49 => eor("EOR", Immediate),
+    0x45 => eor("EOR", ZeroPage),
+    0x55 => eor("EOR", ZeroPageX),
+    0x4D => eor("EOR", Absolute),
+    0x5D => eor("EOR", AbsoluteX),
+    0x59 => eor("EOR", AbsoluteY),
+    0x41 => eor("EOR", IndirectX),
+    0x51 => eor("EOR", IndirectY),
+
+    0xE6 => inc("INC", ZeroPage),
+    0xF6 => inc("INC", ZeroPageX),
+    0xEE => inc("INC", Absolute),
+    0xFE => inc("INC", AbsoluteX),
+    0xE8 => inx("INX", Implied),
+    0xC8 => iny("INY", Implied),
+
+    0x4C => jmp("JMP", Absolute),
+    0x6C => jmp("JMP", Indirect),
+    0x20 => jsr("JSR", Absolute),
+
+    0xA9 => lda("LDA", Immediate),
+    0xA5 => lda("LDA", ZeroPage),
+    0xB5 => lda("LDA", ZeroPageX),
+    0xAD => lda("LDA", Absolute),
+    0xBD => lda("LDA", AbsoluteX),
+    0xB9 => lda("LDA", AbsoluteY),
+    0xA1 => lda("LDA", IndirectX),
+    0xB1 => lda("LDA", IndirectY),
+
+    0xA2 => ldx("LDX", Immediate),
+    0xA6 => ldx("LDX", ZeroPage),
+    0xB6 => ldx("LDX", ZeroPageY),
+    0xAE => ldx("LDX", Absolute),
+    0xBE => ldx("LDX", AbsoluteY),
+
+    0xA0 => ldy("LDY", Immediate),
+    0xA4 => ldy("LDY", ZeroPage),
+    0xB4 => ldy("LDY", ZeroPageX),
+    0xAC => ldy("LDY", Absolute),
+    0xBC => ldy("LDY", AbsoluteX),
+
+    0x4A => lsr("LSR", Accumulator),
+    0x46 => lsr("LSR", ZeroPage),
+    0x56 => lsr("LSR", ZeroPageX),
+    0x4E => lsr("LSR", Absolute),
+    0x5E => lsr("LSR", AbsoluteX),
+
+    0xEA => nop("NOP", Implied),
+
+    0x09 => ora("ORA", Immediate),
+    0x05 => ora("ORA", ZeroPage),
+    0x15 => ora("ORA", ZeroPageX),
+    0x0D => ora("ORA", Absolute),
+    0x1D => ora("ORA", AbsoluteX),
+    0x19 => ora("ORA", AbsoluteY),
+    0x01 => ora("ORA", IndirectX),
+    0x11 => ora("ORA", IndirectY),
+
+    0x48 => pha("PHA", Implied),
+    0x08 => php("PHP", Implied),
+    0x68 => pla("PLA", Implied),
+    0x28 => plp("PLP", Implied),
+
+    0x2A => rol("ROL", Accumulator),
+    0x26 => rol("ROL", ZeroPage),
+    0x36 => rol("ROL", ZeroPageX),
+    0x2E => rol("ROL", Absolute),
+    0x3E => rol("ROL", AbsoluteX),
+
+    0x6A => ror("ROR", Accumulator),
+    0x66 => ror("ROR", ZeroPage),
+    0x76 => ror("ROR", ZeroPageX),
+    0x6E => ror("ROR", Absolute),
+    0x7E => ror("ROR", AbsoluteX),
+
+    0x40 => rti("RTI", Implied),
+    0x60 => rts("RTS", Implied),
+
+    0xE9 => sbc("SBC", Immediate),
+    0xE5 => sbc("SBC", ZeroPage),
+    0xF5 => sbc("SBC", ZeroPageX),
+    0xED => sbc("SBC", Absolute),
+    0xFD => sbc("SBC", AbsoluteX),
+    0xF9 => sbc("SBC", AbsoluteY),
+    0xE1 => sbc("SBC", IndirectX),
+    0xF1 => sbc("SBC", IndirectY),
+
+    0x38 => sec("SEC", Implied),
+    0xF8 => sed("SED", Implied),
+    0x78 => sei("SEI", Implied),
+
+    0x85 => sta("STA", ZeroPage),
+    0x95 => sta("STA", ZeroPageX),
+    0x8D => sta("STA", Absolute),
+    0x9D => sta("STA", AbsoluteX),
+    0x99 => sta("STA", AbsoluteY),
+    0x81 => sta("STA", IndirectX),
+    0x91 => sta("STA", IndirectY),
+
+    0x86 => stx("STX", ZeroPage),
+    0x96 => stx("STX", ZeroPageY),
+    0x8E => stx("STX", Absolute),
+
+    0x84 => sty("STY", ZeroPage),
+    0x94 => sty("STY", ZeroPageX),
+    0x8C => sty("STY", Absolute),
+
+    0xAA => tax("TAX", Implied),
+    0xA8 => tay("TAY", Implied),
+    0xBA => tsx("TSX", Implied),
+    0x8A => txa("TXA", Implied),
+    0x9A => txs("TXS", Implied),
+    0x98 => tya("TYA", Implied),
+
+    // unofficial opcodes
+    0x0C => ign("*NOP", Absolute),
+    0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => ign("*NOP", AbsoluteX),
+    0x04 | 0x44 | 0x64 => ign("*NOP", ZeroPage),
+    0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => ign("*NOP", ZeroPageX),
+    0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => nop("*NOP", Implied),
+    0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => skb("*NOP", Immediate),
+
+    0x4B => alr("*ALR", Immediate),
+    0x0B | 0x2B => anc("*ANC", Immediate),
+    0x6B => arr("*ARR", Immediate),
+    0xCB => axs("*AXS", Immediate),
+
+    0xA7 => lax("*LAX", ZeroPage),
+    0xB7 => lax("*LAX", ZeroPageY),
+    0xAF => lax("*LAX", Absolute),
+    0xBF => lax("*LAX", AbsoluteY),
+    0xA3 => lax("*LAX", IndirectX),
+    0xB3 => lax("*LAX", IndirectY),
+
+    0x87 => sax("*SAX", ZeroPage),
+    0x97 => sax("*SAX", ZeroPageY),
+    0x8F => sax("*SAX", Absolute),
+    0x83 => sax("*SAX", IndirectX),
+
+    0xC7 => dcp("*DCP", ZeroPage),
+    0xD7 => dcp("*DCP", ZeroPageX),
+    0xCF => dcp("*DCP", Absolute),
+    0xDF => dcp("*DCP", AbsoluteX),
+    0xDB => dcp("*DCP", AbsoluteY),
+    0xC3 => dcp("*DCP", IndirectX),
+    0xD3 => dcp("*DCP", IndirectY),
+
+    0xE7 => isc("*ISB", ZeroPage),
+    0xF7 => isc("*ISB", ZeroPageX),
+    0xEF => isc("*ISB", Absolute),
+    0xFF => isc("*ISB", AbsoluteX),
+    0xFB => isc("*ISB", AbsoluteY),
+    0xE3 => isc("*ISB", IndirectX),
+    0xF3 => isc("*ISB", IndirectY),
+
+    0x07 => slo("*SLO", ZeroPage),
+    0x17 => slo("*SLO", ZeroPageX),
+    0x0F => slo("*SLO", Absolute),
+    0x1F => slo("*SLO", AbsoluteX),
+    0x1B => slo("*SLO", AbsoluteY),
+    0x03 => slo("*SLO", IndirectX),
+    0x13 => slo("*SLO", IndirectY),
+
+    0x27 => rla("*RLA", ZeroPage),
+    0x37 => rla("*RLA", ZeroPageX),
+    0x2F => rla("*RLA", Absolute),
+    0x3F => rla("*RLA", AbsoluteX),
+    0x3B => rla("*RLA", AbsoluteY),
+    0x23 => rla("*RLA", IndirectX),
+    0x33 => rla("*RLA", IndirectY),
+
+    0x47 => sre("*SRE", ZeroPage),
+    0x57 => sre("*SRE", ZeroPageX),
+    0x4F => sre("*SRE", Absolute),
+    0x5F => sre("*SRE", AbsoluteX),
+    0x5B => sre("*SRE", AbsoluteY),
+    0x43 => sre("*SRE", IndirectX),
+    0x53 => sre("*SRE", IndirectY),
+
+    0x67 => rra("*RRA", ZeroPage),
+    0x77 => rra("*RRA", ZeroPageX),
+    0x6F => rra("*RRA", Absolute),
+    0x7F => rra("*RRA", AbsoluteX),
+    0x7B => rra("*RRA", AbsoluteY),
+    0x63 => rra("*RRA", IndirectX),
+    0x73 => rra("*RRA", IndirectY),
+
+    0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72
+    | 0x92 | 0xB2 | 0xD2 | 0xF2 => jam("*JAM", Implied),
+}
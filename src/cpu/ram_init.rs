@@ -0,0 +1,78 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+
+// RamInit is what the 2kb of RAM hold at power-on. The real RAM comes up with mostly random
+// contents, which some games use as a source of entropy:
+//      zero        fills it with zeros.
+//      random      fills it with different bytes on every run.
+//      seed:N      fills it with the same pseudo-random bytes for every run with seed N.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RamInit {
+    Zero,
+    Random,
+    Seed(u64),
+}
+
+impl RamInit {
+    pub fn fill(self, ram: &mut [u8]) {
+        let seed = match self {
+            RamInit::Zero => {
+                ram.iter_mut().for_each(|byte| *byte = 0);
+                return;
+            }
+            // the standard library seeds its hashers with randomness from the OS.
+            RamInit::Random => RandomState::new().build_hasher().finish(),
+            RamInit::Seed(seed) => seed,
+        };
+
+        let mut rng = SplitMix64(seed);
+        for chunk in ram.chunks_mut(8) {
+            let bytes = rng.next().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+impl FromStr for RamInit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(RamInit::Zero),
+            "random" => Ok(RamInit::Random),
+            _ => {
+                let seed = s
+                    .strip_prefix("seed:")
+                    .ok_or_else(|| format!("unknown RAM init: {}", s))?;
+                let seed = seed
+                    .parse()
+                    .map_err(|_| format!("invalid RAM init seed: {}", seed))?;
+                Ok(RamInit::Seed(seed))
+            }
+        }
+    }
+}
+
+// SplitMix64 is a small pseudo-random number generator, good enough to scramble RAM. See
+// https://prng.di.unimi.it/splitmix64.c.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[test]
+fn test_parse_ram_init() {
+    assert_eq!("zero".parse(), Ok(RamInit::Zero));
+    assert_eq!("random".parse(), Ok(RamInit::Random));
+    assert_eq!("seed:42".parse(), Ok(RamInit::Seed(42)));
+    assert!("seed:x".parse::<RamInit>().is_err());
+    assert!("ones".parse::<RamInit>().is_err());
+}
@@ -5,7 +5,7 @@ pub struct Registers {
     pub y: u8,
     pub pc: u16,
     pub s: u8,
-    pub p: u8,
+    flags: Flags,
 }
 
 impl Default for Registers {
@@ -16,56 +16,76 @@ impl Default for Registers {
             y: 0,
             pc: 0,
             s: 0xFD,
-            p: 0,
+            flags: Flags::default(),
         }
     }
 }
 
 impl Registers {
     pub fn set_flag(&mut self, flag: Flag, val: bool) {
-        let sum = match flag {
-            Flag::N => 0b1000_0000,
-            Flag::V => 0b0100_0000,
-            Flag::B => 0b0001_0000,
-            Flag::D => 0b0000_1000,
-            Flag::I => 0b0000_0100,
-            Flag::Z => 0b0000_0010,
-            Flag::C => 0b0000_0001,
-        };
-
-        if val {
-            self.p |= sum;
-        } else {
-            self.p &= !sum;
+        let f = &mut self.flags;
+        match flag {
+            Flag::N => f.n = val,
+            Flag::V => f.v = val,
+            Flag::D => f.d = val,
+            Flag::I => f.i = val,
+            Flag::Z => f.z = val,
+            Flag::C => f.c = val,
         }
     }
 
     pub fn get_flag(&self, flag: Flag) -> bool {
+        let f = &self.flags;
         match flag {
-            Flag::N => (self.p & 0b1000_0000) > 0,
-            Flag::V => (self.p & 0b0100_0000) > 0,
-            Flag::B => (self.p & 0b0001_0000) > 0,
-            Flag::D => (self.p & 0b0000_1000) > 0,
-            Flag::I => (self.p & 0b0000_0100) > 0,
-            Flag::Z => (self.p & 0b0000_0010) > 0,
-            Flag::C => (self.p & 0b0000_0001) > 0,
+            Flag::N => f.n,
+            Flag::V => f.v,
+            Flag::D => f.d,
+            Flag::I => f.i,
+            Flag::Z => f.z,
+            Flag::C => f.c,
         }
     }
+
+    // p packs the flags into the status register, bit 5 is not wired to anything so it always
+    // reads as 1, and the B flag is clear.
+    pub fn p(&self) -> u8 {
+        self.flags.to_byte(false)
+    }
+
+    // pushed_p is the copy of the status register pushed to the stack, with the B flag set by
+    // PHP and BRK and clear for IRQ and NMI.
+    // See https://wiki.nesdev.com/w/index.php/Status_flags#The_B_flag
+    pub fn pushed_p(&self, b: bool) -> u8 {
+        self.flags.to_byte(b)
+    }
+
+    // set_p loads the status register, the B flag and bit 5 don't exist in P so they're dropped.
+    pub fn set_p(&mut self, val: u8) {
+        self.flags = Flags {
+            n: val & 0b1000_0000 != 0,
+            v: val & 0b0100_0000 != 0,
+            d: val & 0b0000_1000 != 0,
+            i: val & 0b0000_0100 != 0,
+            z: val & 0b0000_0010 != 0,
+            c: val & 0b0000_0001 != 0,
+        };
+    }
 }
 
+// the flags of the status register. The B flag has no storage in the CPU, it only shows up in the
+// copies of P pushed to the stack, so it has no variant here.
 #[allow(unused)]
 pub enum Flag {
     N,
     V,
-    B,
     D,
     I,
     Z,
     C,
 }
 
-#[derive(Debug, Default)]
-pub struct Flags {
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Flags {
     n: bool,
     v: bool,
     d: bool,
@@ -73,3 +93,16 @@ pub struct Flags {
     z: bool,
     c: bool,
 }
+
+impl Flags {
+    fn to_byte(self, b: bool) -> u8 {
+        (self.n as u8) << 7
+            | (self.v as u8) << 6
+            | 0b0010_0000
+            | (b as u8) << 4
+            | (self.d as u8) << 3
+            | (self.i as u8) << 2
+            | (self.z as u8) << 1
+            | self.c as u8
+    }
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// Watchpoint halts the CPU on the first access to a given address. They are described with the
+// same syntax used by the debugger commands:
+//      break-reg $2006     halts on any read or write to $2006.
+//      break-write $0300   halts on the first write to $0300.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub addr: u16,
+    pub on_read: bool,
+    pub on_write: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, addr: u16, access: Access) -> bool {
+        if self.addr != addr {
+            return false;
+        }
+
+        match access {
+            Access::Read => self.on_read,
+            Access::Write => self.on_write,
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.on_read, self.on_write) {
+            (true, true) => write!(f, "break-reg ${:04X}", self.addr),
+            (false, true) => write!(f, "break-write ${:04X}", self.addr),
+            _ => write!(f, "break-read ${:04X}", self.addr),
+        }
+    }
+}
+
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let (on_read, on_write) = match parts.next() {
+            Some("break-reg") => (true, true),
+            Some("break-write") => (false, true),
+            Some("break-read") => (true, false),
+            _ => return Err(format!("unknown watchpoint command: {}", s)),
+        };
+
+        let addr = parts
+            .next()
+            .ok_or_else(|| format!("missing address in watchpoint: {}", s))?;
+        let addr = addr.trim_start_matches('$').trim_start_matches("0x");
+        let addr = u16::from_str_radix(addr, 16)
+            .map_err(|_| format!("invalid address in watchpoint: {}", s))?;
+
+        Ok(Watchpoint {
+            addr,
+            on_read,
+            on_write,
+        })
+    }
+}
+
+#[test]
+fn test_parse_watchpoint() {
+    let wp: Watchpoint = "break-reg $2006".parse().unwrap();
+    assert_eq!(wp.addr, 0x2006);
+    assert!(wp.matches(0x2006, Access::Read));
+    assert!(wp.matches(0x2006, Access::Write));
+
+    let wp: Watchpoint = "break-write $0300".parse().unwrap();
+    assert_eq!(wp.addr, 0x0300);
+    assert!(!wp.matches(0x0300, Access::Read));
+    assert!(wp.matches(0x0300, Access::Write));
+
+    assert!("break-write".parse::<Watchpoint>().is_err());
+    assert!("step $0300".parse::<Watchpoint>().is_err());
+}
@@ -0,0 +1,183 @@
+use crate::console::Console;
+use crate::cpu::{self, Watchpoint, CPU};
+use std::io::{BufRead, Write};
+
+// Debugger pauses emulation before the instruction at a breakpoint is executed, and lets the user
+// step through the program from a prompt reading these commands:
+//      step, s             runs the next instruction.
+//      continue, c         runs until the next breakpoint.
+//      regs, r             prints the registers.
+//      mem $0300 [len]     prints len bytes of memory starting at $0300, 16 by default.
+//      break $C000         adds a breakpoint, break-reg/break-read/break-write add watchpoints.
+//      quit, q             stops emulation.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: Vec<u16>,
+}
+
+impl Debugger {
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.contains(&addr) {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    // should_break returns whether the instruction the CPU is about to run has a breakpoint.
+    pub fn should_break(&self, cpu: &CPU) -> bool {
+        self.breakpoints.contains(&cpu.pc())
+    }
+
+    // step runs a single instruction, keeping the rest of the console in sync with the CPU.
+    pub fn step(&self, console: &mut Console) {
+        console.step();
+    }
+
+    // continue_until_break runs until the CPU reaches a breakpoint, or a watchpoint halts it. The
+    // breakpoint at the current instruction, if any, is stepped over. The frontend doesn't use it,
+    // as it has to keep presenting frames while the program runs.
+    #[allow(dead_code)]
+    pub fn continue_until_break(&self, console: &mut Console) {
+        self.step(console);
+        while !self.should_break(&console.cpu) && console.cpu.halted().is_none() {
+            self.step(console);
+        }
+    }
+
+    pub fn peek(&self, cpu: &CPU, addr: u16) -> u8 {
+        cpu.peekb(addr)
+    }
+
+    // prompt reads commands until the user asks to continue, returns false if the user asked to
+    // quit instead.
+    pub fn prompt(
+        &mut self,
+        console: &mut Console,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+    ) -> std::io::Result<bool> {
+        let cpu = &console.cpu;
+        let (instruction, _) = cpu::disassemble(cpu, cpu.pc());
+        writeln!(output, "${:04X} {}", cpu.pc(), instruction)?;
+
+        loop {
+            write!(output, "> ")?;
+            output.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(false);
+            }
+
+            let mut parts = line.split_whitespace();
+            match parts.next() {
+                Some("step") | Some("s") => {
+                    self.step(console);
+                    let cpu = &console.cpu;
+                    let (instruction, _) = cpu::disassemble(cpu, cpu.pc());
+                    writeln!(output, "${:04X} {}", cpu.pc(), instruction)?;
+                }
+                Some("continue") | Some("c") => return Ok(true),
+                Some("regs") | Some("r") => writeln!(output, "{}", cpu::trace(&console.cpu))?,
+                Some("mem") | Some("m") => {
+                    let addr = parts.next().and_then(parse_addr);
+                    let len = parts.next().and_then(|l| l.parse().ok()).unwrap_or(16);
+                    match addr {
+                        Some(addr) => self.dump(&console.cpu, addr, len, output)?,
+                        None => writeln!(output, "usage: mem $addr [len]")?,
+                    }
+                }
+                Some("break") | Some("b") => match parts.next().and_then(parse_addr) {
+                    Some(addr) => self.add_breakpoint(addr),
+                    None => writeln!(output, "usage: break $addr")?,
+                },
+                Some(cmd) if cmd.starts_with("break-") => match line.parse::<Watchpoint>() {
+                    Ok(wp) => console.cpu.add_watchpoint(wp),
+                    Err(err) => writeln!(output, "{}", err)?,
+                },
+                Some("quit") | Some("q") => return Ok(false),
+                Some(cmd) => writeln!(output, "unknown command: {}", cmd)?,
+                None => {}
+            }
+        }
+    }
+
+    fn dump(&self, cpu: &CPU, addr: u16, len: u16, output: &mut impl Write) -> std::io::Result<()> {
+        for row in (0..len).step_by(16) {
+            let start = addr.wrapping_add(row);
+            write!(output, "${:04X}:", start)?;
+            for i in 0..16.min(len - row) {
+                write!(output, " {:02X}", self.peek(cpu, start.wrapping_add(i)))?;
+            }
+            writeln!(output)?;
+        }
+        Ok(())
+    }
+}
+
+// parses an address in hex, optionally prefixed with $ or 0x.
+fn parse_addr(s: &str) -> Option<u16> {
+    let s = s.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(s, 16).ok()
+}
+
+pub fn parse_breakpoint(s: &str) -> Result<u16, String> {
+    parse_addr(s).ok_or_else(|| format!("invalid breakpoint address: {}", s))
+}
+
+pub fn parse_entry(s: &str) -> Result<u16, String> {
+    parse_addr(s).ok_or_else(|| format!("invalid entry address: {}", s))
+}
+
+#[cfg(test)]
+fn test_console(program: &[u8]) -> Console {
+    Console::load_rom(&crate::cartridge::test_program(program)).unwrap()
+}
+
+#[test]
+fn test_breakpoints() {
+    let mut console = test_console(&[
+        0xE8, // INX
+        0xE8, // INX
+        0xE8, // INX
+        0x4C, 0x00, 0x80, // JMP $8000
+    ]);
+
+    let mut debugger = Debugger::default();
+    debugger.add_breakpoint(0x8002);
+    debugger.continue_until_break(&mut console);
+    assert_eq!(console.cpu.pc(), 0x8002);
+    assert!(cpu::trace(&console.cpu).contains("X:02"));
+
+    // continuing steps over the current breakpoint and stops at it on the next loop.
+    debugger.continue_until_break(&mut console);
+    assert_eq!(console.cpu.pc(), 0x8002);
+    assert!(cpu::trace(&console.cpu).contains("X:05"));
+
+    debugger.step(&mut console);
+    assert_eq!(console.cpu.pc(), 0x8003);
+}
+
+#[test]
+fn test_prompt() {
+    let mut console = test_console(&[
+        0xA9, 0x42, // LDA #$42
+        0x85, 0x10, // STA $10
+    ]);
+
+    let mut debugger = Debugger::default();
+    let mut input = "s\ns\nmem $0010 2\nbreak $8000\nc\n".as_bytes();
+    let mut output = vec![];
+    let resumed = debugger
+        .prompt(&mut console, &mut input, &mut output)
+        .unwrap();
+    assert!(resumed);
+    assert!(!debugger.should_break(&console.cpu));
+    assert_eq!(debugger.breakpoints, [0x8000]);
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.starts_with("$8000 LDA #$42\n> $8002 STA $10\n> $8004 BRK\n"));
+    assert!(output.contains("$0010: 42 00\n"));
+
+    let mut input = "q\n".as_bytes();
+    let quit = debugger.prompt(&mut console, &mut input, &mut vec![]);
+    assert!(!quit.unwrap());
+}
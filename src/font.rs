@@ -0,0 +1,88 @@
+// a tiny built-in bitmap font for the on-screen overlay, so no font files are needed. Glyphs are
+// 3x5 pixels and only cover the digits and the few letters the overlay uses.
+
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+
+// glyph returns the rows of a character, top to bottom, with the leftmost pixel in bit 2.
+// Characters without a glyph are blank.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'E' => [0b111, 0b100, 0b111, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b111, 0b100, 0b100],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'P' => [0b111, 0b101, 0b111, 0b100, 0b100],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+// pixels returns the x, y coordinates of the pixels lit when drawing text, with a column of space
+// between characters and a row of space between lines.
+pub fn pixels(text: &str) -> Vec<(usize, usize)> {
+    let mut out = vec![];
+    for (line_idx, line) in text.lines().enumerate() {
+        for (char_idx, c) in line.chars().enumerate() {
+            let (left, top) = (char_idx * (GLYPH_WIDTH + 1), line_idx * (GLYPH_HEIGHT + 1));
+            for (y, row) in glyph(c).iter().enumerate() {
+                for x in 0..GLYPH_WIDTH {
+                    if row >> (GLYPH_WIDTH - 1 - x) & 0x01 != 0 {
+                        out.push((left + x, top + y));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+// size returns the width and height of the text in pixels.
+pub fn size(text: &str) -> (usize, usize) {
+    let columns = text.lines().map(|line| line.chars().count()).max();
+    let lines = text.lines().count();
+    match columns {
+        Some(columns) if columns > 0 => (
+            columns * (GLYPH_WIDTH + 1) - 1,
+            lines * (GLYPH_HEIGHT + 1) - 1,
+        ),
+        _ => (0, 0),
+    }
+}
+
+#[test]
+fn test_glyphs() {
+    assert_eq!(glyph('7'), [0b111, 0b001, 0b001, 0b001, 0b001]);
+    assert_eq!(glyph('f'), glyph('F'));
+    assert_eq!(glyph('?'), [0; GLYPH_HEIGHT]);
+    // every digit has a glyph, and no two are alike.
+    let digits: Vec<_> = ('0'..='9').map(glyph).collect();
+    for (i, digit) in digits.iter().enumerate() {
+        assert!(digit.iter().any(|&row| row != 0));
+        assert!(!digits[i + 1..].contains(digit));
+    }
+
+    // "1" is drawn first, the "7" on the second line starts 6 pixels down.
+    let lit = pixels("1\n7");
+    assert_eq!(lit[..5], [(1, 0), (0, 1), (1, 1), (1, 2), (1, 3)]);
+    assert!(lit.contains(&(0, 6)));
+    assert!(lit.contains(&(2, 10)));
+    assert!(!lit.contains(&(0, 10)));
+    // the second character starts a glyph and a space to the right.
+    assert_eq!(pixels(" 1")[0], (5, 0));
+
+    assert_eq!(size("60.0 FPS\nFRAME 1"), (31, 11));
+    assert_eq!(size(""), (0, 0));
+}
@@ -0,0 +1,168 @@
+use sdl2::controller::Button;
+use shrimp::joypad::Joypad;
+use std::collections::HashMap;
+
+pub const PORTS: usize = 2;
+
+// PortAssignment decides which joypad port each connected gamepad drives. A gamepad whose GUID is
+// pinned to a port always takes that port, bumping any other gamepad using it. The rest fill the
+// free ports in connection order, and get their previous port back when they reconnect.
+#[derive(Debug, Default)]
+pub struct PortAssignment {
+    pinned: [Option<String>; PORTS],
+    // holds the instance id of the gamepad connected to each port.
+    ports: [Option<u32>; PORTS],
+    last_port: HashMap<String, usize>,
+}
+
+// Connection is the outcome of connecting a gamepad.
+#[derive(Debug, PartialEq)]
+pub struct Connection {
+    pub port: Option<usize>,
+    // bumped is the gamepad that was using a pinned port when its gamepad connected.
+    pub bumped: Option<u32>,
+}
+
+impl PortAssignment {
+    pub fn new(pinned: [Option<String>; PORTS]) -> Self {
+        PortAssignment {
+            pinned,
+            ..Default::default()
+        }
+    }
+
+    // connect assigns a port to a newly connected gamepad, port is None if there are no free ports
+    // for it. A pinned gamepad taking its port returns the gamepad it bumped, which moves to a free
+    // port if there is one and is left without a port otherwise.
+    pub fn connect(&mut self, id: u32, guid: &str) -> Connection {
+        if let Some(port) = self.pinned.iter().position(|p| p.as_deref() == Some(guid)) {
+            let bumped = self.ports[port].replace(id);
+            if let (Some(bumped), Some(free)) = (bumped, self.free_port(None)) {
+                self.ports[free] = Some(bumped);
+            }
+            return Connection {
+                port: Some(port),
+                bumped,
+            };
+        }
+
+        let preferred = self.last_port.get(guid).cloned();
+        let port = self.free_port(preferred);
+        if let Some(port) = port {
+            self.ports[port] = Some(id);
+            self.last_port.insert(guid.to_string(), port);
+        }
+        Connection { port, bumped: None }
+    }
+
+    pub fn disconnect(&mut self, id: u32) {
+        for port in self.ports.iter_mut() {
+            if *port == Some(id) {
+                *port = None;
+            }
+        }
+    }
+
+    pub fn port(&self, id: u32) -> Option<usize> {
+        self.ports.iter().position(|p| *p == Some(id))
+    }
+
+    // returns the preferred port if it is free, otherwise the first free port. Ports pinned to a
+    // gamepad are only used if there is nothing else available.
+    fn free_port(&self, preferred: Option<usize>) -> Option<usize> {
+        if let Some(port) = preferred {
+            if self.ports[port].is_none() {
+                return Some(port);
+            }
+        }
+
+        let is_free = |port: &usize| self.ports[*port].is_none();
+        (0..PORTS)
+            .filter(is_free)
+            .find(|port| self.pinned[*port].is_none())
+            .or_else(|| (0..PORTS).find(is_free))
+    }
+}
+
+// set_button maps a standard gamepad to the joypad, both A and Y press A, and both B and X press
+// B, so either pair of face buttons can be used.
+pub fn set_button(joypad: &mut Joypad, button: Button, pressed: bool) {
+    match button {
+        Button::Start => joypad.start = pressed,
+        Button::Back => joypad.select = pressed,
+        Button::A | Button::Y => joypad.a = pressed,
+        Button::B | Button::X => joypad.b = pressed,
+        Button::DPadUp => joypad.up = pressed,
+        Button::DPadDown => joypad.down = pressed,
+        Button::DPadLeft => joypad.left = pressed,
+        Button::DPadRight => joypad.right = pressed,
+        _ => {}
+    }
+}
+
+#[test]
+fn test_port_assignment() {
+    let pinned = "03000000de280000ff11000001000000".to_string();
+    let port = |port| Connection { port, bumped: None };
+
+    // gamepads without a pinned port are assigned in connection order.
+    let mut assignment = PortAssignment::new([None, None]);
+    assert_eq!(assignment.connect(1, "a"), port(Some(0)));
+    assert_eq!(assignment.connect(2, "b"), port(Some(1)));
+    assert_eq!(assignment.connect(3, "c"), port(None));
+
+    // reconnecting gets the same port back.
+    assignment.disconnect(1);
+    assignment.disconnect(2);
+    assert_eq!(assignment.port(1), None);
+    assert_eq!(assignment.connect(4, "b"), port(Some(1)));
+    assert_eq!(assignment.connect(5, "a"), port(Some(0)));
+
+    // a pinned gamepad always takes its port, even if it connects last.
+    let mut assignment = PortAssignment::new([Some(pinned.clone()), None]);
+    assert_eq!(assignment.connect(1, "a"), port(Some(1)));
+    assert_eq!(assignment.connect(2, &pinned), port(Some(0)));
+
+    // and bumps whatever gamepad was using it to a free port.
+    let mut assignment = PortAssignment::new([Some(pinned.clone()), None]);
+    assert_eq!(assignment.connect(1, "a"), port(Some(1)));
+    assert_eq!(assignment.connect(2, "b"), port(Some(0)));
+    assignment.disconnect(1);
+    assert_eq!(
+        assignment.connect(3, &pinned),
+        Connection {
+            port: Some(0),
+            bumped: Some(2),
+        }
+    );
+    assert_eq!(assignment.port(2), Some(1));
+
+    // or out, when all the ports are taken.
+    let mut assignment = PortAssignment::new([Some(pinned.clone()), None]);
+    assert_eq!(assignment.connect(1, "a"), port(Some(1)));
+    assert_eq!(assignment.connect(2, "b"), port(Some(0)));
+    assert_eq!(
+        assignment.connect(3, &pinned),
+        Connection {
+            port: Some(0),
+            bumped: Some(2),
+        }
+    );
+    assert_eq!(assignment.port(2), None);
+    assert_eq!(assignment.port(1), Some(1));
+}
+
+#[test]
+fn test_set_button() {
+    let mut joypad = Joypad::default();
+    set_button(&mut joypad, Button::Y, true);
+    set_button(&mut joypad, Button::X, true);
+    set_button(&mut joypad, Button::Back, true);
+    set_button(&mut joypad, Button::DPadLeft, true);
+    assert!(joypad.a && joypad.b && joypad.select && joypad.left);
+    assert!(!joypad.start && !joypad.right);
+
+    set_button(&mut joypad, Button::Y, false);
+    set_button(&mut joypad, Button::LeftShoulder, true);
+    assert!(!joypad.a);
+}
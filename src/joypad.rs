@@ -6,6 +6,100 @@ const UP: u8 = 4;
 const DOWN: u8 = 5;
 const LEFT: u8 = 6;
 const RIGHT: u8 = 7;
+// the Four Score reports these after the buttons of both controllers on each port, most
+// significant bit first.
+const FOUR_SCORE_SIGNATURES: [u8; 2] = [0x10, 0x20];
+// turbo buttons are held for this many frames and then released for as many, which pulses them at
+// 15Hz.
+const TURBO_PERIOD: u8 = 2;
+
+use std::str::FromStr;
+
+// Player selects one of the controllers, players three and four are only connected through a
+// Four Score.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Player {
+    One,
+    Two,
+    Three,
+    Four,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+    Microphone,
+    TurboA,
+    TurboB,
+}
+
+impl Button {
+    pub fn set(self, joypad: &mut Joypad, pressed: bool) {
+        match self {
+            Button::A => joypad.a = pressed,
+            Button::B => joypad.b = pressed,
+            Button::Select => joypad.select = pressed,
+            Button::Start => joypad.start = pressed,
+            Button::Up => joypad.up = pressed,
+            Button::Down => joypad.down = pressed,
+            Button::Left => joypad.left = pressed,
+            Button::Right => joypad.right = pressed,
+            Button::Microphone => joypad.microphone = pressed,
+            Button::TurboA => joypad.turbo_a = pressed,
+            Button::TurboB => joypad.turbo_b = pressed,
+        }
+    }
+}
+
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "a" => Ok(Button::A),
+            "b" => Ok(Button::B),
+            "select" => Ok(Button::Select),
+            "start" => Ok(Button::Start),
+            "up" => Ok(Button::Up),
+            "down" => Ok(Button::Down),
+            "left" => Ok(Button::Left),
+            "right" => Ok(Button::Right),
+            "microphone" => Ok(Button::Microphone),
+            "turbo_a" => Ok(Button::TurboA),
+            "turbo_b" => Ok(Button::TurboB),
+            _ => Err(format!("unknown button: {}", s)),
+        }
+    }
+}
+
+// ControllerType selects what the controller reports once all 8 buttons have been read.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ControllerType {
+    // official Nintendo brand controllers report 1.
+    #[default]
+    Official,
+    // third party controllers such as the U-Force report 0.
+    Clone,
+}
+
+impl FromStr for ControllerType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "official" => Ok(ControllerType::Official),
+            "clone" => Ok(ControllerType::Clone),
+            _ => Err(format!("unknown controller type: {}", s)),
+        }
+    }
+}
 
 // See https://wiki.nesdev.com/w/index.php/Standard_controller for more information on how the NES
 // joypad behaves.
@@ -19,8 +113,20 @@ pub struct Joypad {
     pub right: bool,
     pub start: bool,
     pub select: bool,
+    // only present on the second controller of a Famicom, it is read through $4016 rather than
+    // through the controller's own port.
+    pub microphone: bool,
+    // turbo_a and turbo_b press A and B on and off for as long as they are held.
+    pub turbo_a: bool,
+    pub turbo_b: bool,
+    pub controller_type: ControllerType,
 
     strobe: u8,
+    // while the strobe bit of $4016 is held high the shift register keeps reloading, so every
+    // read reports the A button.
+    strobe_high: bool,
+    // counts the frames for the turbo buttons.
+    turbo_frame: u8,
 }
 
 impl Joypad {
@@ -30,22 +136,83 @@ impl Joypad {
         }
     }
 
+    // set_buttons presses the buttons set in a byte laid out like the joypad reports them, A in
+    // bit 0 through Right in bit 7.
+    pub fn set_buttons(&mut self, buttons: u8) {
+        let pressed = |bit: u8| buttons & (1 << bit) != 0;
+        self.a = pressed(A);
+        self.b = pressed(B);
+        self.select = pressed(SELECT);
+        self.start = pressed(START);
+        self.up = pressed(UP);
+        self.down = pressed(DOWN);
+        self.left = pressed(LEFT);
+        self.right = pressed(RIGHT);
+    }
+
+    // buttons returns the buttons pressed in the same layout set_buttons takes, with the turbo
+    // buttons pressing A and B on the frames they pulse.
+    pub fn buttons(&self) -> u8 {
+        let pressed = [
+            (A, self.a_pressed()),
+            (B, self.b_pressed()),
+            (SELECT, self.select),
+            (START, self.start),
+            (UP, self.up),
+            (DOWN, self.down),
+            (LEFT, self.left),
+            (RIGHT, self.right),
+        ];
+        pressed
+            .iter()
+            .filter(|(_, pressed)| *pressed)
+            .fold(0, |buttons, (bit, _)| buttons | 1 << bit)
+    }
+
+    // next_frame advances the turbo pulse, it is called once per frame.
+    pub fn next_frame(&mut self) {
+        self.turbo_frame = (self.turbo_frame + 1) % (TURBO_PERIOD * 2);
+    }
+
+    fn turbo_pulse(&self) -> bool {
+        self.turbo_frame < TURBO_PERIOD
+    }
+
+    fn a_pressed(&self) -> bool {
+        self.a || (self.turbo_a && self.turbo_pulse())
+    }
+
+    fn b_pressed(&self) -> bool {
+        self.b || (self.turbo_b && self.turbo_pulse())
+    }
+
     pub fn reset(&mut self) {
         self.strobe = 0;
     }
 
+    pub fn set_strobe(&mut self, high: bool) {
+        self.strobe_high = high;
+        if high {
+            self.reset();
+        }
+    }
+
     pub fn state(&mut self) -> bool {
         // Each read reports one bit at a time through D0. The first 8 reads will indicate which
         // buttons or directions are pressed (1 if pressed, 0 if not pressed). All subsequent reads
         // will return 1 on official Nintendo brand controllers but may return 0 on third party
         // controllers such as the U-Force.
+        if self.strobe_high {
+            return self.a_pressed();
+        }
+
         if self.strobe == 8 {
-            return true;
+            return self.controller_type == ControllerType::Official;
         }
 
         let val = match self.strobe {
-            A => self.a,
-            B => self.b,
+            A => self.a_pressed(),
+            B => self.b_pressed(),
             START => self.start,
             SELECT => self.select,
             UP => self.up,
@@ -59,3 +226,129 @@ impl Joypad {
         val
     }
 }
+
+// FourScore is the four player adapter. Each port reports the buttons of its first controller,
+// then the buttons of its second one, then a signature identifying the port. See
+// https://wiki.nesdev.com/w/index.php/Four_player_adapters.
+#[derive(Debug, Default)]
+pub struct FourScore {
+    // the reads since the last strobe on each port.
+    reads: [u8; 2],
+    strobe_high: bool,
+}
+
+impl FourScore {
+    pub fn set_strobe(&mut self, high: bool) {
+        self.strobe_high = high;
+        if high {
+            self.reads = [0; 2];
+        }
+    }
+
+    // state reports the next bit of a port, 0 being $4016 and 1 being $4017, where first and
+    // second are the controllers plugged into it. All 24 bits read, it reports 1s.
+    pub fn state(&mut self, port: usize, first: &mut Joypad, second: &mut Joypad) -> bool {
+        if self.strobe_high {
+            return first.state();
+        }
+
+        let read = self.reads[port];
+        self.reads[port] = (read + 1).min(24);
+        match read {
+            0..=7 => first.state(),
+            8..=15 => second.state(),
+            16..=23 => FOUR_SCORE_SIGNATURES[port] & (0x80 >> (read - 16)) != 0,
+            _ => true,
+        }
+    }
+}
+
+#[test]
+fn test_reads_past_the_eighth() {
+    let mut joypad = Joypad::default();
+    for _ in 0..8 {
+        assert!(!joypad.state());
+    }
+    assert!(joypad.state());
+
+    let mut joypad = Joypad {
+        controller_type: ControllerType::Clone,
+        ..Default::default()
+    };
+    for _ in 0..8 {
+        assert!(!joypad.state());
+    }
+    assert!(!joypad.state());
+}
+
+#[test]
+fn test_turbo() {
+    let mut joypad = Joypad {
+        turbo_a: true,
+        ..Default::default()
+    };
+
+    let mut reported = vec![];
+    for _ in 0..8 {
+        joypad.set_strobe(true);
+        joypad.set_strobe(false);
+        reported.push(joypad.state());
+        // B is not pulsed.
+        assert!(!joypad.state());
+        joypad.next_frame();
+    }
+    assert_eq!(
+        reported,
+        [true, true, false, false, true, true, false, false]
+    );
+
+    // holding A as well keeps it pressed.
+    joypad.a = true;
+    for _ in 0..4 {
+        joypad.set_strobe(true);
+        joypad.set_strobe(false);
+        assert!(joypad.state());
+        joypad.next_frame();
+    }
+}
+
+#[test]
+fn test_buttons() {
+    let mut joypad = Joypad::default();
+    joypad.set_buttons(0x99);
+    assert!(joypad.a && joypad.start && joypad.up && joypad.right);
+    assert!(!joypad.b && !joypad.select);
+    assert_eq!(joypad.buttons(), 0x99);
+
+    joypad.set_buttons(0);
+    joypad.turbo_b = true;
+    assert_eq!(joypad.buttons(), 0x02);
+}
+
+#[test]
+fn test_four_score() {
+    let mut four_score = FourScore::default();
+    let mut joypads: Vec<Joypad> = (0..4).map(|_| Joypad::default()).collect();
+    joypads[0].a = true;
+    joypads[1].b = true;
+    joypads[2].start = true;
+    joypads[3].right = true;
+    for joypad in joypads.iter_mut() {
+        joypad.set_strobe(true);
+        joypad.set_strobe(false);
+    }
+    four_score.set_strobe(true);
+    four_score.set_strobe(false);
+
+    // reads the 24 bits of a port into a number, first bit read in the most significant bit.
+    let mut read = |port: usize, joypads: &mut [Joypad]| {
+        let (first, second) = joypads.split_at_mut(2);
+        (0..24).fold(0u32, |bits, _| {
+            let bit = four_score.state(port, &mut first[port], &mut second[port]);
+            bits << 1 | bit as u32
+        })
+    };
+    // players one and three are on $4016, two and four on $4017.
+    assert_eq!(read(0, &mut joypads), 0x80_10_10);
+    assert_eq!(read(1, &mut joypads), 0x40_01_20);
+}
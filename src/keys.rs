@@ -0,0 +1,242 @@
+use sdl2::keyboard::Keycode;
+use shrimp::joypad::{Button, Player};
+use std::collections::HashMap;
+use std::path::Path;
+
+// the file key bindings are read from when --keys is not given.
+const DEFAULT_PATH: &str = "keys.toml";
+
+// KeyBindings maps keyboard keys to the joypad button they press.
+pub type KeyBindings = HashMap<Keycode, (Player, Button)>;
+
+// RESERVED are the keys the frontend handles itself, which can't be bound to a button. 1 to 9 set
+// the emulation speed.
+const RESERVED: [Keycode; 14] = [
+    Keycode::Escape,
+    Keycode::Tab,
+    Keycode::M,
+    Keycode::P,
+    Keycode::Period,
+    Keycode::Num1,
+    Keycode::Num2,
+    Keycode::Num3,
+    Keycode::Num4,
+    Keycode::Num5,
+    Keycode::Num6,
+    Keycode::Num7,
+    Keycode::Num8,
+    Keycode::Num9,
+];
+
+// default_bindings binds the first controller to the left of the keyboard and the second to the
+// right of it. The microphone used to be on M, it moved to O when M became the mute key.
+pub fn default_bindings() -> KeyBindings {
+    use Button::*;
+    use Player::*;
+
+    vec![
+        (Keycode::R, (One, Start)),
+        (Keycode::LShift, (One, Select)),
+        (Keycode::V, (One, A)),
+        (Keycode::C, (One, B)),
+        (Keycode::W, (One, Up)),
+        (Keycode::S, (One, Down)),
+        (Keycode::A, (One, Left)),
+        (Keycode::D, (One, Right)),
+        (Keycode::G, (One, TurboA)),
+        (Keycode::F, (One, TurboB)),
+        (Keycode::U, (Two, Start)),
+        (Keycode::RShift, (Two, Select)),
+        (Keycode::N, (Two, A)),
+        (Keycode::B, (Two, B)),
+        (Keycode::I, (Two, Up)),
+        (Keycode::K, (Two, Down)),
+        (Keycode::J, (Two, Left)),
+        (Keycode::L, (Two, Right)),
+        (Keycode::O, (Two, Microphone)),
+    ]
+    .into_iter()
+    .collect()
+}
+
+// load reads the key bindings from the given file, or from keys.toml if there is one. The
+// defaults are used for every button the file doesn't bind.
+pub fn load(path: Option<&str>) -> Result<KeyBindings, Box<dyn std::error::Error>> {
+    let config = match path {
+        Some(path) => std::fs::read_to_string(path)?,
+        None if Path::new(DEFAULT_PATH).exists() => std::fs::read_to_string(DEFAULT_PATH)?,
+        None => return Ok(default_bindings()),
+    };
+    Ok(parse(&config)?)
+}
+
+// parse reads key bindings from a TOML file with a table per player, where every button is bound
+// to a key name, e.g.
+//
+//   [player1]
+//   a = "V"
+//   start = 'Return'  # a comment
+//
+// Only that much of TOML is understood: the [player1] to [player4] tables, and buttons set to
+// basic ("...") or literal ('...') strings, without escapes. A # outside a string starts a comment
+// that runs to the end of the line.
+pub fn parse(config: &str) -> Result<KeyBindings, String> {
+    let mut bindings = default_bindings();
+    let mut player = None;
+    for line in config.lines() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            player = match line {
+                "[player1]" => Some(Player::One),
+                "[player2]" => Some(Player::Two),
+                "[player3]" => Some(Player::Three),
+                "[player4]" => Some(Player::Four),
+                _ => return Err(format!("unknown table: {}", line)),
+            };
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=').map(str::trim);
+        let button: Button = parts.next().unwrap_or("").parse()?;
+        let name = parts.next().and_then(unquote).unwrap_or("");
+        let key = keycode(name).ok_or_else(|| format!("invalid binding: {}", line))?;
+        if RESERVED.contains(&key) {
+            return Err(format!("{} is reserved and can't be bound: {}", name, line));
+        }
+        let player = player.ok_or_else(|| format!("binding outside a player table: {}", line))?;
+
+        bindings.retain(|_, binding| *binding != (player, button));
+        bindings.insert(key, (player, button));
+    }
+
+    Ok(bindings)
+}
+
+// strip_comment returns the line up to the # that starts its comment, if any. A # inside a string
+// is part of it.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match quote {
+            None if c == '#' => return &line[..i],
+            None if c == '"' || c == '\'' => quote = Some(c),
+            Some(q) if c == q => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+// unquote returns the contents of a basic or literal string.
+fn unquote(value: &str) -> Option<&str> {
+    let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    value[1..]
+        .strip_suffix(quote)
+        .filter(|s| !s.contains(quote))
+}
+
+// keycode returns the key with the given name. Keys that type a character are named by it, the
+// rest by their name in SDL's Keycode enum.
+fn keycode(name: &str) -> Option<Keycode> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_graphic() {
+            return Keycode::from_i32(c.to_ascii_lowercase() as i32);
+        }
+    }
+
+    let key = match name {
+        "Return" => Keycode::Return,
+        "Escape" => Keycode::Escape,
+        "Backspace" => Keycode::Backspace,
+        "Tab" => Keycode::Tab,
+        "Space" => Keycode::Space,
+        "Up" => Keycode::Up,
+        "Down" => Keycode::Down,
+        "Left" => Keycode::Left,
+        "Right" => Keycode::Right,
+        "LShift" => Keycode::LShift,
+        "RShift" => Keycode::RShift,
+        "LCtrl" => Keycode::LCtrl,
+        "RCtrl" => Keycode::RCtrl,
+        "LAlt" => Keycode::LAlt,
+        "RAlt" => Keycode::RAlt,
+        "Kp0" => Keycode::Kp0,
+        "Kp1" => Keycode::Kp1,
+        "Kp2" => Keycode::Kp2,
+        "Kp3" => Keycode::Kp3,
+        "Kp4" => Keycode::Kp4,
+        "Kp5" => Keycode::Kp5,
+        "Kp6" => Keycode::Kp6,
+        "Kp7" => Keycode::Kp7,
+        "Kp8" => Keycode::Kp8,
+        "Kp9" => Keycode::Kp9,
+        "KpEnter" => Keycode::KpEnter,
+        _ => return None,
+    };
+    Some(key)
+}
+
+#[test]
+fn test_parse_bindings() {
+    let config = r#"
+        # arrows and Z/X for the first player
+        [player1]
+        a = "X"
+        b = "z"
+        up = "Up"
+        start = "Return"
+
+        [player2]
+        select = "Kp0"
+    "#;
+
+    let bindings = parse(config).unwrap();
+    assert_eq!(bindings.get(&Keycode::X), Some(&(Player::One, Button::A)));
+    assert_eq!(bindings.get(&Keycode::Z), Some(&(Player::One, Button::B)));
+    assert_eq!(bindings.get(&Keycode::Up), Some(&(Player::One, Button::Up)));
+    assert_eq!(
+        bindings.get(&Keycode::Kp0),
+        Some(&(Player::Two, Button::Select))
+    );
+    // rebound buttons lose their default key, the rest keep it.
+    assert_eq!(bindings.get(&Keycode::V), None);
+    assert_eq!(bindings.get(&Keycode::R), None);
+    assert_eq!(
+        bindings.get(&Keycode::D),
+        Some(&(Player::One, Button::Right))
+    );
+
+    // quoted #s are keys, not comments, and literal strings work too.
+    let bindings = parse("[player1]\na = \"#\" # fire\nb = '\"'").unwrap();
+    assert_eq!(
+        bindings.get(&Keycode::Hash),
+        Some(&(Player::One, Button::A))
+    );
+    assert_eq!(
+        bindings.get(&Keycode::Quotedbl),
+        Some(&(Player::One, Button::B))
+    );
+
+    assert_eq!(
+        parse("[player2]\nmicrophone = \"M\""),
+        Err("M is reserved and can't be bound: microphone = \"M\"".to_string())
+    );
+    assert!(parse("[player1]\nselect = \"Tab\"").is_err());
+    assert!(parse("[player1]\nstart = \"p\"").is_err());
+    assert!(parse("[player1]\na = \".\"").is_err());
+    assert_eq!(
+        parse("[player1]\nb = \"5\""),
+        Err("5 is reserved and can't be bound: b = \"5\"".to_string())
+    );
+
+    assert!(parse("a = \"X\"").is_err());
+    assert!(parse("[player1]\na = X").is_err());
+    assert!(parse("[player1]\na = \"X'").is_err());
+    assert!(parse("[player1]\nturbo = \"X\"").is_err());
+    assert!(parse("[player1]\na = \"NotAKey\"").is_err());
+}
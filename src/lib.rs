@@ -0,0 +1,15 @@
+// shrimp is a NES emulator. The library is the emulator core, it has no dependency on SDL so it
+// can be driven by any frontend, the shrimp binary is the SDL frontend built on top of it.
+pub mod apu;
+pub mod cartridge;
+pub mod console;
+pub mod cpu;
+pub mod debugger;
+pub mod joypad;
+pub mod md5;
+pub mod movie;
+pub mod patch;
+pub mod png;
+pub mod ppu;
+pub mod selftest;
+pub mod zapper;
@@ -1,22 +1,172 @@
-mod cartridge;
-mod cpu;
-mod joypad;
+mod font;
+mod gamepad;
+mod keys;
 mod nes;
-mod ppu;
 
 use nes::NES;
+use shrimp::cartridge::Cartridge;
+use shrimp::cpu::{GenieCode, RamInit, Watchpoint};
+use shrimp::debugger;
+use shrimp::joypad::ControllerType;
+use shrimp::png;
+use shrimp::ppu::filter::Filter;
+use shrimp::ppu::viewer;
+use shrimp::ppu::{PixelFormat, Region};
+use shrimp::selftest;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
 pub struct Options {
-    #[structopt(short = "r", long)]
-    rom: String,
+    #[structopt(short = "r", long, required_unless = "selftest")]
+    rom: Option<String>,
     #[structopt(short = "s", long, default_value = "1")]
     scale: u8,
+    // the format of the texture the screen is drawn into, either bgr24 or rgb24.
+    #[structopt(long, default_value = "bgr24")]
+    pixel_format: PixelFormat,
+    // a .pal file with the 64 colors of the palette, replacing the built-in one.
+    #[structopt(long)]
+    palette: Option<String>,
+    // the video timing to emulate, either ntsc or pal.
+    #[structopt(long, default_value = "ntsc")]
+    region: Region,
+    // what RAM holds at power-on: zero, random, or seed:N for the same pseudo-random bytes on
+    // every run.
+    #[structopt(long, default_value = "zero")]
+    ram_init: RamInit,
+    // the master volume, from 0.0 to 1.0. M toggles mute.
+    #[structopt(long, default_value = "1.0")]
+    volume: f32,
+    // prints the instructions and PPU dots run per frame, the frame time and the FPS, averaged
+    // over each second.
+    #[structopt(long)]
+    stats: bool,
+    // swaps the red and blue channels of every pixel.
+    #[structopt(long)]
+    swap_rb: bool,
+    // emulates a Famicom rather than a NES, enabling the microphone on the second controller.
+    #[structopt(long)]
+    famicom: bool,
+    // what the controllers report after the 8th read, either official or clone.
+    #[structopt(long, default_value = "official")]
+    controller_type: ControllerType,
+    // applies a RAM patch, as saved with F2, on startup. Patches only hold what changed since
+    // power-on, so they must be applied with the same --ram-init they were saved with.
+    #[structopt(long)]
+    patch: Option<String>,
+    // halts emulation on the first access to an address, e.g. --break 'break-write $2006'.
+    #[structopt(long = "break")]
+    watchpoints: Vec<Watchpoint>,
+    // the GUID of the gamepad that should always drive joypad 1.
+    #[structopt(long)]
+    pad1: Option<String>,
+    // the GUID of the gamepad that should always drive joypad 2.
+    #[structopt(long)]
+    pad2: Option<String>,
+    // paces frames with the display's vertical sync instead of sleeping.
+    #[structopt(long)]
+    vsync: bool,
+    // starts in desktop fullscreen, F11 toggles it.
+    #[structopt(long)]
+    fullscreen: bool,
+    // plugs in a Four Score, players three and four are bound in the [player3] and [player4]
+    // tables of the key bindings.
+    #[structopt(long)]
+    fourscore: bool,
+    // plugs a Zapper into the second port, aimed with the mouse and fired with its left button.
+    #[structopt(long)]
+    zapper: bool,
+    // the video filter the frames go through, either none or ntsc.
+    #[structopt(long, default_value = "none")]
+    filter: Filter,
+    // replays the input log of an FCEUX .fm2 movie, live input takes over once it ends.
+    #[structopt(long)]
+    movie: Option<String>,
+    // records the input of every frame to an FCEUX .fm2 movie.
+    #[structopt(long)]
+    record: Option<String>,
+    // opens a second window showing the pattern tables and nametables.
+    #[structopt(long)]
+    debug_gfx: bool,
+    // a TOML file with the key bindings, keys.toml is used if it exists.
+    #[structopt(long)]
+    keys: Option<String>,
+    // writes a log of every instruction executed to the given file, in Nintendulator's format.
+    #[structopt(long)]
+    trace: Option<String>,
+    // pauses before the instruction at the given address and opens the debugger prompt, e.g.
+    // --breakpoint '$C000'.
+    #[structopt(long = "breakpoint", parse(try_from_str = debugger::parse_breakpoint))]
+    breakpoints: Vec<u16>,
+    // Game Genie codes patching the game's ROM, separated by commas, e.g. --genie SXIOPO.
+    #[structopt(long, use_delimiter = true)]
+    genie: Vec<GenieCode>,
+    // starts running at the given address instead of the one in the reset vector, e.g.
+    // --entry '$C000' runs nestest in its automated mode.
+    #[structopt(long, parse(try_from_str = debugger::parse_entry))]
+    entry: Option<u16>,
+    // runs the ROM without a window for the number of frames given with --frames, then prints the
+    // test status test ROMs write to $6000.
+    #[structopt(long)]
+    headless: bool,
+    #[structopt(long, default_value = "600")]
+    frames: u64,
+    // collects the text test ROMs write from $6004 on and prints it when emulation stops.
+    #[structopt(long)]
+    test_output: bool,
+    // the most verbose messages logged to stderr: off, error, warn, info, debug or trace.
+    #[structopt(long, default_value = "warn")]
+    log_level: log::LevelFilter,
+    // prints the ROM's header instead of running the emulator.
+    #[structopt(long)]
+    info: bool,
+    // writes every tile of the cartridge's CHR ROM to the given PNG file in grayscale, instead of
+    // running the emulator.
+    #[structopt(long)]
+    dump_chr: Option<String>,
+    // runs the bundled accuracy tests headless and prints a pass/fail summary.
+    #[structopt(long)]
+    selftest: bool,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opts = Options::from_args();
-    let mut nes = NES::new(opts);
-    nes.run()
+    env_logger::Builder::new()
+        .filter_level(opts.log_level)
+        .init();
+    if opts.selftest {
+        if !selftest::run_all() {
+            return Err("some self tests failed".into());
+        }
+        return Ok(());
+    }
+
+    if opts.info {
+        let rom = opts.rom.ok_or("no ROM given")?;
+        println!("{}", Cartridge::info(&std::fs::read(rom)?)?);
+        return Ok(());
+    }
+
+    if let Some(path) = opts.dump_chr {
+        let rom = opts.rom.ok_or("no ROM given")?;
+        let cartridge = Cartridge::from_path(rom)?;
+        let (width, height, rgb) = viewer::render_chr(&cartridge);
+        std::fs::write(path, png::encode(width, height, &rgb))?;
+        return Ok(());
+    }
+
+    let headless = opts.headless;
+    let frames = opts.frames;
+    let mut nes = NES::new(opts)?;
+    if headless {
+        nes.run_headless(frames)?;
+        println!("test status: ${:02X}", nes.test_status());
+    } else {
+        nes.run()?;
+    }
+
+    if let Some(output) = nes.test_output() {
+        println!("{}", output);
+    }
+    Ok(())
 }
@@ -0,0 +1,220 @@
+use std::io::Write;
+use std::str::FromStr;
+
+// the soft reset bit of an input line's commands.
+const RESET: u8 = 0x01;
+
+// Frame is a line of a movie's input log.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Frame {
+    // reset presses the reset button before the frame.
+    pub reset: bool,
+    // the buttons of both controllers, one bit per button in the order the joypad reports them.
+    pub pads: [u8; 2],
+}
+
+impl Frame {
+    // writes the frame as an input line, e.g. "|0|RLDUTSBA|........||".
+    fn write(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let commands = if self.reset { RESET } else { 0 };
+        write!(out, "|{}", commands)?;
+        for pad in &self.pads {
+            let buttons: String = "RLDUTSBA"
+                .chars()
+                .enumerate()
+                .map(|(i, c)| if pad & (0x80 >> i) != 0 { c } else { '.' })
+                .collect();
+            write!(out, "|{}", buttons)?;
+        }
+        writeln!(out, "||")
+    }
+}
+
+// Movie is an input log in FCEUX's FM2 format, holding the buttons held on both controllers for
+// every frame. Only the input log is read, the header lines are skipped. See
+// https://fceux.com/web/help/fm2.html.
+#[derive(Debug, Default, PartialEq)]
+pub struct Movie {
+    frames: Vec<Frame>,
+    position: usize,
+}
+
+impl Movie {
+    // next_frame returns the input for the next frame, or None once the movie is over.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        let frame = self.frames.get(self.position).copied();
+        self.position += 1;
+        frame
+    }
+}
+
+// Recorder writes an FM2 movie of the input of every frame, starting from power on.
+pub struct Recorder {
+    out: Box<dyn Write>,
+}
+
+impl Recorder {
+    // new writes the movie's header, rom_md5 identifies the ROM the movie was recorded with.
+    pub fn new(
+        mut out: Box<dyn Write>,
+        rom_name: &str,
+        rom_md5: [u8; 16],
+    ) -> std::io::Result<Self> {
+        writeln!(out, "version 3")?;
+        writeln!(out, "emuVersion 22020")?;
+        writeln!(out, "rerecordCount 0")?;
+        writeln!(out, "palFlag 0")?;
+        writeln!(out, "romFilename {}", rom_name)?;
+        writeln!(out, "romChecksum base64:{}", base64(&rom_md5))?;
+        writeln!(out, "guid 00000000-0000-0000-0000-000000000000")?;
+        writeln!(out, "fourscore 0")?;
+        writeln!(out, "microphone 0")?;
+        writeln!(out, "port0 1")?;
+        writeln!(out, "port1 1")?;
+        writeln!(out, "port2 0")?;
+        writeln!(out, "FDS 0")?;
+        writeln!(out, "NewPPU 0")?;
+        Ok(Recorder { out })
+    }
+
+    pub fn record(&mut self, frame: Frame) -> std::io::Result<()> {
+        frame.write(&mut self.out)
+    }
+}
+
+// encodes data in base64, as used for the ROM checksum in the header.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+impl FromStr for Movie {
+    type Err = String;
+
+    // input lines look like "|0|RLDUTSBA|........||", with the commands, both controllers and
+    // the expansion port separated by pipes. Any character other than a space or a dot is a
+    // pressed button.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut frames = vec![];
+        for line in s.lines().map(str::trim).filter(|l| l.starts_with('|')) {
+            let fields: Vec<&str> = line.split('|').collect();
+            if fields.len() < 4 {
+                return Err(format!("invalid input line: {}", line));
+            }
+
+            let commands: u8 = fields[1]
+                .parse()
+                .map_err(|_| format!("invalid commands: {}", line))?;
+            let mut frame = Frame {
+                reset: commands & RESET != 0,
+                pads: [0; 2],
+            };
+            for (pad, field) in frame.pads.iter_mut().zip(&fields[2..4]) {
+                *pad = parse_buttons(field).ok_or_else(|| format!("invalid input: {}", line))?;
+            }
+            frames.push(frame);
+        }
+
+        Ok(Movie {
+            frames,
+            position: 0,
+        })
+    }
+}
+
+// parses a controller's buttons, given in the order RLDUTSBA. Unconnected controllers have no
+// buttons.
+fn parse_buttons(field: &str) -> Option<u8> {
+    if field.is_empty() {
+        return Some(0);
+    }
+    if field.len() != 8 {
+        return None;
+    }
+
+    let mut buttons = 0;
+    for (i, c) in field.chars().enumerate() {
+        if c != '.' && c != ' ' {
+            buttons |= 0x80 >> i;
+        }
+    }
+    Some(buttons)
+}
+
+#[test]
+fn test_parse_movie() {
+    let fm2 = "version 3
+emuVersion 22020
+port0 1
+port1 1
+port2 0
+|0|........|........||
+|0|.......A|R.......||
+|1|...UT...|......B.||
+";
+    let pads = |movie: &mut Movie| movie.next_frame().map(|f| f.pads);
+    let mut movie: Movie = fm2.parse().unwrap();
+    assert_eq!(pads(&mut movie), Some([0x00, 0x00]));
+    assert_eq!(pads(&mut movie), Some([0x01, 0x80]));
+    assert!(movie.frames[2].reset);
+    assert_eq!(pads(&mut movie), Some([0x18, 0x02]));
+    assert_eq!(pads(&mut movie), None);
+
+    assert!("|0|ABC|........||".parse::<Movie>().is_err());
+    assert!("|0|".parse::<Movie>().is_err());
+}
+
+#[test]
+fn test_record_movie() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // a writer the test can read back after handing it to the recorder.
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let frames = [
+        Frame::default(),
+        Frame {
+            reset: false,
+            pads: [0x09, 0x00],
+        },
+        Frame {
+            reset: true,
+            pads: [0xF0, 0x42],
+        },
+    ];
+    let buffer = Buffer::default();
+    let mut recorder = Recorder::new(Box::new(buffer.clone()), "game.nes", [0xAB; 16]).unwrap();
+    for frame in frames.iter() {
+        recorder.record(*frame).unwrap();
+    }
+
+    let fm2 = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    assert!(fm2.contains("romChecksum base64:q6urq6urq6urq6urq6urqw==\n"));
+    assert!(fm2.contains("|0|....T..A|........||\n"));
+    let movie: Movie = fm2.parse().unwrap();
+    assert_eq!(movie.frames, frames);
+}
@@ -1,37 +1,270 @@
-use crate::cartridge::Cartridge;
-use crate::cpu::CPU;
-use crate::joypad::Joypad;
-use crate::ppu::PPU;
-use sdl2::event::Event;
+use crate::font;
+use crate::gamepad::{self, PortAssignment};
+use crate::keys::{self, KeyBindings};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::controller::GameController;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
 use sdl2::render::{Canvas, TextureAccess};
+use sdl2::video::FullscreenType;
 use sdl2::{pixels::PixelFormatEnum, video::Window};
-use std::cell::RefCell;
-use std::rc::Rc;
+use sdl2::{EventPump, GameControllerSubsystem, JoystickSubsystem};
+use shrimp::apu;
+use shrimp::cartridge::{Cartridge, ConsoleType};
+use shrimp::console::Console;
+use shrimp::cpu;
+use shrimp::debugger::Debugger;
+use shrimp::joypad::FourScore;
+use shrimp::movie::{Frame, Movie, Recorder};
+use shrimp::patch::{Memory, RamPatch};
+use shrimp::ppu::filter::{self, Filter};
+use shrimp::ppu::viewer::{self, VIEWER_HEIGHT, VIEWER_WIDTH};
+use shrimp::ppu::{Palette, PixelFormat, Region, SCREEN_HEIGHT, SCREEN_WIDTH};
+use shrimp::zapper::Zapper;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-pub const SCREEN_WIDTH: usize = 256;
-pub const SCREEN_HEIGHT: usize = 240;
+// samples are dropped rather than queued once this many are waiting to be played, which happens
+// when running faster than real time.
+const MAX_QUEUED_SAMPLES: u32 = apu::SAMPLE_RATE as u32 / 10;
 
+// NES is the SDL frontend, it drives a Console with a window, audio and input.
 pub struct NES {
-    cpu: CPU,
-    ppu: Rc<RefCell<PPU>>,
+    console: Console,
     scale: u8,
+    pixel_format: PixelFormat,
+    swap_rb: bool,
+    ports: PortAssignment,
+    rom_path: PathBuf,
+    patch_path: PathBuf,
+    // baseline is the memory at power-on, which F2 saves the RAM patch against.
+    baseline: Memory,
+    vsync: bool,
+    fullscreen: bool,
+    filter: Filter,
+    debug_gfx: bool,
+    region: Region,
+    keys: KeyBindings,
+    // movie replaces live input until it runs out of frames.
+    movie: Option<Movie>,
+    recorder: Option<Recorder>,
+    // the debugger is only enabled when there are breakpoints.
+    debugger: Option<Debugger>,
+    // stats collects per-frame telemetry, which is printed once a second with --stats.
+    stats: FrameStats,
+    print_stats: bool,
 }
 
 impl NES {
-    pub fn new(opts: super::Options) -> Self {
-        let cartridge = Cartridge::from_path(opts.rom.as_str()).unwrap();
-        let cartridge = Rc::new(RefCell::new(cartridge));
+    pub fn new(opts: super::Options) -> Result<Self, Box<dyn std::error::Error>> {
+        let rom = opts.rom.ok_or("no ROM given")?;
+        let cartridge = Cartridge::from_path(rom.as_str())?;
+        let console_type = cartridge.console_type();
 
-        let ppu = PPU::new(cartridge.clone());
-        let ppu = Rc::new(RefCell::new(ppu));
+        let palette = match opts.palette {
+            Some(path) => Palette::from_path(path)?,
+            None => Palette::default(),
+        };
+        let mut console = Console::new(cartridge, palette);
+        {
+            let mut ppu = console.ppu.borrow_mut();
+            ppu.set_pixel_format(opts.pixel_format);
+            ppu.set_region(opts.region);
+        }
+
+        let cpu = &mut console.cpu;
+        cpu.init_ram(opts.ram_init);
+        cpu.apu.set_volume(opts.volume);
+        cpu.joypad_1.controller_type = opts.controller_type;
+        cpu.joypad_2.controller_type = opts.controller_type;
+        if opts.famicom {
+            if console_type != ConsoleType::Nes {
+                return Err(
+                    format!("cannot run a {:?} cartridge as a Famicom", console_type).into(),
+                );
+            }
+            cpu.famicom = true;
+        }
+        if let Some(path) = opts.trace {
+            let file = std::fs::File::create(path)?;
+            cpu.trace = Some(Box::new(std::io::BufWriter::new(file)));
+        }
+        if opts.zapper {
+            cpu.zapper = Some(Zapper::default());
+        }
+        if opts.fourscore {
+            cpu.four_score = Some(FourScore::default());
+        }
+        if opts.test_output {
+            cpu.test_output = Some(String::new());
+        }
+        for wp in opts.watchpoints {
+            cpu.add_watchpoint(wp);
+        }
+        for code in opts.genie {
+            cpu.add_genie_code(code);
+        }
+        if let Some(entry) = opts.entry {
+            cpu.set_pc(entry);
+        }
+
+        let baseline = console.memory();
+        if let Some(path) = opts.patch {
+            let patch: RamPatch = std::fs::read_to_string(path)?.parse()?;
+            console.apply_patch(&patch);
+        }
 
-        let cpu = CPU::new(cartridge, ppu.clone());
-        Self {
-            cpu,
-            ppu,
+        let movie = match opts.movie {
+            Some(path) => Some(std::fs::read_to_string(path)?.parse()?),
+            None => None,
+        };
+
+        let recorder = match opts.record {
+            Some(path) => {
+                let file = std::fs::File::create(path)?;
+                let name = Path::new(&rom).file_name().unwrap_or_default();
+                Some(Recorder::new(
+                    Box::new(std::io::BufWriter::new(file)),
+                    &name.to_string_lossy(),
+                    console.cartridge.borrow().md5(),
+                )?)
+            }
+            None => None,
+        };
+
+        let debugger = if opts.breakpoints.is_empty() {
+            None
+        } else {
+            let mut debugger = Debugger::default();
+            for addr in opts.breakpoints {
+                debugger.add_breakpoint(addr);
+            }
+            Some(debugger)
+        };
+
+        Ok(Self {
+            console,
             scale: opts.scale,
+            pixel_format: opts.pixel_format,
+            swap_rb: opts.swap_rb,
+            ports: PortAssignment::new([opts.pad1, opts.pad2]),
+            rom_path: PathBuf::from(&rom),
+            patch_path: Path::new(&rom).with_extension("patch"),
+            baseline,
+            vsync: opts.vsync,
+            fullscreen: opts.fullscreen,
+            filter: opts.filter,
+            debug_gfx: opts.debug_gfx,
+            region: opts.region,
+            keys: keys::load(opts.keys.as_deref())?,
+            movie,
+            recorder,
+            debugger,
+            stats: FrameStats::default(),
+            print_stats: opts.stats,
+        })
+    }
+
+    // reset behaves like the console's reset button, the CPU jumps to the reset vector and the PPU
+    // goes back to its power-on state, while RAM and the cartridge are left intact.
+    pub fn reset(&mut self) {
+        self.console.reset();
+    }
+
+    // run_headless runs the given number of frames as fast as possible, without a window or audio.
+    // Watchpoints are reported but don't stop emulation.
+    pub fn run_headless(&mut self, frames: u64) -> std::io::Result<()> {
+        let mut frame = 0;
+        self.start_frame(false)?;
+        while frame < frames {
+            self.console.step_frame();
+            let cpu = &mut self.console.cpu;
+            match cpu.halted() {
+                Some(wp) => {
+                    println!("Hit watchpoint {}", wp);
+                    cpu.resume();
+                }
+                None => {
+                    self.start_frame(false)?;
+                    frame += 1;
+                }
+            }
         }
+        self.console.cpu.apu.take_samples();
+        Ok(())
+    }
+
+    // start_frame sets up the input for the next frame, pressing the movie's buttons while one
+    // is playing and recording the frame when recording. reset tells whether the reset button was
+    // pressed since the last frame.
+    fn start_frame(&mut self, mut reset: bool) -> std::io::Result<()> {
+        match self.movie.as_mut().map(Movie::next_frame) {
+            Some(Some(frame)) => {
+                if frame.reset {
+                    self.reset();
+                    reset = true;
+                }
+                self.console.cpu.joypad_1.set_buttons(frame.pads[0]);
+                self.console.cpu.joypad_2.set_buttons(frame.pads[1]);
+            }
+            Some(None) => self.movie = None,
+            None => {}
+        }
+
+        if let Some(recorder) = self.recorder.as_mut() {
+            let cpu = &self.console.cpu;
+            recorder.record(Frame {
+                reset,
+                pads: [cpu.joypad_1.buttons(), cpu.joypad_2.buttons()],
+            })?;
+        }
+        Ok(())
+    }
+
+    // screen returns a copy of the last frame, in the format set with --pixel-format.
+    #[allow(dead_code)]
+    pub fn screen(&self) -> Vec<u8> {
+        self.console.ppu.borrow().screen.to_vec()
+    }
+
+    // save_patch writes the bytes of RAM and PRG RAM that changed since power-on to the ROM's
+    // .patch file, which --patch applies.
+    pub fn save_patch(&self) -> std::io::Result<()> {
+        let patch = RamPatch::diff(&self.baseline, &self.console.memory());
+        std::fs::write(&self.patch_path, patch.to_string())
+    }
+
+    // screenshot writes the last frame as a PNG file, scaled like the window is.
+    pub fn screenshot(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.console.screenshot(self.scale.max(1) as usize))
+    }
+
+    // peek_cpu reads the CPU's address space without any side effects.
+    #[allow(dead_code)]
+    pub fn peek_cpu(&self, addr: u16) -> u8 {
+        self.console.cpu.peekb(addr)
+    }
+
+    // peek_ppu reads the PPU's address space, where the pattern tables, nametables and palettes
+    // live, without any side effects.
+    #[allow(dead_code)]
+    pub fn peek_ppu(&self, addr: u16) -> u8 {
+        self.console.ppu.borrow().peek(addr)
+    }
+
+    // test_status returns the byte at $6000, where test ROMs report their status: 0x80 while the
+    // test is running and the result code once it is done.
+    pub fn test_status(&self) -> u8 {
+        self.console.cartridge.borrow().read(0x6000)
+    }
+
+    // test_output returns the text written by test ROMs from $6004 on, if --test-output is set.
+    pub fn test_output(&self) -> Option<&str> {
+        self.console.cpu.test_output.as_deref()
     }
 
     pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
@@ -40,7 +273,7 @@ impl NES {
 
         let window = video_subsystem
             .window(
-                "Shrimp",
+                &window_title(&self.rom_path, None),
                 SCREEN_WIDTH as u32 * self.scale as u32,
                 SCREEN_HEIGHT as u32 * self.scale as u32,
             )
@@ -49,52 +282,325 @@ impl NES {
 
         gl::load_with(|name| video_subsystem.gl_get_proc_address(name) as *const _);
 
+        let audio_subsystem = sdl_context.audio()?;
+        let audio: AudioQueue<f32> = audio_subsystem.open_queue(
+            None,
+            &AudioSpecDesired {
+                freq: Some(apu::SAMPLE_RATE),
+                channels: Some(1),
+                samples: None,
+            },
+        )?;
+        audio.resume();
+
+        let joystick_subsystem = sdl_context.joystick()?;
+        let controller_subsystem = sdl_context.game_controller()?;
+        // controllers are closed when dropped, so we need to hold on to them.
+        let mut controllers: HashMap<u32, GameController> = HashMap::new();
+
         let mut event_pump = sdl_context.event_pump()?;
-        let mut canvas: Canvas<Window> = window.into_canvas().accelerated().build()?;
+        let mut canvas = window.into_canvas().accelerated();
+        if self.vsync {
+            canvas = canvas.present_vsync();
+        }
+        let mut canvas: Canvas<Window> = canvas.build()?;
+        set_fullscreen(&mut canvas, self.fullscreen)?;
 
         let texture_creator = canvas.texture_creator();
         let mut texture = texture_creator.create_texture(
-            PixelFormatEnum::BGR24,
+            texture_format(self.pixel_format),
             TextureAccess::Streaming,
-            SCREEN_WIDTH as u32,
+            self.filter.width() as u32,
             SCREEN_HEIGHT as u32,
         )?;
 
+        let mut viewer_canvas = if self.debug_gfx {
+            let window = video_subsystem
+                .window("Shrimp graphics", VIEWER_WIDTH as u32, VIEWER_HEIGHT as u32)
+                .build()?;
+            Some(window.into_canvas().build()?)
+        } else {
+            None
+        };
+        let viewer_texture_creator = viewer_canvas
+            .as_ref()
+            .map(Canvas::<Window>::texture_creator);
+        let mut viewer_texture = match &viewer_texture_creator {
+            Some(texture_creator) => Some(texture_creator.create_texture(
+                texture_format(PixelFormat::Rgb24),
+                TextureAccess::Streaming,
+                VIEWER_WIDTH as u32,
+                VIEWER_HEIGHT as u32,
+            )?),
+            None => None,
+        };
+
+        self.start_frame(false)?;
+        let mut pacer = FramePacer::new(Instant::now());
+        let mut frame_start = Instant::now();
+        // F3 toggles an overlay showing the FPS and frame number, the FPS being updated once a
+        // second.
+        let mut overlay = false;
+        let mut fps = 0.0;
+        // holding tab runs as fast as possible, the number keys set a speed multiplier.
+        let mut fast_forward = false;
+        let mut speed = 1;
+        // P pauses emulation, the window keeps presenting the last frame and handling events.
+        // While paused the period key runs a single frame.
+        let mut pause = Pause::default();
+        let mut running = true;
+        // reset tells whether the reset button was pressed since the last frame started, which
+        // may have been a few paused frames ago.
+        let mut reset = false;
         'running: loop {
-            self.cpu.tick();
-            let mut ppu = self.ppu.borrow_mut();
-            ppu.tick(&mut self.cpu);
+            if let (true, Some(debugger)) = (running, self.debugger.as_mut()) {
+                let console = &mut self.console;
+                if debugger.should_break(&console.cpu) {
+                    let stdin = std::io::stdin();
+                    let mut input = stdin.lock();
+                    let mut output = std::io::stdout();
+                    if !debugger.prompt(console, &mut input, &mut output)? {
+                        break 'running;
+                    }
+                }
+            }
 
-            if ppu.frame_complete {
-                texture.update(None, &ppu.screen, SCREEN_WIDTH * 3)?;
+            // a paused frame is complete right away, presenting the last frame again.
+            let frame_complete = !running || self.console.step();
+            let cpu = &mut self.console.cpu;
+            if let Some(wp) = cpu.halted() {
+                let (instruction, _) = cpu::disassemble(cpu, cpu.pc());
+                println!(
+                    "Hit watchpoint {} before ${:04X} {}, press F5 to continue",
+                    wp,
+                    cpu.pc(),
+                    instruction
+                );
+                if !wait_for_resume(&mut event_pump) {
+                    break 'running;
+                }
+                cpu.resume();
+            }
 
+            if frame_complete {
+                let ppu = self.console.ppu.borrow();
+                let format = if self.swap_rb {
+                    self.pixel_format.swapped()
+                } else {
+                    self.pixel_format
+                };
+                let filter = self.filter;
+                texture.with_lock(None, |buf, pitch| {
+                    filter::blit(&ppu, filter, buf, pitch, format)
+                })?;
+
+                let (width, height) = canvas.output_size()?;
                 canvas.clear();
-                canvas.copy(&texture, None, None)?;
+                canvas.copy(&texture, None, letterbox(width, height))?;
+                if overlay {
+                    let text = format!("{:.1} FPS\nFRAME {}", fps, ppu.frame());
+                    draw_text(&mut canvas, &text, self.scale.max(1) as u32)?;
+                }
                 canvas.present();
+                if let (Some(canvas), Some(texture)) =
+                    (viewer_canvas.as_mut(), viewer_texture.as_mut())
+                {
+                    texture.update(None, &viewer::render(&ppu), VIEWER_WIDTH * 3)?;
+                    canvas.copy(texture, None, None)?;
+                    canvas.present();
+                }
+                drop(ppu);
+                let samples = self.console.cpu.apu.take_samples();
+                if audio.size() / std::mem::size_of::<f32>() as u32 <= MAX_QUEUED_SAMPLES {
+                    audio.queue(&samples);
+                }
 
                 while let Some(event) = event_pump.poll_event() {
-                    let j1 = &mut self.cpu.joypad_1;
-                    let j2 = &mut self.cpu.joypad_2;
                     match event {
                         Event::Quit { .. }
+                        | Event::Window {
+                            win_event: WindowEvent::Close,
+                            ..
+                        }
                         | Event::KeyDown {
                             keycode: Some(Keycode::Escape),
                             ..
                         } => break 'running,
+                        Event::KeyDown {
+                            keycode: Some(Keycode::F1),
+                            ..
+                        } => {
+                            self.reset();
+                            reset = true;
+                            break;
+                        }
+                        Event::KeyDown {
+                            keycode: Some(Keycode::F2),
+                            ..
+                        } => {
+                            if let Err(err) = self.save_patch() {
+                                log::error!("failed to save RAM patch: {}", err);
+                            }
+                        }
+                        Event::KeyDown {
+                            keycode: Some(Keycode::P),
+                            ..
+                        } => pause.toggle(),
+                        Event::KeyDown {
+                            keycode: Some(Keycode::Period),
+                            ..
+                        } => pause.step(),
+                        Event::KeyDown {
+                            keycode: Some(Keycode::F3),
+                            ..
+                        } => overlay = !overlay,
+                        Event::KeyDown {
+                            keycode: Some(Keycode::F11),
+                            ..
+                        } => {
+                            self.fullscreen = !self.fullscreen;
+                            set_fullscreen(&mut canvas, self.fullscreen)?;
+                        }
+                        Event::KeyDown {
+                            keycode: Some(Keycode::M),
+                            ..
+                        } => {
+                            let apu = &mut self.console.cpu.apu;
+                            apu.set_muted(!apu.muted());
+                        }
+                        Event::KeyDown {
+                            keycode: Some(Keycode::F12),
+                            ..
+                        } => {
+                            let timestamp = std::time::SystemTime::now()
+                                .duration_since(std::time::UNIX_EPOCH)?
+                                .as_millis();
+                            let path = format!("shrimp-{}.png", timestamp);
+                            match self.screenshot(&path) {
+                                Ok(()) => println!("Saved screenshot to {}", path),
+                                Err(err) => log::error!("failed to save screenshot: {}", err),
+                            }
+                            break;
+                        }
+                        Event::KeyDown {
+                            keycode: Some(Keycode::Tab),
+                            ..
+                        } => fast_forward = true,
+                        Event::KeyUp {
+                            keycode: Some(Keycode::Tab),
+                            ..
+                        } => fast_forward = false,
                         Event::KeyUp {
                             keycode: Some(keycode),
                             ..
-                        } => set_keys(j1, j2, keycode, false),
+                        } => set_keys(&self.keys, &mut self.console, keycode, false),
                         Event::KeyDown {
                             keycode: Some(keycode),
                             ..
-                        } => set_keys(j1, j2, keycode, true),
+                        } => {
+                            if let Some(multiplier) = speed_multiplier(keycode) {
+                                speed = multiplier;
+                            }
+                            set_keys(&self.keys, &mut self.console, keycode, true);
+                        }
+                        Event::MouseMotion { x, y, .. } => {
+                            if let Some(zapper) = self.console.cpu.zapper.as_mut() {
+                                let (width, height) = canvas.output_size()?;
+                                zapper.target = screen_position(x, y, letterbox(width, height));
+                            }
+                        }
+                        Event::MouseButtonDown {
+                            mouse_btn: MouseButton::Left,
+                            ..
+                        } => {
+                            if let Some(zapper) = self.console.cpu.zapper.as_mut() {
+                                zapper.trigger = true;
+                            }
+                        }
+                        Event::MouseButtonUp {
+                            mouse_btn: MouseButton::Left,
+                            ..
+                        } => {
+                            if let Some(zapper) = self.console.cpu.zapper.as_mut() {
+                                zapper.trigger = false;
+                            }
+                        }
+                        Event::ControllerDeviceAdded { which, .. } => {
+                            let opened =
+                                open_gamepad(&controller_subsystem, &joystick_subsystem, which);
+                            let (controller, guid) = match opened {
+                                Ok(opened) => opened,
+                                Err(err) => {
+                                    log::error!("failed to open gamepad {}: {}", which, err);
+                                    continue;
+                                }
+                            };
+                            let id = controller.instance_id();
+                            let connection = self.ports.connect(id, &guid);
+                            if let Some(bumped) = connection.bumped {
+                                // the buttons the bumped gamepad was holding are left pressed
+                                // otherwise.
+                                let cpu = &mut self.console.cpu;
+                                match connection.port {
+                                    Some(0) => cpu.joypad_1.set_buttons(0),
+                                    _ => cpu.joypad_2.set_buttons(0),
+                                }
+                                if self.ports.port(bumped).is_none() {
+                                    log::warn!("no free port left for gamepad {}", bumped);
+                                }
+                            }
+                            controllers.insert(id, controller);
+                        }
+                        Event::ControllerDeviceRemoved { which, .. } => {
+                            self.ports.disconnect(which);
+                            controllers.remove(&which);
+                        }
+                        Event::ControllerButtonDown { which, button, .. } => {
+                            let cpu = &mut self.console.cpu;
+                            match self.ports.port(which) {
+                                Some(0) => gamepad::set_button(&mut cpu.joypad_1, button, true),
+                                Some(_) => gamepad::set_button(&mut cpu.joypad_2, button, true),
+                                None => {}
+                            }
+                        }
+                        Event::ControllerButtonUp { which, button, .. } => {
+                            let cpu = &mut self.console.cpu;
+                            match self.ports.port(which) {
+                                Some(0) => gamepad::set_button(&mut cpu.joypad_1, button, false),
+                                Some(_) => gamepad::set_button(&mut cpu.joypad_2, button, false),
+                                None => {}
+                            }
+                        }
                         _ => {}
                     }
                 }
+                let ran = running;
+                running = pause.run_frame();
+                if running {
+                    self.start_frame(std::mem::take(&mut reset))?;
+                }
 
-                // 60 FPS
-                std::thread::sleep(std::time::Duration::from_nanos(16000000));
+                let busy = frame_start.elapsed();
+                // with vsync the presentation already blocks until the next refresh.
+                if !self.vsync {
+                    let fast_forward = fast_forward && running;
+                    let frame_time = frame_time(self.region.frame_time(), speed, fast_forward);
+                    std::thread::sleep(pacer.next_frame(Instant::now(), frame_time));
+                }
+                if ran {
+                    let instructions = self.console.cpu.instructions;
+                    let dots = self.console.ppu.borrow().dots();
+                    let elapsed = frame_start.elapsed();
+                    if let Some(report) = self.stats.record(instructions, dots, busy, elapsed) {
+                        if self.print_stats {
+                            println!("{}", report);
+                        }
+                        fps = report.fps;
+                        let title = window_title(&self.rom_path, Some(fps));
+                        canvas.window_mut().set_title(&title)?;
+                    }
+                }
+                frame_start = Instant::now();
             }
         }
 
@@ -102,26 +608,573 @@ impl NES {
     }
 }
 
-fn set_keys(j1: &mut Joypad, j2: &mut Joypad, keycode: Keycode, pressed: bool) {
-    match keycode {
-        // Joypad 1
-        Keycode::R => j1.start = pressed,
-        Keycode::LShift => j1.select = pressed,
-        Keycode::V => j1.a = pressed,
-        Keycode::C => j1.b = pressed,
-        Keycode::W => j1.up = pressed,
-        Keycode::S => j1.down = pressed,
-        Keycode::A => j1.left = pressed,
-        Keycode::D => j1.right = pressed,
-        // Joypad 2
-        Keycode::U => j2.start = pressed,
-        Keycode::RShift => j2.select = pressed,
-        Keycode::N => j2.a = pressed,
-        Keycode::B => j2.b = pressed,
-        Keycode::I => j2.up = pressed,
-        Keycode::K => j2.down = pressed,
-        Keycode::J => j2.left = pressed,
-        Keycode::L => j2.right = pressed,
-        _ => {}
+// window_title names the window after the ROM, followed by the FPS once it's been measured.
+fn window_title(rom: &Path, fps: Option<f64>) -> String {
+    let name = rom
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_else(|| "Shrimp".into());
+    match fps {
+        Some(fps) => format!("{} \u{2014} {:.0} fps", name, fps),
+        None => name.into_owned(),
     }
 }
+
+// draw_text draws white text on a black box in the top left corner of the window, each pixel of
+// the font being a square of the given size.
+fn draw_text(canvas: &mut Canvas<Window>, text: &str, size: u32) -> Result<(), String> {
+    let margin = size as i32 * 2;
+    let (width, height) = font::size(text);
+    canvas.set_draw_color(Color::BLACK);
+    canvas.fill_rect(Rect::new(
+        0,
+        0,
+        (width as u32 + 4) * size,
+        (height as u32 + 4) * size,
+    ))?;
+
+    let rects: Vec<Rect> = font::pixels(text)
+        .into_iter()
+        .map(|(x, y)| {
+            Rect::new(
+                margin + x as i32 * size as i32,
+                margin + y as i32 * size as i32,
+                size,
+                size,
+            )
+        })
+        .collect();
+    canvas.set_draw_color(Color::WHITE);
+    canvas.fill_rects(&rects)?;
+    // clear() paints with the draw color.
+    canvas.set_draw_color(Color::BLACK);
+    Ok(())
+}
+
+// Pause decides whether each frame is emulated, the window keeps running while paused.
+#[derive(Debug, Default)]
+struct Pause {
+    paused: bool,
+    // step is set when a single frame should run while paused.
+    step: bool,
+}
+
+impl Pause {
+    fn toggle(&mut self) {
+        self.paused = !self.paused;
+        self.step = false;
+    }
+
+    fn step(&mut self) {
+        self.step = self.paused;
+    }
+
+    // run_frame returns whether the next frame should be emulated, using up a pending step.
+    fn run_frame(&mut self) -> bool {
+        !self.paused || std::mem::take(&mut self.step)
+    }
+}
+
+// FramePacer keeps frames on a fixed schedule, so the time spent emulating a frame is subtracted
+// from the time slept after it.
+struct FramePacer {
+    deadline: Instant,
+}
+
+impl FramePacer {
+    fn new(now: Instant) -> Self {
+        FramePacer { deadline: now }
+    }
+
+    // returns how long to sleep until the next frame is due. When emulation falls more than a
+    // frame behind the schedule is restarted, rather than running frames back to back to catch up.
+    fn next_frame(&mut self, now: Instant, frame_time: Duration) -> Duration {
+        self.deadline += frame_time;
+        if now > self.deadline + frame_time {
+            self.deadline = now;
+        }
+        self.deadline.saturating_duration_since(now)
+    }
+}
+
+// FrameStats accumulates per-frame telemetry and averages it over roughly a second of wall-clock
+// time. The CPU and PPU counters are totals since reset, the frame's share being the difference
+// with the last frame's.
+#[derive(Default)]
+struct FrameStats {
+    last_instructions: u64,
+    last_dots: u64,
+    frames: u32,
+    instructions: u64,
+    dots: u64,
+    busy: Duration,
+    elapsed: Duration,
+}
+
+// StatsReport holds the averages over the frames run since the last report.
+#[derive(Debug, PartialEq)]
+struct StatsReport {
+    instructions: u64,
+    dots: u64,
+    frame_time: Duration,
+    fps: f64,
+}
+
+impl std::fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.1} fps, {:.2}ms per frame, {} instructions, {} dots",
+            self.fps,
+            self.frame_time.as_secs_f64() * 1000.0,
+            self.instructions,
+            self.dots
+        )
+    }
+}
+
+impl FrameStats {
+    // record adds a frame, given the CPU and PPU counters at its end, the time spent emulating and
+    // presenting it and the wall-clock time it took including any sleep. It returns the averages
+    // once a second's worth of frames has been recorded. A reset rewinds the counters, so the
+    // frame it happens in counts as empty.
+    fn record(
+        &mut self,
+        instructions: u64,
+        dots: u64,
+        busy: Duration,
+        elapsed: Duration,
+    ) -> Option<StatsReport> {
+        self.instructions += instructions.saturating_sub(self.last_instructions);
+        self.dots += dots.saturating_sub(self.last_dots);
+        self.last_instructions = instructions;
+        self.last_dots = dots;
+        self.frames += 1;
+        self.busy += busy;
+        self.elapsed += elapsed;
+        if self.elapsed < Duration::from_secs(1) {
+            return None;
+        }
+
+        let frames = self.frames;
+        let report = StatsReport {
+            instructions: self.instructions / frames as u64,
+            dots: self.dots / frames as u64,
+            frame_time: self.busy / frames,
+            fps: frames as f64 / self.elapsed.as_secs_f64(),
+        };
+        *self = FrameStats {
+            last_instructions: instructions,
+            last_dots: dots,
+            ..FrameStats::default()
+        };
+        Some(report)
+    }
+}
+
+// frame_time returns how long a frame should take when running at the given speed multiplier, a
+// fast forwarding frame takes no time at all.
+fn frame_time(real_time: Duration, speed: u32, fast_forward: bool) -> Duration {
+    if fast_forward {
+        Duration::from_secs(0)
+    } else {
+        real_time / speed.max(1)
+    }
+}
+
+// maps the number keys to speed multipliers, 1 being real time.
+fn speed_multiplier(keycode: Keycode) -> Option<u32> {
+    let speed = match keycode {
+        Keycode::Num1 => 1,
+        Keycode::Num2 => 2,
+        Keycode::Num3 => 3,
+        Keycode::Num4 => 4,
+        Keycode::Num5 => 5,
+        Keycode::Num6 => 6,
+        Keycode::Num7 => 7,
+        Keycode::Num8 => 8,
+        Keycode::Num9 => 9,
+        _ => return None,
+    };
+    Some(speed)
+}
+
+// letterbox returns where to draw the screen in a window of the given size, scaled by the largest
+// integer factor that fits so pixels stay square, and centered.
+fn letterbox(width: u32, height: u32) -> Rect {
+    let scale = (width / SCREEN_WIDTH as u32)
+        .min(height / SCREEN_HEIGHT as u32)
+        .max(1);
+    let (w, h) = (SCREEN_WIDTH as u32 * scale, SCREEN_HEIGHT as u32 * scale);
+    let x = (width as i32 - w as i32) / 2;
+    let y = (height as i32 - h as i32) / 2;
+    Rect::new(x, y, w, h)
+}
+
+// screen_position maps a position in the window to the pixel drawn there, given where the
+// screen is drawn. Positions in the borders around the screen map to None.
+fn screen_position(x: i32, y: i32, screen: Rect) -> Option<(usize, usize)> {
+    if !screen.contains_point((x, y)) {
+        return None;
+    }
+    let x = (x - screen.x()) as usize * SCREEN_WIDTH / screen.width() as usize;
+    let y = (y - screen.y()) as usize * SCREEN_HEIGHT / screen.height() as usize;
+    Some((x, y))
+}
+
+fn set_fullscreen(canvas: &mut Canvas<Window>, fullscreen: bool) -> Result<(), String> {
+    let mode = if fullscreen {
+        FullscreenType::Desktop
+    } else {
+        FullscreenType::Off
+    };
+    canvas.window_mut().set_fullscreen(mode)
+}
+
+fn texture_format(format: PixelFormat) -> PixelFormatEnum {
+    match format {
+        PixelFormat::Bgr24 => PixelFormatEnum::BGR24,
+        PixelFormat::Rgb24 => PixelFormatEnum::RGB24,
+    }
+}
+
+// open_gamepad opens the gamepad with the given device index, and returns it along with its GUID.
+fn open_gamepad(
+    controllers: &GameControllerSubsystem,
+    joysticks: &JoystickSubsystem,
+    which: u32,
+) -> Result<(GameController, String), Box<dyn std::error::Error>> {
+    let controller = controllers.open(which)?;
+    let guid = joysticks.device_guid(which)?.string();
+    Ok((controller, guid))
+}
+
+// blocks until the user asks to resume emulation, returns false if the user asked to quit instead.
+fn wait_for_resume(event_pump: &mut EventPump) -> bool {
+    loop {
+        match event_pump.wait_event() {
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => return false,
+            Event::KeyDown {
+                keycode: Some(Keycode::F5),
+                ..
+            } => return true,
+            _ => {}
+        }
+    }
+}
+
+fn set_keys(keys: &KeyBindings, console: &mut Console, keycode: Keycode, pressed: bool) {
+    if let Some(&(player, button)) = keys.get(&keycode) {
+        console.set_button(player, button, pressed);
+    }
+}
+
+// builds a NES running an NROM cartridge with the given program at $8000.
+#[cfg(test)]
+fn test_nes(program: &[u8]) -> NES {
+    test_nes_with_args(program, &[])
+}
+
+// builds a NES like test_nes, passing it extra command line arguments.
+#[cfg(test)]
+fn test_nes_with_args(program: &[u8], args: &[&str]) -> NES {
+    use structopt::StructOpt;
+
+    let data = shrimp::cartridge::test_program(program);
+    let dir = std::env::temp_dir().join(format!("shrimp-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // tests run in parallel, so every ROM gets its own file.
+    static ROMS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let id = ROMS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    let rom = dir.join(format!("test-{}.nes", id));
+    std::fs::write(&rom, data).unwrap();
+    let opts = ["shrimp", "-r", rom.to_str().unwrap()];
+    let opts = super::Options::from_iter(opts.iter().chain(args));
+    let nes = NES::new(opts).unwrap();
+    std::fs::remove_file(&rom).unwrap();
+    nes
+}
+
+#[test]
+fn test_patch_with_seeded_ram() {
+    let args = ["--ram-init", "seed:1"];
+    let mut nes = test_nes_with_args(&[], &args);
+    let ram = nes.console.cpu.ram_mut();
+    // zeroing a byte is a change too, as seeded RAM doesn't start out zeroed.
+    let seeded = ram.iter().position(|&b| b != 0).unwrap();
+    ram[seeded] = 0;
+    ram[0x300] = ram[0x300].wrapping_add(1);
+    nes.save_patch().unwrap();
+    let patch = std::fs::read_to_string(&nes.patch_path).unwrap();
+    std::fs::remove_file(&nes.patch_path).unwrap();
+    assert_eq!(patch.lines().count(), 2, "{}", patch);
+
+    let path = std::env::temp_dir().join(format!("shrimp-seeded-{}.patch", std::process::id()));
+    std::fs::write(&path, patch).unwrap();
+    let reloaded = test_nes_with_args(&[], &[args[0], args[1], "--patch", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(reloaded.console.cpu.ram(), nes.console.cpu.ram());
+}
+
+#[test]
+fn test_reset() {
+    let mut nes = test_nes(&[
+        0xE6, 0x00, // INC $00
+        0x4C, 0x02, 0x80, // JMP *
+    ]);
+
+    assert_eq!(nes.console.cpu.pc(), 0x8000);
+    for _ in 0..10 {
+        nes.console.cpu.tick();
+    }
+    assert_eq!(nes.console.cpu.pc(), 0x8002);
+    assert_eq!(nes.console.cpu.ram()[0], 1);
+
+    nes.reset();
+    assert_eq!(nes.console.cpu.pc(), 0x8000);
+    assert_eq!(nes.console.ppu.borrow().scanline(), 0);
+    // RAM survives the reset.
+    assert_eq!(nes.console.cpu.ram()[0], 1);
+}
+
+#[test]
+fn test_entry() {
+    let nes = test_nes_with_args(&[], &["--entry", "$C000"]);
+    assert_eq!(nes.console.cpu.pc(), 0xC000);
+
+    let nes = test_nes(&[]);
+    assert_eq!(nes.console.cpu.pc(), 0x8000);
+}
+
+#[test]
+fn test_peek() {
+    let mut nes = test_nes(&[
+        0xA9, 0x42, // LDA #$42
+        0x85, 0x10, // STA $10
+        0xA9, 0x20, // LDA #$20
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x05, // LDA #$05
+        0x8D, 0x06, 0x20, // STA $2006
+        0xA9, 0x37, // LDA #$37
+        0x8D, 0x07, 0x20, // STA $2007
+    ]);
+    for _ in 0..8 {
+        nes.console.cpu.tick();
+    }
+
+    assert_eq!(nes.peek_cpu(0x0010), 0x42);
+    assert_eq!(nes.peek_cpu(0x0810), 0x42);
+    assert_eq!(nes.peek_cpu(0x8000), 0xA9);
+    assert_eq!(nes.peek_ppu(0x2005), 0x37);
+
+    // peeking PPUSTATUS leaves the vblank flag set.
+    nes.console.ppu.borrow_mut().set_vblank(true);
+    assert_eq!(nes.peek_cpu(0x2002) & 0x80, 0x80);
+    assert_eq!(nes.peek_cpu(0x3FFA) & 0x80, 0x80);
+}
+
+#[test]
+fn test_run_headless() {
+    let mut nes = test_nes(&[
+        0xA9, 0x80, // LDA #$80
+        0x8D, 0x00, 0x60, // STA $6000
+        0x2C, 0x02, 0x20, // BIT $2002
+        0x10, 0xFB, // BPL $8005
+        0xE6, 0x10, // INC $10
+        0xA5, 0x10, // LDA $10
+        0xC9, 0x03, // CMP #$03
+        0xD0, 0xF3, // BNE $8005
+        0xA9, 0x00, // LDA #$00
+        0x8D, 0x00, 0x60, // STA $6000
+        0x4C, 0x17, 0x80, // JMP *
+    ]);
+
+    nes.run_headless(1).unwrap();
+    assert_eq!(nes.test_status(), 0x80);
+    nes.run_headless(3).unwrap();
+    assert_eq!(nes.console.cpu.ram()[0x10], 3);
+    assert_eq!(nes.test_status(), 0x00);
+    assert_eq!(nes.screen().len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+}
+
+#[test]
+fn test_test_output() {
+    let program = [
+        0xA2, 0x00, // LDX #$00
+        0xBD, 0x10, 0x80, // LDA $8010,X
+        0x9D, 0x04, 0x60, // STA $6004,X
+        0xE8, // INX
+        0xE0, 0x05, // CPX #$05
+        0xD0, 0xF5, // BNE $8002
+        0x4C, 0x0D, 0x80, // JMP *
+        b'P', b'A', b'S', b'S', 0x00,
+    ];
+
+    let mut nes = test_nes(&program);
+    nes.console.cpu.test_output = Some(String::new());
+    for _ in 0..30 {
+        nes.console.cpu.tick();
+    }
+    assert_eq!(nes.test_output(), Some("PASS"));
+    assert_eq!(nes.console.cartridge.borrow().read(0x6004), b'P');
+
+    let mut nes = test_nes(&program);
+    for _ in 0..30 {
+        nes.console.cpu.tick();
+    }
+    assert_eq!(nes.test_output(), None);
+    assert_eq!(nes.console.cartridge.borrow().read(0x6007), b'S');
+}
+
+#[test]
+fn test_frame_pacer() {
+    const FRAME_TIME: Duration = Duration::from_nanos(16_639_267);
+    let start = Instant::now();
+    let mut pacer = FramePacer::new(start);
+
+    // a frame that took 6ms sleeps for the rest of the frame period.
+    let now = start + Duration::from_millis(6);
+    assert_eq!(
+        pacer.next_frame(now, FRAME_TIME),
+        FRAME_TIME - Duration::from_millis(6)
+    );
+
+    // running a bit late is made up for in the next frame.
+    let now = start + FRAME_TIME * 2 + Duration::from_millis(1);
+    assert_eq!(pacer.next_frame(now, FRAME_TIME), Duration::from_millis(0));
+    let now = now + Duration::from_millis(5);
+    let sleep = pacer.next_frame(now, FRAME_TIME);
+    assert_eq!(sleep, start + FRAME_TIME * 3 - now);
+
+    // falling far behind restarts the schedule.
+    let now = start + FRAME_TIME * 10;
+    assert_eq!(pacer.next_frame(now, FRAME_TIME), Duration::from_millis(0));
+    assert_eq!(pacer.next_frame(now, FRAME_TIME), FRAME_TIME);
+}
+
+#[test]
+fn test_window_title() {
+    let rom = Path::new("roms/Legend of Zelda, The (U).nes");
+    assert_eq!(window_title(rom, None), "Legend of Zelda, The (U)");
+    assert_eq!(
+        window_title(rom, Some(59.94)),
+        "Legend of Zelda, The (U) \u{2014} 60 fps"
+    );
+    assert_eq!(
+        window_title(Path::new("zelda"), Some(30.2)),
+        "zelda \u{2014} 30 fps"
+    );
+    assert_eq!(window_title(Path::new(""), None), "Shrimp");
+}
+
+#[test]
+fn test_pause() {
+    let mut pause = Pause::default();
+    assert!(pause.run_frame());
+    // stepping only does something while paused.
+    pause.step();
+    assert!(pause.run_frame());
+    assert!(pause.run_frame());
+
+    pause.toggle();
+    assert!(!pause.run_frame());
+    assert!(!pause.run_frame());
+    // a step runs a single frame and stays paused.
+    pause.step();
+    pause.step();
+    assert!(pause.run_frame());
+    assert!(!pause.run_frame());
+
+    // a step pending when unpausing isn't carried over to the next pause.
+    pause.step();
+    pause.toggle();
+    assert!(pause.run_frame());
+    pause.toggle();
+    assert!(!pause.run_frame());
+}
+
+#[test]
+fn test_frame_time() {
+    let ntsc = Region::Ntsc.frame_time();
+    assert_eq!(frame_time(ntsc, 1, false), ntsc);
+    assert_eq!(frame_time(ntsc, 2, false), ntsc / 2);
+    assert_eq!(frame_time(ntsc, 0, false), ntsc);
+    assert_eq!(frame_time(ntsc, 2, true), Duration::from_secs(0));
+    assert!(frame_time(Region::Pal.frame_time(), 1, false) > ntsc);
+
+    assert_eq!(speed_multiplier(Keycode::Num4), Some(4));
+    assert_eq!(speed_multiplier(Keycode::Num0), None);
+}
+
+#[test]
+fn test_frame_stats() {
+    let mut stats = FrameStats::default();
+    let ms = Duration::from_millis;
+    // 40 frames of 25ms, each taking 10ms to emulate and running 100 instructions and 1000 dots.
+    for frame in 1..40 {
+        assert_eq!(
+            stats.record(frame * 100, frame * 1000, ms(10), ms(25)),
+            None
+        );
+    }
+    assert_eq!(
+        stats.record(4000, 40_000, ms(10), ms(25)),
+        Some(StatsReport {
+            instructions: 100,
+            dots: 1000,
+            frame_time: ms(10),
+            fps: 40.0,
+        })
+    );
+
+    // the next second starts from scratch: 10 slow frames followed by 10 fast ones.
+    for _ in 0..10 {
+        assert_eq!(stats.record(4000, 40_000, ms(90), ms(90)), None);
+    }
+    for _ in 0..9 {
+        assert_eq!(stats.record(4000, 40_000, ms(5), ms(10)), None);
+    }
+    let report = stats.record(4200, 40_000, ms(5), ms(10)).unwrap();
+    assert_eq!(report.instructions, 10);
+    assert_eq!(report.frame_time, Duration::from_micros(47_500));
+    assert!((report.fps - 20.0).abs() < 1e-9);
+
+    // a reset rewinds the counters.
+    assert_eq!(stats.record(50, 500, ms(10), ms(10)), None);
+    assert_eq!(stats.instructions, 0);
+    assert_eq!(stats.record(150, 1500, ms(10), ms(10)), None);
+    assert_eq!(stats.instructions, 100);
+    assert_eq!(
+        stats
+            .record(250, 2500, ms(10), ms(980))
+            .unwrap()
+            .to_string(),
+        "3.0 fps, 10.00ms per frame, 66 instructions, 666 dots"
+    );
+}
+
+#[test]
+fn test_letterbox() {
+    // a window made with --scale fills up exactly.
+    assert_eq!(letterbox(512, 480), Rect::new(0, 0, 512, 480));
+    // 1080p fits a 4x screen, with bars on both sides.
+    assert_eq!(letterbox(1920, 1080), Rect::new(448, 60, 1024, 960));
+    // the smaller dimension decides the scale.
+    assert_eq!(letterbox(800, 2000), Rect::new(16, 640, 768, 720));
+    // windows smaller than the screen draw it at 1x, cropped around the center.
+    assert_eq!(letterbox(200, 100), Rect::new(-28, -70, 256, 240));
+}
+
+#[test]
+fn test_screen_position() {
+    let screen = letterbox(1920, 1080);
+    assert_eq!(screen_position(448, 60, screen), Some((0, 0)));
+    assert_eq!(screen_position(1471, 1019, screen), Some((255, 239)));
+    assert_eq!(screen_position(960, 540, screen), Some((128, 120)));
+    assert_eq!(screen_position(447, 540, screen), None);
+    assert_eq!(screen_position(960, 1020, screen), None);
+}
@@ -0,0 +1,107 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+// PRG_RAM_START is where the CPU sees the cartridge's PRG RAM, patches address it from there.
+const PRG_RAM_START: u16 = 0x6000;
+
+// RamPatch holds the bytes of the CPU RAM and of the cartridge's PRG RAM that differ from a
+// freshly powered on machine. It is serialized as one "address:value" pair per line, both in hex,
+// e.g. "0300:42", with the PRG RAM starting at $6000.
+#[derive(Debug, Default, PartialEq)]
+pub struct RamPatch {
+    deltas: Vec<(u16, u8)>,
+}
+
+// Memory is the state a patch covers, the CPU RAM and the cartridge's PRG RAM, which is empty for
+// cartridges without any.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Memory {
+    pub ram: Vec<u8>,
+    pub prg_ram: Vec<u8>,
+}
+
+impl RamPatch {
+    pub fn diff(base: &Memory, state: &Memory) -> Self {
+        let mut deltas = diff(&base.ram, &state.ram, 0);
+        deltas.extend(diff(&base.prg_ram, &state.prg_ram, PRG_RAM_START));
+        RamPatch { deltas }
+    }
+
+    pub fn apply(&self, ram: &mut [u8], prg_ram: &mut [u8]) {
+        for &(addr, val) in &self.deltas {
+            let byte = if addr >= PRG_RAM_START {
+                prg_ram.get_mut((addr - PRG_RAM_START) as usize)
+            } else {
+                ram.get_mut(addr as usize)
+            };
+            if let Some(byte) = byte {
+                *byte = val;
+            }
+        }
+    }
+}
+
+// diff returns the bytes of state that differ from base, addressed from start.
+fn diff(base: &[u8], state: &[u8], start: u16) -> Vec<(u16, u8)> {
+    base.iter()
+        .zip(state.iter())
+        .enumerate()
+        .filter(|(_, (base, state))| base != state)
+        .map_while(|(offset, (_, state))| {
+            let addr = u16::try_from(offset).ok()?.checked_add(start)?;
+            Some((addr, *state))
+        })
+        .collect()
+}
+
+impl fmt::Display for RamPatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (addr, val) in &self.deltas {
+            writeln!(f, "{:04X}:{:02X}", addr, val)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RamPatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut deltas = vec![];
+        for line in s.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let mut parts = line.split(':');
+            let addr = parts.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+            let val = parts.next().and_then(|v| u8::from_str_radix(v, 16).ok());
+            match (addr, val) {
+                (Some(addr), Some(val)) => deltas.push((addr, val)),
+                _ => return Err(format!("invalid patch line: {}", line)),
+            }
+        }
+
+        Ok(RamPatch { deltas })
+    }
+}
+
+#[test]
+fn test_patch_roundtrip() {
+    let base = Memory {
+        ram: vec![0; 0x800],
+        prg_ram: vec![0; 0x2000],
+    };
+    let mut state = base.clone();
+    state.ram[0x0000] = 0x01;
+    state.ram[0x0300] = 0x42;
+    state.ram[0x07FF] = 0xFF;
+    state.prg_ram[0x0010] = 0x24;
+
+    let patch = RamPatch::diff(&base, &state);
+    assert!(patch.to_string().contains("6010:24"));
+    let patch: RamPatch = patch.to_string().parse().unwrap();
+
+    let mut patched = base.clone();
+    patch.apply(&mut patched.ram, &mut patched.prg_ram);
+    assert_eq!(patched, state);
+
+    assert!("0300".parse::<RamPatch>().is_err());
+}
@@ -0,0 +1,108 @@
+// a minimal PNG encoder for 8-bit RGB images. The image data is stored in uncompressed deflate
+// blocks, which keeps the encoder small at the cost of bigger files. See
+// https://www.w3.org/TR/PNG/ and https://tools.ietf.org/html/rfc1950.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+// the biggest payload a stored deflate block can hold.
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// encode returns the PNG file for an image given as rows of R, G, B bytes.
+pub fn encode(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3, "wrong image size");
+
+    let mut png = SIGNATURE.to_vec();
+
+    let mut ihdr = vec![];
+    ihdr.extend(&(width as u32).to_be_bytes());
+    ihdr.extend(&(height as u32).to_be_bytes());
+    // 8 bits per channel, truecolor, deflate, adaptive filtering and no interlacing.
+    ihdr.extend(&[8, 2, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+
+    // every row starts with its filter type, 0 meaning unfiltered.
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend(row);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(&crc.to_be_bytes());
+}
+
+// wraps data in a zlib stream made of stored deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate with a 32kb window and no preset dictionary.
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        out.push(last as u8);
+        let len = block.len() as u16;
+        out.extend(&len.to_le_bytes());
+        out.extend(&(!len).to_le_bytes());
+        out.extend(block);
+    }
+    out.extend(&adler32(data).to_be_bytes());
+    out
+}
+
+// crc32 computes the CRC-32 (IEEE 802.3) checksum of data.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// decodes a PNG file with the png crate, so the encoder is checked against an independent
+// decoder, returning the width, height and RGB bytes.
+#[cfg(test)]
+pub fn test_decode(png: &[u8]) -> (usize, usize, Vec<u8>) {
+    let mut reader = ::png::Decoder::new(png).read_info().unwrap();
+    let mut rgb = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut rgb).unwrap();
+    assert_eq!(info.color_type, ::png::ColorType::Rgb);
+    assert_eq!(info.bit_depth, ::png::BitDepth::Eight);
+    rgb.truncate(info.buffer_size());
+    (info.width as usize, info.height as usize, rgb)
+}
+
+#[test]
+fn test_checksums() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+}
+
+#[test]
+fn test_encode() {
+    let rgb: Vec<u8> = (0..300 * 100 * 3).map(|i| i as u8).collect();
+    let png = encode(300, 100, &rgb);
+    assert_eq!(test_decode(&png), (300, 100, rgb));
+}
@@ -0,0 +1,206 @@
+use super::{pack_rgb, unpack_rgb, PixelFormat, PPU, RGB, SCREEN_WIDTH};
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+// the composite signal is sampled 8 times per pixel, and the color subcarrier has a period of 12
+// samples. A scanline is 341 pixels long, so every line and every frame starts 4 samples further
+// into the subcarrier, which is what makes the artifacts crawl.
+const SAMPLES_PER_PIXEL: usize = 8;
+const SUBCARRIER_PERIOD: usize = 12;
+const PHASE_STEP: usize = 4;
+
+// the NTSC filter decodes 2 pixels out of every PPU pixel, as the signal holds more detail than a
+// single pixel can show.
+pub const NTSC_WIDTH: usize = SCREEN_WIDTH * 2;
+
+// Filter is the video filter the frame goes through before it's drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Filter {
+    #[default]
+    None,
+    // Ntsc encodes every row as a composite video signal and decodes it back like a TV would,
+    // blurring edges and bleeding colors into their neighbours.
+    Ntsc,
+}
+
+impl Filter {
+    // width returns the width of the filtered frame.
+    pub fn width(self) -> usize {
+        match self {
+            Filter::None => SCREEN_WIDTH,
+            Filter::Ntsc => NTSC_WIDTH,
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Filter::None),
+            "ntsc" => Ok(Filter::Ntsc),
+            _ => Err(format!("unknown filter: {}", s)),
+        }
+    }
+}
+
+// blit writes the current frame into dst like PPU::blit_into does, passing it through the filter
+// first. Each row of dst must have room for filter.width() pixels.
+pub fn blit(ppu: &PPU, filter: Filter, dst: &mut [u8], pitch: usize, format: PixelFormat) {
+    if filter == Filter::None {
+        ppu.blit_into(dst, pitch, format);
+        return;
+    }
+
+    let mut ntsc = Ntsc::new();
+    for (y, row) in ppu.screen.chunks(SCREEN_WIDTH * 3).enumerate() {
+        let phase = (ppu.frame as usize + y) * PHASE_STEP % SUBCARRIER_PERIOD;
+        let pixels = row
+            .chunks(3)
+            .map(|pixel| unpack_rgb(ppu.pixel_format, pixel));
+        let dst = &mut dst[y * pitch..y * pitch + NTSC_WIDTH * 3];
+        for (pixel, dst) in ntsc.row(pixels, phase).iter().zip(dst.chunks_mut(3)) {
+            dst.copy_from_slice(&pack_rgb(format, pixel));
+        }
+    }
+}
+
+// Ntsc runs rows of pixels through a composite encoder and decoder. The chroma is modulated onto
+// the luma, and separating them again over a whole subcarrier period smears both across
+// neighbouring pixels.
+struct Ntsc {
+    // the cosine and sine of the subcarrier at each of its phases.
+    carrier: [(f32, f32); SUBCARRIER_PERIOD],
+    // the samples of the current row, with half a subcarrier period of padding at both ends.
+    signal: Vec<f32>,
+    // the decoded pixels of the current row.
+    decoded: Vec<RGB>,
+}
+
+impl Ntsc {
+    fn new() -> Self {
+        let mut carrier = [(0.0, 0.0); SUBCARRIER_PERIOD];
+        for (t, entry) in carrier.iter_mut().enumerate() {
+            let angle = 2.0 * PI * t as f32 / SUBCARRIER_PERIOD as f32;
+            *entry = (angle.cos(), angle.sin());
+        }
+        Ntsc {
+            carrier,
+            signal: Vec::with_capacity(SCREEN_WIDTH * SAMPLES_PER_PIXEL + SUBCARRIER_PERIOD),
+            decoded: Vec::with_capacity(NTSC_WIDTH),
+        }
+    }
+
+    // row encodes and decodes a row of pixels, phase being the subcarrier phase of its first
+    // sample. The edge pixels extend past both ends of the row.
+    fn row(&mut self, pixels: impl Iterator<Item = RGB>, phase: usize) -> &[RGB] {
+        let pad = SUBCARRIER_PERIOD / 2;
+        // the sample at index i of the signal is sample i - pad of the row, whose subcarrier phase
+        // is offset by this much.
+        let offset = phase + SUBCARRIER_PERIOD - pad;
+        self.signal.clear();
+        let mut last = None;
+        for pixel in pixels {
+            let yiq = to_yiq(&pixel);
+            if last.is_none() {
+                self.encode(yiq, pad, offset);
+            }
+            self.encode(yiq, SAMPLES_PER_PIXEL, offset);
+            last = Some(yiq);
+        }
+        if let Some(yiq) = last {
+            self.encode(yiq, pad, offset);
+        }
+
+        let step = SAMPLES_PER_PIXEL * SCREEN_WIDTH / NTSC_WIDTH;
+        let n = SUBCARRIER_PERIOD as f32;
+        self.decoded.clear();
+        for x in 0..self.signal.len().saturating_sub(2 * pad) / step {
+            // the window is centered on the output pixel, so it starts half a period before it,
+            // which is right where the padded signal has it.
+            let start = x * step + step / 2;
+            let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+            let window = &self.signal[start..start + SUBCARRIER_PERIOD];
+            for (t, &sample) in window.iter().enumerate() {
+                let (cos, sin) = self.carrier[(start + t + offset) % SUBCARRIER_PERIOD];
+                y += sample;
+                i += sample * cos * 2.0;
+                q += sample * sin * 2.0;
+            }
+            self.decoded.push(from_yiq(y / n, i / n, q / n));
+        }
+        &self.decoded
+    }
+
+    // encode appends count samples of a color to the signal.
+    fn encode(&mut self, (y, i, q): (f32, f32, f32), count: usize, offset: usize) {
+        for _ in 0..count {
+            let (cos, sin) = self.carrier[(self.signal.len() + offset) % SUBCARRIER_PERIOD];
+            self.signal.push(y + i * cos + q * sin);
+        }
+    }
+}
+
+fn to_yiq(color: &RGB) -> (f32, f32, f32) {
+    let (r, g, b) = (color.r as f32, color.g as f32, color.b as f32);
+    (
+        0.299 * r + 0.587 * g + 0.114 * b,
+        0.596 * r - 0.274 * g - 0.322 * b,
+        0.211 * r - 0.523 * g + 0.312 * b,
+    )
+}
+
+fn from_yiq(y: f32, i: f32, q: f32) -> RGB {
+    let clamp = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+    RGB {
+        r: clamp(y + 0.956 * i + 0.621 * q),
+        g: clamp(y - 0.272 * i - 0.647 * q),
+        b: clamp(y - 1.106 * i + 1.703 * q),
+    }
+}
+
+#[test]
+fn test_ntsc_filter() {
+    use crate::cartridge::{test_rom, Cartridge};
+    use crate::ppu::Palette;
+    use crate::ppu::SCREEN_HEIGHT;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let cartridge = Rc::new(RefCell::new(
+        Cartridge::from_data(test_rom(0, 1, 1)).unwrap(),
+    ));
+    let mut ppu = PPU::new(cartridge, Palette::default());
+    ppu.pixel_format = PixelFormat::Rgb24;
+    // a flat gray frame with a white pixel at the start of the first row.
+    for (i, byte) in ppu.screen.iter_mut().enumerate() {
+        *byte = if i < 3 { 255 } else { 100 };
+    }
+
+    // without a filter the frame is blitted as is.
+    let pitch = SCREEN_WIDTH * 3;
+    let mut filtered = vec![0; pitch * SCREEN_HEIGHT];
+    let mut blitted = vec![0; pitch * SCREEN_HEIGHT];
+    blit(&ppu, Filter::None, &mut filtered, pitch, PixelFormat::Rgb24);
+    ppu.blit_into(&mut blitted, pitch, PixelFormat::Rgb24);
+    assert_eq!(filtered, blitted);
+
+    let pitch = Filter::Ntsc.width() * 3;
+    let mut filtered = vec![0; pitch * SCREEN_HEIGHT];
+    blit(&ppu, Filter::Ntsc, &mut filtered, pitch, PixelFormat::Rgb24);
+    assert_eq!(Filter::Ntsc.width(), 512);
+    // flat colors decode back to themselves, give or take rounding.
+    let row = &filtered[pitch..2 * pitch];
+    assert!(
+        row.iter().all(|&c| (c as i32 - 100).abs() <= 1),
+        "{:?}",
+        row
+    );
+    // the white pixel bleeds into its neighbours, and its sharp edge shows up as artifact colors.
+    let row = &filtered[..pitch];
+    let luma = |x: usize| to_yiq(&unpack_rgb(PixelFormat::Rgb24, &row[x * 3..])).0;
+    assert!(luma(2) > 120.0 && luma(2) < 200.0, "{}", luma(2));
+    assert_ne!(row[2 * 3], row[2 * 3 + 2]);
+    assert!(row[4 * 3..].iter().all(|&c| (c as i32 - 100).abs() <= 1));
+}
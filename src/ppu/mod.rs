@@ -1,40 +1,144 @@
+pub mod filter;
+mod palette;
 mod register;
+pub mod viewer;
 
-use crate::cartridge::Cartridge;
-use crate::{
-    cpu::CPU,
-    nes::{SCREEN_HEIGHT, SCREEN_WIDTH},
-};
-use register::{AddressLatch, Register};
+#[cfg(test)]
+use crate::cartridge::test_rom;
+use crate::cartridge::{Cartridge, Mirroring};
+pub use palette::Palette;
+use register::{AddressLatch, Loopy, Register};
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
 
 const VBLANK_SCANLINE: u16 = 241;
-const LAST_SCANLINE: u16 = 261;
+const DOTS_PER_SCANLINE: u16 = 341;
 const PIXEL_COUNT: usize = (SCREEN_HEIGHT * SCREEN_WIDTH * 3) as usize;
-const CYCLES_PER_SCANLINE: u64 = 114; // 29781 cycles per frame / 261 scanlines
-static PALETTE: [u8; 192] = [
-    124, 124, 124, 0, 0, 252, 0, 0, 188, 68, 40, 188, 148, 0, 132, 168, 0, 32, 168, 16, 0, 136, 20,
-    0, 80, 48, 0, 0, 120, 0, 0, 104, 0, 0, 88, 0, 0, 64, 88, 0, 0, 0, 0, 0, 0, 0, 0, 0, 188, 188,
-    188, 0, 120, 248, 0, 88, 248, 104, 68, 252, 216, 0, 204, 228, 0, 88, 248, 56, 0, 228, 92, 16,
-    172, 124, 0, 0, 184, 0, 0, 168, 0, 0, 168, 68, 0, 136, 136, 0, 0, 0, 0, 0, 0, 0, 0, 0, 248,
-    248, 248, 60, 188, 252, 104, 136, 252, 152, 120, 248, 248, 120, 248, 248, 88, 152, 248, 120,
-    88, 252, 160, 68, 248, 184, 0, 184, 248, 24, 88, 216, 84, 88, 248, 152, 0, 232, 216, 120, 120,
-    120, 0, 0, 0, 0, 0, 0, 252, 252, 252, 164, 228, 252, 184, 184, 248, 216, 184, 248, 248, 184,
-    248, 248, 164, 192, 240, 208, 176, 252, 224, 168, 248, 216, 120, 216, 248, 120, 184, 248, 184,
-    184, 248, 216, 0, 252, 252, 248, 216, 248, 0, 0, 0, 0, 0, 0,
-];
 
 const SPRITE_PALETTE_OFFSET: usize = 16;
 const PALETTE_BASE: usize = 0x3F00;
+// the color emphasis bits of PPUMASK darken the channels that are not emphasized by this much.
+const EMPHASIS_ATTENUATION: f32 = 0.816;
 
-#[derive(Default)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 struct RGB {
     r: u8,
     g: u8,
     b: u8,
 }
 
+// PixelFormat is the byte order in which each pixel is written to the screen buffer, it must match
+// the format of the texture the screen is drawn into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelFormat {
+    Bgr24,
+    Rgb24,
+}
+
+impl PixelFormat {
+    pub fn swapped(self) -> Self {
+        match self {
+            PixelFormat::Bgr24 => PixelFormat::Rgb24,
+            PixelFormat::Rgb24 => PixelFormat::Bgr24,
+        }
+    }
+}
+
+impl FromStr for PixelFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bgr24" => Ok(PixelFormat::Bgr24),
+            "rgb24" => Ok(PixelFormat::Rgb24),
+            _ => Err(format!("unknown pixel format: {}", s)),
+        }
+    }
+}
+
+// Region selects the video timing of the console. PAL consoles have 50 more scanlines per frame,
+// run at 50Hz, and their PPU runs 3.2 dots per CPU cycle rather than 3.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+}
+
+impl Region {
+    // the pre-render scanline, which ends the frame.
+    fn last_scanline(self) -> u16 {
+        match self {
+            Region::Ntsc => 261,
+            Region::Pal => 311,
+        }
+    }
+
+    // the PPU dots run in the given number of CPU cycles.
+    fn dots(self, cpu_cycles: u64) -> u64 {
+        match self {
+            Region::Ntsc => cpu_cycles * 3,
+            Region::Pal => cpu_cycles * 16 / 5,
+        }
+    }
+
+    // the time it takes to render a frame, 60.0988Hz on NTSC and 50.007Hz on PAL.
+    pub fn frame_time(self) -> Duration {
+        match self {
+            Region::Ntsc => Duration::from_nanos(16_639_267),
+            Region::Pal => Duration::from_nanos(19_997_200),
+        }
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            _ => Err(format!("unknown region: {}", s)),
+        }
+    }
+}
+
+// returns the 2 bit color of the pixel at the given bit of a row of a tile, given the row's two
+// pattern table planes.
+fn color_index(lo: u8, hi: u8, bit: u8) -> u16 {
+    let (lsb, msb) = ((lo >> bit) & 0x01, (hi >> bit) & 0x01);
+    (lsb | msb << 1) as u16
+}
+
+// packs a color into the three bytes of a pixel in the given format.
+fn pack_rgb(format: PixelFormat, color: &RGB) -> [u8; 3] {
+    match format {
+        PixelFormat::Bgr24 => [color.b, color.g, color.r],
+        PixelFormat::Rgb24 => [color.r, color.g, color.b],
+    }
+}
+
+fn unpack_rgb(format: PixelFormat, pixel: &[u8]) -> RGB {
+    match format {
+        PixelFormat::Bgr24 => RGB {
+            r: pixel[2],
+            g: pixel[1],
+            b: pixel[0],
+        },
+        PixelFormat::Rgb24 => RGB {
+            r: pixel[0],
+            g: pixel[1],
+            b: pixel[2],
+        },
+    }
+}
+
+#[derive(PartialEq)]
 enum SpritePriority {
     Front,
     Back,
@@ -54,11 +158,20 @@ struct SpritePixel {
     sprite_zero: bool,
 }
 
+struct BackgroundPixel {
+    color: RGB,
+    // transparent pixels show the backdrop color, and never trigger a sprite zero hit.
+    opaque: bool,
+}
+
 struct Sprite {
     x: u8,
     y: u8,
     attributes: u8,
     tile_index: u8,
+    // sprite_zero is set for the sprite at the start of OAM, the only one that can set the sprite
+    // zero hit flag.
+    sprite_zero: bool,
 }
 
 impl Sprite {
@@ -76,9 +189,9 @@ impl Sprite {
 
     fn flip(&self) -> Flip {
         match (self.attributes & 0xC0) >> 6 {
-            0x01 => Flip::Horizontal,
-            0x10 => Flip::Vertical,
-            0x11 => Flip::Both,
+            1 => Flip::Horizontal,
+            2 => Flip::Vertical,
+            3 => Flip::Both,
             _ => Flip::None,
         }
     }
@@ -89,9 +202,13 @@ pub struct PPU {
     ppumask: u8,
     ppustatus: u8,
     oamaddr: u8,
-    ppuscroll: u16,
-    ppuaddr: u16,
-    cycles: u64,
+    // loopy holds the current VRAM address and scroll position.
+    loopy: Loopy,
+    // dots counts the PPU dots run since reset, the PPU catches up with the CPU by running the
+    // dots that fit in the CPU cycles elapsed.
+    dots: u64,
+    // dot is the position within the current scanline, from 0 to 340.
+    dot: u16,
     has_blanked: bool,
     // nametables is an array with 4 individual nametables, each one of them contains a value that
     // represents an index into the pattern table, which holds the sprite for each tile in the
@@ -104,10 +221,14 @@ pub struct PPU {
     // oam contains the addresses for the foreground sprites.
     oam: [u8; 0x100],
 
+    // address_latch is the write toggle shared by PPUSCROLL and PPUADDR.
     address_latch: AddressLatch,
-    // TODO: I think address and scroll share the same latch.
-    // scroll_latch: AddressLatch,
     scanline: u16,
+    // the sprites and background tiles of the current scanline, fetched at its start.
+    scanline_sprites: Vec<Sprite>,
+    scanline_tiles: [u8; 66],
+    // the background palette of each of the scanline's tiles.
+    scanline_palettes: [u8; 33],
 
     cartridge: Rc<RefCell<Cartridge>>,
 
@@ -115,21 +236,44 @@ pub struct PPU {
     // pixels, for a total of (32 * 8  * 30 * 8) = (256 * 240) = PIXEL_COUNT.
     pub screen: [u8; PIXEL_COUNT],
     pub frame_complete: bool,
+    // frame_ended is set when a frame completes while catching up for a register access, and is
+    // reported by the next step.
+    frame_ended: bool,
+    // nmi is set when vblank raises an NMI, and is reported by the next step.
+    nmi: bool,
+    // frame counts the frames rendered since power on.
+    frame: u64,
     ppudata_buffer: u8,
+    // vblank_suppressed is set by a PPUSTATUS read racing the vblank flag, which keeps the flag
+    // and the NMI from being raised for that frame.
+    vblank_suppressed: bool,
+    // io_latch holds the last value written to or read from a register, which reads of the write
+    // only registers and of the unused bits of PPUSTATUS return.
+    io_latch: u8,
+    pixel_format: PixelFormat,
+    palette: Palette,
+    region: Region,
 }
 
 impl PPU {
-    pub fn new(cartridge: Rc<RefCell<Cartridge>>) -> Self {
+    pub fn new(cartridge: Rc<RefCell<Cartridge>>, palette: Palette) -> Self {
+        // the power-on state, see https://wiki.nesdev.com/w/index.php/PPU_power_up_state. The
+        // vblank and sprite overflow flags are usually found set.
         PPU {
-            ppuctrl: 0x10,
+            ppuctrl: 0,
             ppumask: 0,
-            ppustatus: 0x10,
-            oamaddr: 0x01,
-            ppuscroll: 0,
-            ppuaddr: 0x0001,
+            ppustatus: 0xA0,
+            oamaddr: 0,
+            loopy: Loopy::default(),
             address_latch: AddressLatch::HI,
             scanline: 0,
+            scanline_sprites: vec![],
+            scanline_tiles: [0; 66],
+            scanline_palettes: [0; 33],
             frame_complete: false,
+            frame_ended: false,
+            nmi: false,
+            frame: 0,
 
             nametables: [0; 0x0400 * 4],
             palette_ram_idx: [0; 0x20],
@@ -138,41 +282,141 @@ impl PPU {
             cartridge,
 
             has_blanked: false,
-            cycles: 0,
+            dots: 0,
+            dot: 0,
             ppudata_buffer: 0,
+            vblank_suppressed: false,
+            io_latch: 0,
+            pixel_format: PixelFormat::Bgr24,
+            palette,
+            region: Region::default(),
         }
     }
 
-    pub fn tick(&mut self, cpu: &mut CPU) {
+    // reset clears what the console's reset button clears: PPUCTRL, PPUMASK, the scroll position
+    // and the write latch. The vblank flag, OAMADDR, PPUADDR (v), VRAM and OAM are left untouched.
+    pub fn reset(&mut self) {
+        self.ppuctrl = 0;
+        self.ppumask = 0;
+        self.ppustatus &= 0x80;
+        self.loopy.t = 0;
+        self.loopy.x = 0;
+        self.address_latch = AddressLatch::HI;
+        self.ppudata_buffer = 0;
+        self.scanline = 0;
+        self.dots = 0;
+        self.dot = 0;
+        self.has_blanked = false;
         self.frame_complete = false;
+        self.frame_ended = false;
+        self.nmi = false;
+    }
 
-        loop {
-            if self.cycles + CYCLES_PER_SCANLINE > cpu.cycles {
-                break;
-            }
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
 
-            if self.scanline < (SCREEN_HEIGHT as u16) {
-                self.render_scanline();
-            }
+    pub fn set_pixel_format(&mut self, format: PixelFormat) {
+        self.pixel_format = format;
+    }
 
-            self.scanline += 1;
+    // step catches the PPU up with the CPU, given the CPU cycles elapsed since reset, and returns
+    // whether it raised an NMI. It runs 3 dots per CPU cycle, 3.2 on PAL, so register accesses,
+    // which catch the PPU up to the cycle they happen on, take effect at the right pixel. The PPU
+    // never calls back into the CPU, so the CPU is free to access the PPU and cartridge through
+    // the bus while servicing it.
+    pub fn step(&mut self, cpu_cycles: u64) -> bool {
+        self.catch_up(cpu_cycles);
+        self.frame_complete = std::mem::take(&mut self.frame_ended);
+        std::mem::take(&mut self.nmi)
+    }
+
+    // poll_nmi catches the PPU up to the given CPU cycle and returns whether it raised an NMI
+    // since the last step, which step then no longer reports. The CPU polls it while servicing an
+    // interrupt, where an NMI can hijack the sequence.
+    pub fn poll_nmi(&mut self, cpu_cycle: u64) -> bool {
+        self.catch_up(cpu_cycle);
+        std::mem::take(&mut self.nmi)
+    }
 
-            if self.scanline == VBLANK_SCANLINE {
-                self.set_vblank(true);
+    // frame returns the number of frames rendered since power on.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // dots returns the number of dots run since reset.
+    pub fn dots(&self) -> u64 {
+        self.dots
+    }
+
+    fn catch_up(&mut self, cpu_cycles: u64) {
+        let dots = self.region.dots(cpu_cycles);
+        while self.dots < dots {
+            self.step_dot();
+        }
+    }
+
+    // step_dot runs a single dot. Visible scanlines fetch their sprites and tiles on dot 0 and
+    // output a pixel on each of dots 1 to 256, see https://wiki.nesdev.com/w/index.php/PPU_rendering.
+    fn step_dot(&mut self) {
+        let visible = self.scanline < SCREEN_HEIGHT as u16;
+        let pre_render = self.scanline == self.region.last_scanline();
+
+        match self.dot {
+            0 if visible => self.fetch_scanline(),
+            1..=256 if visible => self.render_pixel(self.dot as usize - 1),
+            1 if pre_render => {
+                self.set_vblank(false);
+                self.set_sprite_overflow(false);
                 self.ppustatus &= 0xBF;
-                if self.vblank_nmi() {
-                    cpu.nmi();
+            }
+            1 if self.scanline == VBLANK_SCANLINE => {
+                if !self.vblank_suppressed {
+                    self.set_vblank(true);
+                    self.nmi |= self.vblank_nmi();
                 }
-            } else if self.scanline == LAST_SCANLINE {
-                self.frame_complete = true;
-                self.scanline = 0;
-                self.set_vblank(false);
+                self.vblank_suppressed = false;
+            }
+            _ => {}
+        }
+
+        if (visible || pre_render) && self.rendering_enabled() {
+            match self.dot {
+                256 => self.loopy.increment_y(),
+                257 => self.loopy.copy_x(),
+                260 => self.cartridge.borrow_mut().notify_scanline(),
+                // the pre-render scanline reloads the scroll position for the next frame.
+                280 if pre_render => self.loopy.copy_y(),
+                _ => {}
             }
+        }
 
-            self.cycles += CYCLES_PER_SCANLINE;
+        self.dots += 1;
+        self.dot += 1;
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > self.region.last_scanline() {
+                self.scanline = 0;
+                self.frame_ended = true;
+                self.frame = self.frame.wrapping_add(1);
+            }
         }
     }
 
+    pub fn set_oam(&mut self, data: &[u8; 0x100]) {
+        self.oam = *data;
+    }
+
+    #[cfg(test)]
+    pub fn oam(&self) -> &[u8; 0x100] {
+        &self.oam
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
     pub fn vblank_nmi(&self) -> bool {
         self.ppuctrl & 0x80 != 0
     }
@@ -181,6 +425,10 @@ impl PPU {
         self.ppustatus |= 0x40;
     }
 
+    fn rendering_enabled(&self) -> bool {
+        self.render_background() || self.render_sprites()
+    }
+
     fn render_background(&self) -> bool {
         self.ppumask & 0x08 > 0
     }
@@ -205,6 +453,15 @@ impl PPU {
         }
     }
 
+    // sprites are either 8x8 or 8x16 pixels.
+    fn sprite_height(&self) -> u16 {
+        if self.ppuctrl & 0x20 == 0 {
+            8
+        } else {
+            16
+        }
+    }
+
     fn background_offset(&self) -> u16 {
         if self.ppuctrl & 0x10 == 0 {
             0
@@ -215,83 +472,99 @@ impl PPU {
 
     fn set_sprite_overflow(&mut self, val: bool) {
         if val {
-            self.ppustatus |= 0x40;
+            self.ppustatus |= 0x20;
         } else {
-            self.ppustatus &= !0x40;
+            self.ppustatus &= !0x20;
         }
     }
 
-    fn base_nametable(&self) -> u16 {
-        match self.ppuctrl & 0x03 {
-            0x00 => 0x2000,
-            0x01 => 0x2400,
-            0x02 => 0x2800,
-            0x03 => 0x2C00,
-            _ => unreachable!(),
-        }
+    // pre-fetches both sprite and background tile data for the current scanline.
+    fn fetch_scanline(&mut self) {
+        self.scanline_sprites = self.get_scanline_sprite_pixels();
+        self.scanline_tiles = self.get_scanline_background_pixels();
+        self.scanline_palettes = self.get_scanline_palettes();
     }
 
-    // walks through the nametable to get the correct sprite index, then fetches that sprite from
-    // the chr_rom and pushes the corresponding line of pixels into the screen.
+    // renders a whole scanline at once, using the registers as they are at its start.
+    #[cfg(test)]
     fn render_scanline(&mut self) {
-        // pre-fetch both sprite and background tile data for this scanline.
-        let visible_sprites = self.get_scanline_sprite_pixels();
-        let scanline_tiles = self.get_scanline_background_pixels();
-
+        self.fetch_scanline();
         for x in 0..SCREEN_WIDTH {
-            let bg_pixel = self.get_background_pixel(&scanline_tiles, x as u8);
-            let fg_pixel = self.get_sprite_pixel(&visible_sprites, x as u8);
-            if let Some(ref fg_pixel) = fg_pixel {
-                if fg_pixel.sprite_zero {
-                    self.set_sprite_zero_hit();
-                }
+            self.render_pixel(x);
+        }
+    }
+
+    // walks through the nametable to get the correct sprite index, then fetches that sprite from
+    // the chr_rom and pushes the corresponding pixel into the screen.
+    fn render_pixel(&mut self, x: usize) {
+        let bg_pixel =
+            self.get_background_pixel(&self.scanline_tiles, &self.scanline_palettes, x as u8);
+        let fg_pixel = self.get_sprite_pixel(&self.scanline_sprites, x as u8);
+        // a hit needs opaque sprite zero and background pixels at the same x, and never happens
+        // at x=255.
+        if let (Some(bg), Some(fg)) = (&bg_pixel, &fg_pixel) {
+            if bg.opaque && fg.sprite_zero && x != 255 {
+                self.set_sprite_zero_hit();
             }
+        }
 
-            let pixel = match (bg_pixel, fg_pixel) {
-                (None, None) => continue,
-                (None, Some(fg)) => fg.color,
-                (Some(bg), None) => bg,
-                (
-                    Some(bg),
-                    Some(SpritePixel {
-                        priority: SpritePriority::Back,
-                        ..
-                    }),
-                ) => bg,
-                (
-                    Some(_),
-                    Some(SpritePixel {
-                        color,
-                        priority: SpritePriority::Front,
-                        ..
-                    }),
-                ) => color,
-            };
-
-            let scanline = self.scanline as usize;
-            self.set_pixel(x as usize, scanline, pixel);
-        }
-    }
-
-    // returns an array of 64 bytes, each representing a row of a background tile that is visible
-    // on the current scanline.
-    fn get_scanline_background_pixels(&mut self) -> [u8; 64] {
-        let mut out = [0; 64];
-
-        for i in 0..32 {
-            // each sprite is 8 pixels wide, so the chr index in the scanline is the position of
-            // the pixel in the scanline divided by 8.
-            let chr_idx = i as u16 % 32 + ((self.scanline as u16 / 8) % 32) * 32;
+        // the sprite pixel comes from the lowest indexed opaque sprite, which is then drawn
+        // behind the background only if it has the back priority and the background is opaque.
+        // This way a back priority sprite hidden by the background still hides any higher indexed
+        // sprite under it, whatever their priority.
+        // with both hidden, as in the left column when clipped, the pixel takes the backdrop color.
+        let pixel = match (bg_pixel, fg_pixel) {
+            (None, None) => self.color(PALETTE_BASE as u16),
+            (Some(bg), Some(fg)) if fg.priority == SpritePriority::Back && bg.opaque => bg.color,
+            (_, Some(fg)) => fg.color,
+            (Some(bg), None) => bg.color,
+        };
+
+        let scanline = self.scanline as usize;
+        self.set_pixel(x, scanline, pixel);
+    }
+
+    // returns an array of 66 bytes, each pair representing a row of one of the 33 background
+    // tiles that are at least partially visible on the current scanline, starting at the scroll
+    // position in v. v's coarse and fine Y are advanced at the end of every scanline, rolling
+    // over into the next nametable after row 29 as nametables are 32x30 tiles.
+    fn get_scanline_background_pixels(&mut self) -> [u8; 66] {
+        let mut out = [0; 66];
+
+        let mut v = Loopy {
+            v: self.loopy.v,
+            ..Default::default()
+        };
+        for i in 0..33 {
             // read the chr_address from the nametable
-            let base = self.base_nametable();
-            let chr_address = 16 * self.readb(base + chr_idx) as u16;
-            let chr_address = chr_address + self.scanline % 8;
+            let chr_address = 16 * self.readb(v.tile_address()) as u16;
+            let chr_address = chr_address + v.fine_y();
             let chr_address = chr_address + self.background_offset();
 
             // load the two planes of the current tile's line
             let cartridge = self.cartridge.borrow();
             out[2 * i] = cartridge.read(chr_address);
             out[(2 * i) + 1] = cartridge.read(chr_address + 8);
+            v.increment_x();
+        }
+
+        out
+    }
+
+    // returns the background palette of each of the 33 tiles fetched by
+    // get_scanline_background_pixels. The palette is picked from the tile's attribute byte by
+    // its position in the nametable, so it scrolls along with the tile.
+    fn get_scanline_palettes(&self) -> [u8; 33] {
+        let mut out = [0; 33];
+
+        let mut v = Loopy {
+            v: self.loopy.v,
+            ..Default::default()
+        };
+        for palette in out.iter_mut() {
+            let attr_byte = self.readb(v.attribute_address());
+            *palette = (attr_byte >> v.attribute_shift()) & 0x03;
+            v.increment_x();
         }
 
         out
@@ -303,7 +576,7 @@ impl PPU {
             let i = i * 4;
             let sprite_y = self.oam[i].wrapping_add(1);
             let y = self.scanline;
-            if y < sprite_y as u16 + 8 && y >= sprite_y as u16 {
+            if y < sprite_y as u16 + self.sprite_height() && y >= sprite_y as u16 {
                 let sprite = Sprite {
                     // sprite data is delayed by one scanline, so we must add 1 to the y position
                     // of each sprite. See https://wiki.nesdev.com/w/index.php/PPU_OAM for more
@@ -312,13 +585,15 @@ impl PPU {
                     tile_index: self.oam[i + 1],
                     attributes: self.oam[i + 2],
                     x: self.oam[i + 3],
+                    sprite_zero: i == 0,
                 };
 
-                if out.len() > 8 {
+                // only 8 sprites fit in a scanline, finding a 9th one sets the overflow flag.
+                if out.len() == 8 {
                     self.set_sprite_overflow(true);
-                } else {
-                    out.push(sprite);
+                    break;
                 }
+                out.push(sprite);
             }
         }
 
@@ -336,12 +611,20 @@ impl PPU {
             if x >= sprite.x && x < sprite.x.wrapping_add(8) {
                 let flip = sprite.flip();
 
-                let chr_address = sprite.tile_index as u16 + self.foreground_offset();
-                let y = y - sprite.y as u16;
-                let mut chr_address = 16 * chr_address + y;
+                let height = self.sprite_height();
+                let mut row = y - sprite.y as u16;
                 if flip == Flip::Both || flip == Flip::Vertical {
-                    chr_address = 7 - chr_address;
+                    row = height - 1 - row;
                 }
+                let chr_address = if height == 16 {
+                    // tall sprites pick the pattern table with bit 0 of the tile index, and are
+                    // made of an even tile for the top half and the next one for the bottom.
+                    let table = (sprite.tile_index as u16 & 0x01) * 0x1000;
+                    let tile = (sprite.tile_index as u16 & 0xFE) + row / 8;
+                    16 * tile + table + row % 8
+                } else {
+                    16 * sprite.tile_index as u16 + self.foreground_offset() + row
+                };
                 // load the two planes of the current tile's line
                 let chr_left = cartridge.read(chr_address);
                 let chr_right = cartridge.read(chr_address + 8);
@@ -352,8 +635,7 @@ impl PPU {
                 } else {
                     7 - (x % 8)
                 };
-                let (lsb, msb) = ((chr_left >> bit) & 0x01, (chr_right >> bit) & 0x01);
-                let color_idx = (lsb | msb << 1) as u16;
+                let color_idx = color_index(chr_left, chr_right, bit);
                 if color_idx == 0 {
                     continue;
                 }
@@ -363,15 +645,10 @@ impl PPU {
                     + SPRITE_PALETTE_OFFSET
                     + palette_index as usize
                     + color_idx as usize;
-                let color_addr = self.readb(palette_addr as u16) as usize & 0x3F;
                 return Some(SpritePixel {
-                    color: RGB {
-                        r: PALETTE[color_addr * 3],
-                        g: PALETTE[color_addr * 3 + 1],
-                        b: PALETTE[color_addr * 3 + 2],
-                    },
+                    color: self.color(palette_addr as u16),
                     priority: sprite.priority(),
-                    sprite_zero: chr_address < 0x03,
+                    sprite_zero: sprite.sprite_zero,
                 });
             } else {
                 continue;
@@ -381,46 +658,72 @@ impl PPU {
         None
     }
 
-    // takes a &[u8; 64], representing the pixels for the current scanline, and returns the pixel
+    // takes a &[u8; 66], representing the pixels for the current scanline, and returns the pixel
     // color that should be display at position (x, scanline).
-    fn get_background_pixel(&self, tiles: &[u8; 64], x: u8) -> Option<RGB> {
+    fn get_background_pixel(
+        &self,
+        tiles: &[u8; 66],
+        palettes: &[u8; 33],
+        x: u8,
+    ) -> Option<BackgroundPixel> {
         if !self.render_background() || (!self.render_background_leftmost() && x < 8) {
             return None;
         }
 
-        let index = (x as usize / 8) * 2;
-        let chr_left = tiles[index];
-        let chr_right = tiles[index + 1];
+        // the first tile is shifted to the left by the fine X scroll.
+        let pixel = x as usize + self.loopy.x as usize;
+        let tile = pixel / 8;
+        let chr_left = tiles[tile * 2];
+        let chr_right = tiles[tile * 2 + 1];
 
-        let bit = 7 - (x % 8);
-        let (lsb, msb) = ((chr_left >> bit) & 0x01, (chr_right >> bit) & 0x01);
-        let color_idx = (lsb | msb << 1) as u16;
+        let bit = 7 - (pixel % 8) as u8;
+        let color_idx = color_index(chr_left, chr_right, bit);
 
-        let attr_byte = self.get_attr_byte(x, self.scanline);
-        let (left, top) = (x % 32 < 16, self.scanline % 32 < 16);
-        let palette_offset = match (left, top) {
-            (true, true) => attr_byte & 0x03,
-            (false, true) => (attr_byte >> 2) & 0x03,
-            (true, false) => (attr_byte >> 4) & 0x03,
-            (false, false) => (attr_byte >> 6) & 0x03,
-        };
-        let palette_index = palette_offset << 2;
+        let palette_index = palettes[tile] << 2;
         debug_assert!(palette_index as u16 | color_idx < 0x20);
 
         let palette_addr = PALETTE_BASE + palette_index as usize + color_idx as usize;
-        let color_addr = self.readb(palette_addr as u16) as usize & 0x3F;
-        Some(RGB {
-            r: PALETTE[color_addr * 3],
-            g: PALETTE[color_addr * 3 + 1],
-            b: PALETTE[color_addr * 3 + 2],
+        Some(BackgroundPixel {
+            color: self.color(palette_addr as u16),
+            opaque: color_idx != 0,
         })
     }
 
-    fn get_attr_byte(&self, x: u8, y: u16) -> u8 {
-        let x = x as u16 / 32;
-        let y = y / 32;
-        let base = self.base_nametable();
-        self.readb(base + 0x3C0 + x + (y * 8))
+    // color looks up the color at the given palette RAM address, applying the grayscale and color
+    // emphasis bits of PPUMASK.
+    fn color(&self, palette_addr: u16) -> RGB {
+        let mut color_addr = self.readb(palette_addr) as usize & 0x3F;
+        // grayscale keeps only the luminance, which is the gray column of the palette.
+        if self.ppumask & 0x01 > 0 {
+            color_addr &= 0x30;
+        }
+
+        let mut color = self.palette.color(color_addr);
+
+        let emphasis = self.ppumask >> 5;
+        if emphasis != 0 {
+            let attenuate = |c: u8| (c as f32 * EMPHASIS_ATTENUATION) as u8;
+            if emphasis & 0x01 == 0 {
+                color.r = attenuate(color.r);
+            }
+            if emphasis & 0x02 == 0 {
+                color.g = attenuate(color.g);
+            }
+            if emphasis & 0x04 == 0 {
+                color.b = attenuate(color.b);
+            }
+        }
+        color
+    }
+
+    // nametable reads a byte of the nametables at an address in the $2000-$2FFF range, following
+    // the cartridge's mirroring.
+    pub fn nametable(&self, addr: u16) -> u8 {
+        self.nametables[self.nametable_index(addr as usize)]
+    }
+
+    pub fn palette_ram(&self) -> &[u8; 0x20] {
+        &self.palette_ram_idx
     }
 
     // pub fn get_vblank(&mut self) -> bool {
@@ -437,10 +740,42 @@ impl PPU {
         }
     }
 
+    // blit_into writes the current frame into dst, where each row starts pitch bytes after the
+    // previous one, using the given pixel format. pitch must fit a row of SCREEN_WIDTH * 3 bytes
+    // and dst must hold SCREEN_HEIGHT rows, the last one needing no padding. Panics otherwise.
+    pub fn blit_into(&self, dst: &mut [u8], pitch: usize, format: PixelFormat) {
+        let row_len = SCREEN_WIDTH * 3;
+        assert!(
+            pitch >= row_len,
+            "blit_into pitch of {} bytes is shorter than a {} byte row",
+            pitch,
+            row_len
+        );
+        let len = pitch * (SCREEN_HEIGHT - 1) + row_len;
+        assert!(
+            dst.len() >= len,
+            "blit_into buffer of {} bytes is shorter than the {} bytes of a frame",
+            dst.len(),
+            len
+        );
+        for (y, row) in self.screen.chunks(row_len).enumerate() {
+            let dst = &mut dst[y * pitch..y * pitch + row_len];
+            if format == self.pixel_format {
+                dst.copy_from_slice(row);
+                continue;
+            }
+
+            for (src, dst) in row.chunks(3).zip(dst.chunks_mut(3)) {
+                let color = unpack_rgb(self.pixel_format, src);
+                dst.copy_from_slice(&pack_rgb(format, &color));
+            }
+        }
+    }
+
     fn set_pixel(&mut self, x: usize, y: usize, val: RGB) {
-        self.screen[(y * SCREEN_WIDTH + x) * 3] = val.b;
-        self.screen[(y * SCREEN_WIDTH + x) * 3 + 1] = val.g;
-        self.screen[(y * SCREEN_WIDTH + x) * 3 + 2] = val.r;
+        let offset = (y * SCREEN_WIDTH + x) * 3;
+        let pixel = pack_rgb(self.pixel_format, &val);
+        self.screen[offset..offset + 3].copy_from_slice(&pixel);
     }
 
     fn map_addr(addr: u16) -> u16 {
@@ -452,15 +787,32 @@ impl PPU {
         }
     }
 
+    // maps an address in the $2000-$2FFF range into the nametables array, following the
+    // cartridge's mirroring.
+    fn nametable_index(&self, addr: usize) -> usize {
+        let table = (addr - 0x2000) / 0x0400;
+        let table = match self.cartridge.borrow().mirroring() {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::FourScreen => table,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        };
+        table * 0x0400 + addr % 0x0400
+    }
+
     fn readb(&self, addr: u16) -> u8 {
         let addr = PPU::map_addr(addr) as usize;
         match addr {
             // addresses 0x0000 to 0x1FFF are mapped to the pattern table, which can reside in the
             // PPU RAM or the cartridge's ROM.
             0x0000..=0x1FFF => self.cartridge.borrow().read(addr as u16),
-            0x2000..=0x2FFF => self.nametables[addr % 0x0400],
+            0x2000..=0x2FFF => self.nametables[self.nametable_index(addr)],
             0x3F00..=0x3F1F => self.palette_ram_idx[addr % 0x0020],
-            _ => unimplemented!("PPU::readb at {:X}", addr),
+            _ => {
+                log::error!("PPU read of unmapped address ${:04X}", addr);
+                0
+            }
         }
     }
 
@@ -468,59 +820,116 @@ impl PPU {
         let addr = PPU::map_addr(addr) as usize;
         match addr {
             0x0000..=0x1FFF => self.cartridge.borrow_mut().write(addr as u16, val),
-            0x2000..=0x2FFF => self.nametables[addr % 0x0400] = val,
+            0x2000..=0x2FFF => {
+                let idx = self.nametable_index(addr);
+                self.nametables[idx] = val;
+            }
             0x3F00..=0x3F1F => self.palette_ram_idx[addr % 0x0020] = val,
-            _ => unimplemented!("PPU::writeb at {:X}", addr),
+            _ => log::error!("PPU write of unmapped address ${:04X}", addr),
         }
     }
 
     fn incr_ppuaddr(&mut self) {
         let inc = if (self.ppuctrl & 0x04) == 0 { 1 } else { 32 };
-        self.ppuaddr = self.ppuaddr.wrapping_add(inc as u16);
+        self.loopy.v = self.loopy.v.wrapping_add(inc as u16) & 0x7FFF;
     }
 
-    pub fn read(&mut self, addr: u16) -> u8 {
-        debug_assert!(addr <= 7);
+    // read_at reads a register at the given CPU cycle, once the PPU has caught up to it. A
+    // PPUSTATUS read on the cycle the vblank flag is due sees the flag clear and suppresses the
+    // flag and the NMI for that frame, see
+    // https://wiki.nesdev.com/w/index.php/PPU_frame_timing#VBL_Flag_Timing.
+    pub fn read_at(&mut self, addr: u16, cpu_cycle: u64) -> u8 {
+        self.catch_up(cpu_cycle);
+        if addr == Register::PPUSTATUS as u16 && self.vblank_due(cpu_cycle) {
+            self.vblank_suppressed = true;
+        }
+        self.read(addr)
+    }
 
-        let reg: Register = (addr as usize).into();
-        match reg {
-            Register::PPUCTRL => self.ppuctrl,
-            Register::PPUMASK => self.ppumask,
+    // write_at writes a register at the given CPU cycle, once the PPU has caught up to it.
+    pub fn write_at(&mut self, addr: u16, val: u8, cpu_cycle: u64) {
+        self.catch_up(cpu_cycle);
+        self.write(addr, val);
+    }
+
+    // vblank_due returns whether the vblank flag is set by one of the dots run during the given
+    // CPU cycle, the PPU having caught up to its start.
+    fn vblank_due(&self, cpu_cycle: u64) -> bool {
+        let dots = self.region.dots(cpu_cycle + 1).saturating_sub(self.dots);
+        let position = self.scanline as u64 * DOTS_PER_SCANLINE as u64 + self.dot as u64;
+        let vblank = VBLANK_SCANLINE as u64 * DOTS_PER_SCANLINE as u64 + 1;
+        position <= vblank && vblank - position < dots
+    }
+
+    pub fn read(&mut self, addr: u16) -> u8 {
+        let val = self.peek_register(addr);
+        match (addr as usize).into() {
             Register::PPUSTATUS => {
-                let val = self.ppustatus;
                 self.ppustatus &= 0x7F;
                 self.address_latch = AddressLatch::HI;
-                // self.scroll_latch = AddressLatch::HI;
-                val
             }
-            Register::OAMADDR => panic!("OAMADDR is write only"), // self.oamaddr,
-            Register::OAMDATA => self.oam[self.oamaddr as usize],
-            Register::PPUSCROLL => panic!("PPUSCROLL is write only"),
-            Register::PPUADDR => panic!("PPUADDR is write only"),
             Register::PPUDATA => {
-                let addr = self.ppuaddr;
-                let val = self.readb(addr);
+                let addr = self.loopy.v & 0x3FFF;
+                if addr < 0x3F00 {
+                    self.ppudata_buffer = self.readb(addr);
+                }
                 self.incr_ppuaddr();
+            }
+            _ => {}
+        }
+        self.io_latch = val;
+        val
+    }
+
+    // peek_register returns what reading a register would, without clearing the vblank flag or
+    // advancing the PPUDATA address and read buffer.
+    pub fn peek_register(&self, addr: u16) -> u8 {
+        debug_assert!(addr <= 7);
+
+        let reg: Register = (addr as usize).into();
+        match reg {
+            Register::PPUCTRL
+            | Register::PPUMASK
+            | Register::OAMADDR
+            | Register::PPUSCROLL
+            | Register::PPUADDR => self.io_latch,
+            Register::PPUSTATUS => (self.ppustatus & 0xE0) | (self.io_latch & 0x1F),
+            Register::OAMDATA => self.oam[self.oamaddr as usize],
+            Register::PPUDATA => {
+                let addr = self.loopy.v & 0x3FFF;
                 if addr < 0x3F00 {
-                    let buffered_val = self.ppudata_buffer;
-                    self.ppudata_buffer = val;
-                    buffered_val
+                    self.ppudata_buffer
                 } else {
-                    val
+                    self.readb(addr)
                 }
             }
         }
     }
 
+    // peek reads the PPU's own address space, $0000-$3FFF, following the same mirroring as
+    // rendering does.
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.readb(addr)
+    }
+
     pub fn write(&mut self, addr: u16, val: u8) {
         debug_assert!(addr <= 7);
 
         let reg: Register = (addr as usize).into();
         match reg {
             Register::PPUCTRL => {
-                // self.address_latch = AddressLatch::HI;
-                // self.ppustatus &= 0x7F;
-                self.ppuctrl = val
+                // the NMI line is the vblank flag ANDed with bit 7, so enabling NMIs during vblank
+                // raises one right away, and disabling them drops one that wasn't serviced yet.
+                let was_enabled = self.vblank_nmi();
+                self.ppuctrl = val;
+                self.loopy.write_ctrl(val);
+                if self.ppustatus & 0x80 != 0 {
+                    match (was_enabled, self.vblank_nmi()) {
+                        (false, true) => self.nmi = true,
+                        (true, false) => self.nmi = false,
+                        _ => {}
+                    }
+                }
             }
             Register::PPUMASK => self.ppumask = val,
             Register::PPUSTATUS => {
@@ -532,48 +941,701 @@ impl PPU {
                 self.oamaddr = self.oamaddr.wrapping_add(1);
             }
             Register::PPUSCROLL => {
-                let val = val as u16;
-                match self.address_latch {
-                    AddressLatch::HI => self.ppuscroll = (self.ppuscroll & 0x00FF) | val << 8,
-                    AddressLatch::LO => self.ppuscroll = (self.ppuscroll & 0xFF00) | val,
-                };
+                self.loopy.write_scroll(&self.address_latch, val);
                 self.address_latch.next();
             }
             Register::PPUADDR => {
-                let val = val as u16;
-                match self.address_latch {
-                    AddressLatch::HI => self.ppuaddr = (self.ppuaddr & 0x00FF) | val << 8,
-                    AddressLatch::LO => self.ppuaddr = (self.ppuaddr & 0xFF00) | val,
-                };
-
-                // TODO: cpu_dummy_writes/cpu_dummy_writes_ppumem.nes fails with:
-                //      A single write to $2006 must not change the address used by $2007 when
-                //      vblank is on.
-                //
-                // I assume we need to set something like this, but it still fails with it.
-                //      if !self.get_vblank() {
-                //          self.address_latch.next();
-                //      }
-
+                // the first write only goes to t, v is loaded on the second one. A single
+                // write, like a dummy write from a read-modify-write instruction, leaves the
+                // address used by PPUDATA alone.
+                self.loopy.write_addr(&self.address_latch, val);
                 self.address_latch.next();
             }
             Register::PPUDATA => {
-                self.writeb(self.ppuaddr, val);
+                self.writeb(self.loopy.v & 0x3FFF, val);
                 self.incr_ppuaddr();
             }
         }
 
-        match reg {
-            Register::PPUADDR
-            | Register::PPUSCROLL
-            | Register::PPUCTRL
-            | Register::PPUDATA
-            | Register::PPUMASK
-            | Register::PPUSTATUS => {
-                self.ppustatus &= 0b1110_0000;
-                self.ppustatus |= 0b0001_1111 & val;
+        self.io_latch = val;
+    }
+}
+
+#[test]
+fn test_pack_rgb() {
+    let color = RGB {
+        r: 0x11,
+        g: 0x22,
+        b: 0x33,
+    };
+    assert_eq!(pack_rgb(PixelFormat::Bgr24, &color), [0x33, 0x22, 0x11]);
+    assert_eq!(pack_rgb(PixelFormat::Rgb24, &color), [0x11, 0x22, 0x33]);
+    assert_eq!(
+        pack_rgb(PixelFormat::Bgr24.swapped(), &color),
+        pack_rgb(PixelFormat::Rgb24, &color)
+    );
+}
+
+#[test]
+fn test_blit_into() {
+    let mut ppu = test_ppu(&[]);
+    let color = RGB {
+        r: 0x11,
+        g: 0x22,
+        b: 0x33,
+    };
+    ppu.set_pixel(0, 1, color);
+
+    let pitch = SCREEN_WIDTH * 3 + 8;
+    let mut dst = vec![0xFF; pitch * SCREEN_HEIGHT];
+    ppu.blit_into(&mut dst, pitch, PixelFormat::Rgb24);
+
+    assert_eq!(dst[0..3], [0, 0, 0]);
+    assert_eq!(dst[pitch..pitch + 3], [0x11, 0x22, 0x33]);
+    // the padding at the end of each row is left untouched.
+    assert_eq!(dst[SCREEN_WIDTH * 3..pitch], [0xFF; 8]);
+
+    // the last row doesn't need its padding.
+    let mut dst = vec![0xFF; pitch * (SCREEN_HEIGHT - 1) + SCREEN_WIDTH * 3];
+    ppu.blit_into(&mut dst, pitch, PixelFormat::Rgb24);
+    assert_eq!(dst[pitch..pitch + 3], [0x11, 0x22, 0x33]);
+}
+
+#[test]
+#[should_panic(expected = "shorter than the 184320 bytes of a frame")]
+fn test_blit_into_short_buffer() {
+    let ppu = test_ppu(&[]);
+    let mut dst = vec![0; SCREEN_WIDTH * 3 * SCREEN_HEIGHT - 1];
+    ppu.blit_into(&mut dst, SCREEN_WIDTH * 3, PixelFormat::Rgb24);
+}
+
+#[test]
+#[should_panic(expected = "pitch of 767 bytes is shorter than a 768 byte row")]
+fn test_blit_into_short_pitch() {
+    let ppu = test_ppu(&[]);
+    let mut dst = vec![0; SCREEN_WIDTH * 3 * SCREEN_HEIGHT];
+    ppu.blit_into(&mut dst, SCREEN_WIDTH * 3 - 1, PixelFormat::Rgb24);
+}
+
+#[cfg(test)]
+fn test_ppu(chr: &[u8]) -> PPU {
+    // builds an NROM image with an empty 16kb PRG bank and the given CHR data at $0000.
+    let mut data = test_rom(0, 1, 1);
+    data[16 + 0x4000..16 + 0x4000 + chr.len()].copy_from_slice(chr);
+
+    let cartridge = Rc::new(RefCell::new(Cartridge::from_data(data).unwrap()));
+    PPU::new(cartridge, Palette::default())
+}
+
+#[test]
+fn test_sprite_flip() {
+    // tile 1 only has its top left pixel set.
+    let mut chr = [0; 0x20];
+    chr[0x10] = 0x80;
+    let mut ppu = test_ppu(&chr);
+    ppu.ppumask = 0x10;
+
+    let cases = [
+        (0x00, (0, 0)),
+        (0x40, (7, 0)),
+        (0x80, (0, 7)),
+        (0xC0, (7, 7)),
+    ];
+    for (attributes, expected) in cases.iter() {
+        let sprites = [Sprite {
+            x: 8,
+            y: 10,
+            attributes: *attributes,
+            tile_index: 1,
+            sprite_zero: false,
+        }];
+
+        let mut opaque = vec![];
+        for row in 0..8 {
+            ppu.scanline = 10 + row;
+            for col in 0..8 {
+                if ppu.get_sprite_pixel(&sprites, 8 + col).is_some() {
+                    opaque.push((col, row));
+                }
             }
-            _ => {}
         }
+        assert_eq!(opaque, [*expected], "attributes {:02X}", attributes);
+    }
+}
+
+#[test]
+fn test_sprite_overflow() {
+    let mut ppu = test_ppu(&[]);
+    for i in 0..9 {
+        ppu.oam[i * 4] = 0x10;
+        ppu.oam[i * 4 + 3] = i as u8 * 8;
+    }
+    for i in 9..64 {
+        ppu.oam[i * 4] = 0xF0;
+    }
+
+    ppu.scanline = 0x11;
+    let sprites = ppu.get_scanline_sprite_pixels();
+    assert_eq!(sprites.len(), 8);
+    assert_eq!(ppu.ppustatus & 0x20, 0x20);
+    // the sprite zero hit flag is left alone.
+    assert_eq!(ppu.ppustatus & 0x40, 0x00);
+}
+
+#[test]
+fn test_tall_sprites() {
+    // tile 2 has its top left pixel set and tile 3 its top right one.
+    let mut chr = [0; 0x40];
+    chr[0x20] = 0x80;
+    chr[0x30] = 0x01;
+    let mut ppu = test_ppu(&chr);
+    ppu.ppumask = 0x10;
+    ppu.ppuctrl = 0x20;
+
+    let cases = [(0x00, [(0, 0), (7, 8)]), (0x80, [(7, 7), (0, 15)])];
+    for (attributes, expected) in cases.iter() {
+        let sprites = [Sprite {
+            x: 8,
+            y: 10,
+            attributes: *attributes,
+            tile_index: 2,
+            sprite_zero: false,
+        }];
+
+        let mut opaque = vec![];
+        for row in 0..16 {
+            ppu.scanline = 10 + row;
+            for col in 0..8 {
+                if ppu.get_sprite_pixel(&sprites, 8 + col).is_some() {
+                    opaque.push((col, row));
+                }
+            }
+        }
+        assert_eq!(opaque, expected, "attributes {:02X}", attributes);
+    }
+}
+
+#[test]
+fn test_background_scrolling() {
+    // the low plane of row 2 of every tile holds the tile's own index.
+    let mut chr = [0; 0x1000];
+    for tile in 0..0x100 {
+        chr[tile * 16 + 2] = tile as u8;
+    }
+    let mut ppu = test_ppu(&chr);
+    for (i, tile) in ppu.nametables.iter_mut().enumerate() {
+        *tile = i as u8;
+    }
+
+    // scroll to the pixel (11, 18): coarse X 1, fine X 3, coarse Y 2, fine Y 2.
+    ppu.write(0, 0x00);
+    ppu.write(5, 11);
+    ppu.write(5, 18);
+    assert_eq!(ppu.loopy.x, 3);
+
+    // the scroll position is loaded into v at the end of the frame.
+    ppu.write(1, 0x08);
+    ppu.scanline = Region::Ntsc.last_scanline();
+    for _ in 0..DOTS_PER_SCANLINE {
+        ppu.step_dot();
+    }
+    assert_eq!(ppu.scanline, 0);
+
+    let tiles = ppu.get_scanline_background_pixels();
+    assert_eq!(tiles[0], 2 * 32 + 1);
+    assert_eq!(tiles[2], 2 * 32 + 2);
+    // the 33rd tile wraps around into the next nametable.
+    assert_eq!(tiles[64], 2 * 32 + 1);
+}
+
+#[test]
+fn test_background_rows() {
+    // every row of every tile holds the tile's own index in its low plane.
+    let mut chr = [0; 0x1000];
+    for tile in 0..0x100 {
+        for row in 0..8 {
+            chr[tile * 16 + row] = tile as u8;
+        }
+    }
+    // the first nametable has each row's number in its tiles, the one below it (at $2800 with
+    // vertical scrolling) has the row's number plus $80.
+    let mut ppu = test_ppu(&chr);
+    for row in 0..30 {
+        for col in 0..32 {
+            ppu.nametables[row * 32 + col] = row as u8;
+            ppu.nametables[0x400 + row * 32 + col] = 0x80 + row as u8;
+        }
+    }
+
+    // (vertical scroll, scanline, tile fetched)
+    let cases = [
+        (0, 0, 0),
+        (0, 232, 29),
+        (0, 239, 29),
+        // scrolling down two rows wraps into the next nametable after row 29, not row 31.
+        (16, 223, 29),
+        (16, 224, 0x80),
+        (16, 232, 0x81),
+    ];
+    for &(scroll, scanline, tile) in cases.iter() {
+        ppu.write(1, 0x00);
+        ppu.write(0, 0x00);
+        ppu.write(5, 0);
+        ppu.write(5, scroll);
+        ppu.write(1, 0x08);
+        ppu.scanline = Region::Ntsc.last_scanline();
+        ppu.dot = 0;
+        // run the pre-render line and the visible lines up to the fetch on dot 0.
+        for _ in 0..DOTS_PER_SCANLINE as usize * (scanline as usize + 1) + 1 {
+            ppu.step_dot();
+        }
+        assert_eq!(ppu.scanline, scanline);
+        assert_eq!(
+            ppu.scanline_tiles[0], tile,
+            "scroll {} scanline {}",
+            scroll, scanline
+        );
+    }
+}
+
+#[test]
+fn test_nametable_mirroring() {
+    // (flags 6, mirror of $2000, address that must not mirror $2000)
+    let cases = [(0x00, 0x2400, 0x2800), (0x01, 0x2800, 0x2400)];
+    for (flags, mirror, other) in cases.iter() {
+        let mut data = test_rom(0, 1, 1);
+        data[6] = *flags;
+        let cartridge = Rc::new(RefCell::new(Cartridge::from_data(data).unwrap()));
+        let mut ppu = PPU::new(cartridge, Palette::default());
+
+        ppu.write(6, 0x20);
+        ppu.write(6, 0x00);
+        ppu.write(7, 0x42);
+
+        let mut read = |addr: u16| {
+            ppu.write(6, (addr >> 8) as u8);
+            ppu.write(6, addr as u8);
+            // PPUDATA reads are buffered, so the first one returns stale data.
+            ppu.read(7);
+            ppu.read(7)
+        };
+        assert_eq!(read(*mirror), 0x42, "flags {:02X}", flags);
+        assert_eq!(read(*other), 0x00, "flags {:02X}", flags);
+    }
+}
+
+#[test]
+fn test_chr_ram() {
+    // an NROM cartridge without CHR ROM.
+    let cartridge = Rc::new(RefCell::new(
+        Cartridge::from_data(test_rom(0, 1, 0)).unwrap(),
+    ));
+    let mut ppu = PPU::new(cartridge, Palette::default());
+
+    ppu.write(6, 0x00);
+    ppu.write(6, 0x10);
+    for i in 0..16 {
+        ppu.write(7, i);
+    }
+
+    ppu.write(6, 0x00);
+    ppu.write(6, 0x10);
+    // PPUDATA reads are buffered, so the first one returns stale data.
+    ppu.read(7);
+    for i in 0..16 {
+        assert_eq!(ppu.read(7), i);
+    }
+}
+
+#[test]
+fn test_grayscale_and_emphasis() {
+    let mut ppu = test_ppu(&[]);
+    ppu.palette_ram_idx[1] = 0x16;
+    let rgb = |c: RGB| (c.r, c.g, c.b);
+    let entry = |i: usize| rgb(Palette::default().color(i));
+
+    assert_eq!(rgb(ppu.color(0x3F01)), entry(0x16));
+
+    // grayscale collapses the color to the gray column of its row.
+    ppu.ppumask = 0x01;
+    let gray = entry(0x10);
+    assert_eq!(rgb(ppu.color(0x3F01)), gray);
+
+    // emphasizing red and green darkens blue.
+    ppu.ppumask = 0x61;
+    let (r, g, b) = gray;
+    let b = (b as f32 * EMPHASIS_ATTENUATION) as u8;
+    assert_eq!(rgb(ppu.color(0x3F01)), (r, g, b));
+}
+
+#[test]
+fn test_custom_palette() {
+    let data: Vec<u8> = (0..192).map(|i| i as u8).collect();
+    let mut ppu = test_ppu(&[0xFF; 8]);
+    ppu.palette = Palette::from_bytes(&data).unwrap();
+    ppu.ppuctrl = 0;
+    ppu.ppumask = 0x0A;
+    ppu.palette_ram_idx[1] = 0x05;
+
+    ppu.render_scanline();
+    // color 5 of the palette, stored as B, G, R.
+    assert_eq!(ppu.screen[..3], [17, 16, 15]);
+}
+
+#[test]
+fn test_region_timing() {
+    for region in [Region::Ntsc, Region::Pal].iter() {
+        let mut ppu = test_ppu(&[]);
+        ppu.set_region(*region);
+        let mut cpu_cycles = 0;
+
+        // counts the scanlines and CPU cycles between two frames.
+        let mut frame = |ppu: &mut PPU| {
+            let (start, mut scanlines) = (cpu_cycles, 0);
+            loop {
+                cpu_cycles += 1;
+                let scanline = ppu.scanline;
+                ppu.step(cpu_cycles);
+                if ppu.scanline != scanline {
+                    scanlines += 1;
+                }
+                if ppu.frame_complete {
+                    return (scanlines, cpu_cycles - start);
+                }
+            }
+        };
+
+        frame(&mut ppu);
+        let (scanlines, cycles) = frame(&mut ppu);
+        match region {
+            Region::Ntsc => assert_eq!((scanlines, cycles), (262, 29781)),
+            Region::Pal => assert_eq!((scanlines, cycles), (312, 33247)),
+        }
+    }
+}
+
+#[test]
+fn test_sprite_priority() {
+    // tile 0 is solid color 1, tile 1 is solid color 2 and tile 2 is transparent.
+    let mut chr = [0; 0x30];
+    chr[0x00..0x08].copy_from_slice(&[0xFF; 8]);
+    chr[0x18..0x20].copy_from_slice(&[0xFF; 8]);
+    let mut ppu = test_ppu(&chr);
+    ppu.ppumask = 0x1E;
+    ppu.palette_ram_idx[0x00] = 0x0F;
+    ppu.palette_ram_idx[0x01] = 0x30;
+    ppu.palette_ram_idx[0x11] = 0x16;
+    ppu.palette_ram_idx[0x16] = 0x2A;
+    for i in 0..64 {
+        ppu.oam[i * 4] = 0xF0;
+    }
+    // sprite 0 covers x 16 to 23 and sprite 1, in the second palette, covers x 20 to 27.
+    ppu.oam[0..4].copy_from_slice(&[0x1F, 0x00, 0x00, 0x10]);
+    ppu.oam[4..8].copy_from_slice(&[0x1F, 0x01, 0x01, 0x14]);
+    ppu.scanline = 0x20;
+
+    let render = |ppu: &mut PPU| -> Vec<RGB> {
+        ppu.render_scanline();
+        let row = &ppu.screen[0x20 * SCREEN_WIDTH * 3..];
+        [0x10, 0x14, 0x18, 0x1C]
+            .iter()
+            .map(|x| unpack_rgb(PixelFormat::Bgr24, &row[x * 3..x * 3 + 3]))
+            .collect()
+    };
+    let color = |i| Palette::default().color(i);
+
+    // over a transparent background the lower indexed sprite is drawn on top.
+    ppu.nametables.iter_mut().for_each(|tile| *tile = 2);
+    let (sprite_0, sprite_1, backdrop) = (color(0x16), color(0x2A), color(0x0F));
+    assert_eq!(render(&mut ppu), [sprite_0, sprite_0, sprite_1, backdrop]);
+
+    // whatever its priority.
+    ppu.oam[2] = 0x20;
+    assert_eq!(render(&mut ppu), [sprite_0, sprite_0, sprite_1, backdrop]);
+
+    // behind an opaque background it hides sprite 1 where they overlap, even though sprite 1
+    // is in front of the background.
+    ppu.nametables.iter_mut().for_each(|tile| *tile = 0);
+    let bg = color(0x30);
+    assert_eq!(render(&mut ppu), [bg, bg, sprite_1, bg]);
+}
+
+#[test]
+fn test_sprite_zero_hit() {
+    // tile 0 is solid, tile 1 is transparent.
+    let mut ppu = test_ppu(&[0xFF; 8]);
+    ppu.ppuctrl = 0x00;
+    ppu.ppumask = 0x1E;
+    for i in 0..64 {
+        ppu.oam[i * 4] = 0xF0;
+    }
+
+    // an opaque sprite that isn't sprite 0 doesn't set the flag, nor does a transparent sprite 0.
+    ppu.oam[0..4].copy_from_slice(&[0x1F, 0x01, 0x00, 0x10]);
+    ppu.oam[4..8].copy_from_slice(&[0x1F, 0x00, 0x00, 0x10]);
+    ppu.scanline = 0x20;
+    ppu.render_scanline();
+    assert_eq!(ppu.ppustatus & 0x40, 0x00);
+
+    ppu.oam[1] = 0x00;
+    ppu.render_scanline();
+    assert_eq!(ppu.ppustatus & 0x40, 0x40);
+
+    // the flag is cleared on the pre-render scanline.
+    ppu.scanline = Region::Ntsc.last_scanline();
+    ppu.step_dot();
+    ppu.step_dot();
+    assert_eq!(ppu.ppustatus & 0x40, 0x00);
+
+    // no hit over a transparent background.
+    ppu.ppumask = 0x14;
+    ppu.scanline = 0x20;
+    ppu.render_scanline();
+    assert_eq!(ppu.ppustatus & 0x40, 0x00);
+}
+
+#[test]
+fn test_io_latch() {
+    let mut ppu = test_ppu(&[]);
+    ppu.write(0, 0x5A);
+    // write only registers read back the last value written.
+    assert_eq!(ppu.read(5), 0x5A);
+    assert_eq!(ppu.read(3), 0x5A);
+
+    // the unused bits of PPUSTATUS come from the latch too.
+    ppu.set_vblank(true);
+    ppu.set_sprite_overflow(false);
+    ppu.write(1, 0x1F);
+    assert_eq!(ppu.read(2), 0x9F);
+    assert_eq!(ppu.read(6), 0x9F);
+}
+
+#[test]
+fn test_power_up_state() {
+    let mut ppu = test_ppu(&[]);
+    assert_eq!(ppu.ppuctrl, 0);
+    assert_eq!(ppu.ppumask, 0);
+    assert_eq!(ppu.ppustatus, 0xA0);
+    assert_eq!(ppu.oamaddr, 0);
+    assert_eq!((ppu.loopy.v, ppu.loopy.t, ppu.loopy.x), (0, 0, 0));
+    assert_eq!(ppu.address_latch, AddressLatch::HI);
+    assert_eq!(ppu.ppudata_buffer, 0);
+
+    ppu.write(0, 0x80);
+    ppu.write(1, 0x1E);
+    ppu.write(3, 0x20);
+    ppu.write(6, 0x21);
+    ppu.write(6, 0x08);
+    ppu.write(5, 0x0B);
+    ppu.reset();
+    assert_eq!(ppu.ppuctrl, 0);
+    assert_eq!(ppu.ppumask, 0);
+    assert_eq!(ppu.ppustatus & 0x80, 0x80);
+    assert_eq!(ppu.address_latch, AddressLatch::HI);
+    assert_eq!((ppu.loopy.t, ppu.loopy.x), (0, 0));
+    // OAMADDR and PPUADDR survive a reset.
+    assert_eq!(ppu.oamaddr, 0x20);
+    assert_eq!(ppu.loopy.v, 0x2108);
+}
+
+#[test]
+fn test_ppustatus_latch_bits() {
+    let mut ppu = test_ppu(&[]);
+    ppu.set_vblank(true);
+    ppu.set_sprite_overflow(true);
+    ppu.write(0, 0x0B);
+    // writes leave the status flags alone.
+    assert_eq!(ppu.ppustatus & 0xE0, 0xA0);
+    assert_eq!(ppu.read(2), 0xAB);
+    // reading PPUSTATUS clears vblank but not the latch.
+    assert_eq!(ppu.read(2), 0x2B);
+}
+
+#[test]
+fn test_ppuaddr_writes() {
+    let mut ppu = test_ppu(&[]);
+    ppu.set_vblank(true);
+    ppu.write(6, 0x21);
+    ppu.write(6, 0x08);
+    assert_eq!(ppu.loopy.v, 0x2108);
+
+    // a single write only changes t.
+    ppu.write(6, 0x23);
+    assert_eq!(ppu.loopy.t, 0x2308);
+    assert_eq!(ppu.loopy.v, 0x2108);
+    ppu.write(7, 0x42);
+    assert_eq!(ppu.nametable(0x2108), 0x42);
+
+    // reading PPUSTATUS resets the toggle, so the next write is a high byte again.
+    ppu.read(2);
+    ppu.write(6, 0x22);
+    ppu.write(6, 0x00);
+    assert_eq!(ppu.loopy.v, 0x2200);
+}
+
+#[test]
+fn test_vblank_read_race() {
+    // the vblank flag is due on dot 1 of scanline 241, which NTSC runs on CPU cycle 27394.
+    let vblank_cycle = (VBLANK_SCANLINE as u64 * DOTS_PER_SCANLINE as u64 + 1) / 3;
+    let vblank_ppu = || {
+        let mut ppu = test_ppu(&[]);
+        // the vblank flag is set at power-on, boot code clears it with a read like this one.
+        ppu.read_at(2, 0);
+        ppu.write(0, 0x80);
+        ppu
+    };
+
+    // a read on that cycle sees the flag clear, and neither the flag nor the NMI are raised.
+    let mut ppu = vblank_ppu();
+    assert_eq!(ppu.read_at(2, vblank_cycle) & 0x80, 0x00);
+    assert!(!ppu.step(vblank_cycle + 10));
+    assert_eq!(ppu.ppustatus & 0x80, 0x00);
+    // the next frame is unaffected.
+    let frame_cycles = Region::Ntsc.last_scanline() as u64 + 1;
+    let frame_cycles = frame_cycles * DOTS_PER_SCANLINE as u64 / 3;
+    assert!(ppu.step(vblank_cycle + frame_cycles + 10));
+
+    // reads before it don't interfere.
+    let mut ppu = vblank_ppu();
+    assert_eq!(ppu.read_at(2, vblank_cycle - 5) & 0x80, 0x00);
+    assert!(ppu.step(vblank_cycle + 10));
+    assert_eq!(ppu.read_at(2, vblank_cycle + 11) & 0x80, 0x80);
+}
+
+#[test]
+fn test_scrolled_attributes() {
+    // a solid background whose first attribute byte gives each quadrant of its 4x4 tile area a
+    // different palette, each palette drawing in a different color.
+    let mut ppu = test_ppu(&[0xFF; 8]);
+    ppu.nametables[0x3C0] = 0b11_10_01_00;
+    for palette in 0..4 {
+        ppu.palette_ram_idx[palette * 4 + 1] = 0x11 + palette as u8;
+    }
+    ppu.write(1, 0x0A);
+
+    // renders the scanline starting at the given v and fine X.
+    let render = |ppu: &mut PPU, v: u16, x: u8| {
+        ppu.loopy.v = v;
+        ppu.loopy.x = x;
+        ppu.render_scanline();
+        ppu.screen[..SCREEN_WIDTH * 3].to_vec()
+    };
+    let palette_color = |palette: usize| {
+        let color = Palette::default().color(0x11 + palette);
+        pack_rgb(PixelFormat::Bgr24, &color).to_vec()
+    };
+
+    // tiles are colored by their quadrant, two tiles wide.
+    let unscrolled = render(&mut ppu, 0x2000, 0);
+    let pixel = |row: &[u8], x: usize| row[x * 3..x * 3 + 3].to_vec();
+    assert_eq!(pixel(&unscrolled, 15), palette_color(0));
+    assert_eq!(pixel(&unscrolled, 16), palette_color(1));
+
+    // scrolling 12 pixels right shifts the colors along with the tiles.
+    let scrolled = render(&mut ppu, 0x2001, 4);
+    assert_eq!(pixel(&scrolled, 3), palette_color(0));
+    assert_eq!(pixel(&scrolled, 4), palette_color(1));
+    assert_eq!(scrolled[..20 * 3], unscrolled[12 * 3..32 * 3]);
+
+    // the bottom quadrants start on the third row of tiles.
+    let bottom = render(&mut ppu, 0x2000 | 2 << 5, 0);
+    assert_eq!(pixel(&bottom, 0), palette_color(2));
+    assert_eq!(pixel(&bottom, 16), palette_color(3));
+}
+
+#[test]
+fn test_mid_frame_left_column() {
+    // a solid background, with the left column hidden until the start of scanline 120.
+    let mut ppu = test_ppu(&[0xFF; 8]);
+    ppu.palette_ram_idx[0] = 0x0F;
+    ppu.palette_ram_idx[1] = 0x30;
+    ppu.write(0, 0x00);
+    ppu.write(1, 0x08);
+    ppu.loopy.v = 0x2000;
+    ppu.write_at(1, 0x0A, 120 * DOTS_PER_SCANLINE as u64 / 3);
+    ppu.step(240 * DOTS_PER_SCANLINE as u64 / 3);
+
+    let color = |i| pack_rgb(PixelFormat::Bgr24, &Palette::default().color(i));
+    let (backdrop, solid) = (color(0x0F), color(0x30));
+    let pixel = |x: usize, y: usize| {
+        let offset = (y * SCREEN_WIDTH + x) * 3;
+        ppu.screen[offset..offset + 3].to_vec()
+    };
+    for &y in [0, 60, 119].iter() {
+        assert_eq!(pixel(0, y), backdrop, "scanline {}", y);
+        assert_eq!(pixel(7, y), backdrop, "scanline {}", y);
+        assert_eq!(pixel(8, y), solid, "scanline {}", y);
+    }
+    for &y in [120, 180, 239].iter() {
+        assert_eq!(pixel(0, y), solid, "scanline {}", y);
+        assert_eq!(pixel(7, y), solid, "scanline {}", y);
+        assert_eq!(pixel(8, y), solid, "scanline {}", y);
+    }
+}
+
+#[test]
+fn test_mid_scanline_scroll() {
+    // the first row of the nametable alternates a transparent and a solid tile, so the fine X
+    // scroll moves the edges between them.
+    let mut chr = [0; 0x20];
+    chr[0x10..0x18].copy_from_slice(&[0xFF; 8]);
+    let mut ppu = test_ppu(&chr);
+    for (i, tile) in ppu.nametables[..32].iter_mut().enumerate() {
+        *tile = i as u8 % 2;
+    }
+    ppu.palette_ram_idx[0] = 0x0F;
+    ppu.palette_ram_idx[1] = 0x30;
+    ppu.write(0, 0x00);
+    ppu.write(1, 0x0A);
+
+    // the scroll is changed to fine X 4 on the CPU cycle that starts on dot 129 of the first
+    // scanline, right after its first 128 pixels are drawn.
+    let render = |ppu: &mut PPU, split: bool| {
+        ppu.scanline = 0;
+        ppu.dot = 0;
+        ppu.dots = 0;
+        ppu.loopy.v = 0x2000;
+        ppu.loopy.x = 0;
+        ppu.address_latch = AddressLatch::HI;
+        if split {
+            ppu.write_at(5, 0x04, 129 / 3);
+        }
+        ppu.step(DOTS_PER_SCANLINE as u64 / 3);
+        let row = SCREEN_WIDTH * 3;
+        ppu.screen[..row].to_vec()
+    };
+    let unsplit = render(&mut ppu, false);
+    let split = render(&mut ppu, true);
+
+    // the left half is drawn before the write, and the right half after it.
+    let half = SCREEN_WIDTH / 2 * 3;
+    assert_eq!(split[..half], unsplit[..half]);
+    assert_ne!(split[half..], unsplit[half..]);
+}
+
+#[test]
+fn test_ppuctrl_nmi_edges() {
+    let mut ppu = test_ppu(&[]);
+    let vblank_cycle = (VBLANK_SCANLINE as u64 * DOTS_PER_SCANLINE as u64 + 1) / 3;
+    // NMIs are disabled when vblank starts.
+    assert!(!ppu.step(vblank_cycle + 1));
+
+    // every rising edge of bit 7 during vblank raises an NMI.
+    let mut nmis = 0;
+    for val in [0x80, 0x80, 0x00, 0x80, 0x00, 0x80].iter() {
+        ppu.write(0, *val);
+        nmis += ppu.step(vblank_cycle + 1) as u32;
     }
+    assert_eq!(nmis, 3);
+
+    // a falling edge drops the NMI before it is serviced.
+    ppu.write(0, 0x00);
+    ppu.write(0, 0x80);
+    ppu.write(0, 0x00);
+    assert!(!ppu.step(vblank_cycle + 1));
+
+    // once the vblank flag is cleared there are no more NMIs.
+    ppu.read(2);
+    ppu.write(0, 0x80);
+    assert!(!ppu.step(vblank_cycle + 1));
 }
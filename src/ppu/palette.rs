@@ -0,0 +1,77 @@
+use super::RGB;
+use std::fmt;
+
+// a palette holds the RGB values of the 64 colors the PPU can output, in the same layout as .pal
+// files.
+const PALETTE_SIZE: usize = 64 * 3;
+
+static DEFAULT_PALETTE: [u8; PALETTE_SIZE] = [
+    124, 124, 124, 0, 0, 252, 0, 0, 188, 68, 40, 188, 148, 0, 132, 168, 0, 32, 168, 16, 0, 136, 20,
+    0, 80, 48, 0, 0, 120, 0, 0, 104, 0, 0, 88, 0, 0, 64, 88, 0, 0, 0, 0, 0, 0, 0, 0, 0, 188, 188,
+    188, 0, 120, 248, 0, 88, 248, 104, 68, 252, 216, 0, 204, 228, 0, 88, 248, 56, 0, 228, 92, 16,
+    172, 124, 0, 0, 184, 0, 0, 168, 0, 0, 168, 68, 0, 136, 136, 0, 0, 0, 0, 0, 0, 0, 0, 0, 248,
+    248, 248, 60, 188, 252, 104, 136, 252, 152, 120, 248, 248, 120, 248, 248, 88, 152, 248, 120,
+    88, 252, 160, 68, 248, 184, 0, 184, 248, 24, 88, 216, 84, 88, 248, 152, 0, 232, 216, 120, 120,
+    120, 0, 0, 0, 0, 0, 0, 252, 252, 252, 164, 228, 252, 184, 184, 248, 216, 184, 248, 248, 184,
+    248, 248, 164, 192, 240, 208, 176, 252, 224, 168, 248, 216, 120, 216, 248, 120, 184, 248, 184,
+    184, 248, 216, 0, 252, 252, 248, 216, 248, 0, 0, 0, 0, 0, 0,
+];
+
+#[derive(Clone)]
+pub struct Palette([u8; PALETTE_SIZE]);
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette(DEFAULT_PALETTE)
+    }
+}
+
+#[derive(Debug)]
+pub struct PaletteSizeError(usize);
+
+impl fmt::Display for PaletteSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "palette files must be {} bytes long, got {}",
+            PALETTE_SIZE, self.0
+        )
+    }
+}
+
+impl std::error::Error for PaletteSizeError {}
+
+impl Palette {
+    pub fn from_bytes(data: &[u8]) -> Result<Self, PaletteSizeError> {
+        if data.len() != PALETTE_SIZE {
+            return Err(PaletteSizeError(data.len()));
+        }
+
+        let mut palette = [0; PALETTE_SIZE];
+        palette.copy_from_slice(data);
+        Ok(Palette(palette))
+    }
+
+    pub fn from_path(path: impl AsRef<str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let data = std::fs::read(path.as_ref())?;
+        Ok(Palette::from_bytes(&data)?)
+    }
+
+    pub(super) fn color(&self, index: usize) -> RGB {
+        RGB {
+            r: self.0[index * 3],
+            g: self.0[index * 3 + 1],
+            b: self.0[index * 3 + 2],
+        }
+    }
+}
+
+#[test]
+fn test_palette_size() {
+    assert!(Palette::from_bytes(&[0; PALETTE_SIZE]).is_ok());
+    let err = Palette::from_bytes(&[0; 64]).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "palette files must be 192 bytes long, got 64"
+    );
+}
@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub(super) enum AddressLatch {
     LO,
     HI,
@@ -42,3 +42,121 @@ impl From<usize> for Register {
         }
     }
 }
+
+// Loopy holds the internal registers the PPU uses both to address VRAM through PPUADDR/PPUDATA
+// and to keep track of the scroll position while rendering. The write toggle shared by PPUSCROLL
+// and PPUADDR is the AddressLatch. See https://wiki.nesdev.com/w/index.php/PPU_scrolling.
+//
+// Both v and t are laid out as yyy NN YYYYY XXXXX, that is fine Y, nametable select, coarse Y and
+// coarse X.
+#[derive(Debug, Default)]
+pub(super) struct Loopy {
+    // v is the current VRAM address.
+    pub(super) v: u16,
+    // t is the temporary VRAM address, which holds the scroll position of the top left corner
+    // of the screen.
+    pub(super) t: u16,
+    // x is the fine X scroll, the pixel within the first visible tile.
+    pub(super) x: u8,
+}
+
+impl Loopy {
+    pub(super) fn write_ctrl(&mut self, val: u8) {
+        self.t = (self.t & !0x0C00) | (val as u16 & 0x03) << 10;
+    }
+
+    pub(super) fn write_scroll(&mut self, latch: &AddressLatch, val: u8) {
+        let val = val as u16;
+        match latch {
+            AddressLatch::HI => {
+                self.t = (self.t & !0x001F) | val >> 3;
+                self.x = val as u8 & 0x07;
+            }
+            AddressLatch::LO => {
+                self.t = (self.t & !0x73E0) | (val & 0x07) << 12 | (val & 0xF8) << 2;
+            }
+        }
+    }
+
+    pub(super) fn write_addr(&mut self, latch: &AddressLatch, val: u8) {
+        let val = val as u16;
+        match latch {
+            AddressLatch::HI => self.t = (self.t & 0x00FF) | (val & 0x3F) << 8,
+            AddressLatch::LO => {
+                self.t = (self.t & 0xFF00) | val;
+                self.v = self.t;
+            }
+        }
+    }
+
+    pub(super) fn coarse_x(&self) -> u16 {
+        self.v & 0x001F
+    }
+
+    pub(super) fn coarse_y(&self) -> u16 {
+        (self.v >> 5) & 0x001F
+    }
+
+    pub(super) fn fine_y(&self) -> u16 {
+        (self.v >> 12) & 0x07
+    }
+
+    // tile_address returns the nametable address of the tile v points to.
+    pub(super) fn tile_address(&self) -> u16 {
+        0x2000 | (self.v & 0x0FFF)
+    }
+
+    // attribute_address returns the address of the attribute byte covering the tile v points
+    // to, each byte holding the palettes of a 4x4 tile area.
+    pub(super) fn attribute_address(&self) -> u16 {
+        0x23C0 | (self.v & 0x0C00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07)
+    }
+
+    // attribute_shift returns the position in the attribute byte of the palette of the 2x2 tile
+    // quadrant v points to: the top left one is in bits 0-1, then top right, bottom left and
+    // bottom right.
+    pub(super) fn attribute_shift(&self) -> u8 {
+        ((self.coarse_y() & 0x02) << 1 | (self.coarse_x() & 0x02)) as u8
+    }
+
+    // moves v to the next tile, switching to the horizontally adjacent nametable at the end of
+    // the current one.
+    pub(super) fn increment_x(&mut self) {
+        if self.coarse_x() == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    // moves v to the next row of pixels, switching to the vertically adjacent nametable after
+    // the 30th row of tiles. Coarse Y values past the 30th row wrap within the same nametable.
+    pub(super) fn increment_y(&mut self) {
+        if self.fine_y() < 7 {
+            self.v += 0x1000;
+            return;
+        }
+
+        self.v &= !0x7000;
+        let y = match self.coarse_y() {
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            31 => 0,
+            y => y + 1,
+        };
+        self.v = (self.v & !0x03E0) | y << 5;
+    }
+
+    // copies the horizontal position from t into v.
+    pub(super) fn copy_x(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    // copies the vertical position from t into v.
+    pub(super) fn copy_y(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+}
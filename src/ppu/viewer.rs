@@ -0,0 +1,214 @@
+// the graphics viewer draws both pattern tables side by side, with the four nametables laid out
+// below them as they are in the PPU's address space.
+use super::{color_index, PPU, RGB};
+use crate::cartridge::Cartridge;
+
+pub const VIEWER_WIDTH: usize = 512;
+pub const VIEWER_HEIGHT: usize = PATTERN_TABLE_SIZE + 480;
+
+// the CHR dump lays out every 8kb of CHR like the pattern tables, the two 4kb halves side by side,
+// one below the other.
+pub const CHR_WIDTH: usize = PATTERN_TABLE_SIZE * 2;
+
+// a pattern table is 16x16 tiles of 8x8 pixels.
+const PATTERN_TABLE_SIZE: usize = 128;
+
+// the CHR dump shows the 2 bit color indices as shades of gray, from black to white.
+const GRAYSCALE: [RGB; 4] = [
+    RGB { r: 0, g: 0, b: 0 },
+    RGB {
+        r: 85,
+        g: 85,
+        b: 85,
+    },
+    RGB {
+        r: 170,
+        g: 170,
+        b: 170,
+    },
+    RGB {
+        r: 255,
+        g: 255,
+        b: 255,
+    },
+];
+
+// tile_rgb decodes the 16 bytes of a tile into its 8x8 pixels, using the given colors for each
+// of the tile's 2 bit color indices.
+fn tile_rgb(planes: &[u8; 16], colors: &[RGB; 4]) -> [RGB; 64] {
+    let mut out = [RGB::default(); 64];
+    for row in 0..8 {
+        for col in 0..8 {
+            let color_idx = color_index(planes[row], planes[row + 8], 7 - col as u8);
+            out[row * 8 + col] = colors[color_idx as usize];
+        }
+    }
+    out
+}
+
+// render returns the viewer's image as rows of R, G, B bytes. The pattern tables are drawn with
+// the first background palette.
+pub fn render(ppu: &PPU) -> Vec<u8> {
+    let mut out = vec![0; VIEWER_WIDTH * VIEWER_HEIGHT * 3];
+
+    for table in 0..2 {
+        for tile in 0..256 {
+            let x = table * PATTERN_TABLE_SIZE + (tile % 16) * 8;
+            let y = (tile / 16) * 8;
+            let pixels = tile_rgb(
+                &tile_planes(ppu, table as u16 * 0x1000, tile as u8),
+                &colors(ppu, 0),
+            );
+            draw_tile(&mut out, VIEWER_WIDTH, x, y, &pixels);
+        }
+    }
+
+    let pattern_table = ppu.background_offset();
+    for nametable in 0..4 {
+        let base = 0x2000 + nametable as u16 * 0x0400;
+        for tile in 0..32 * 30 {
+            let (col, row) = (tile % 32, tile / 32);
+            let attr = ppu.nametable(base + 0x3C0 + (row as u16 / 4) * 8 + col as u16 / 4);
+            let shift = (row % 4 / 2) * 4 + (col % 4 / 2) * 2;
+            let palette = (attr >> shift) & 0x03;
+
+            let index = ppu.nametable(base + tile as u16);
+            let pixels = tile_rgb(
+                &tile_planes(ppu, pattern_table, index),
+                &colors(ppu, palette),
+            );
+            let x = (nametable % 2) * 256 + col * 8;
+            let y = PATTERN_TABLE_SIZE + (nametable / 2) * 240 + row * 8;
+            draw_tile(&mut out, VIEWER_WIDTH, x, y, &pixels);
+        }
+    }
+
+    out
+}
+
+// render_chr returns every tile of the cartridge's CHR ROM in grayscale, as the width, height and
+// rows of R, G, B bytes of the image. Cartridges with CHR RAM have their pattern tables, as they
+// are at power-on, rendered instead.
+pub fn render_chr(cartridge: &Cartridge) -> (usize, usize, Vec<u8>) {
+    let chr_rom = cartridge.chr_rom();
+    let tiles = if chr_rom.is_empty() {
+        (0..512).map(|tile| cartridge.chr_at(tile)).collect()
+    } else {
+        chr_rom
+            .chunks(16)
+            .map(|chunk| {
+                let mut planes = [0; 16];
+                planes[..chunk.len()].copy_from_slice(chunk);
+                planes
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let height = tiles.len().div_ceil(512) * PATTERN_TABLE_SIZE;
+    let mut out = vec![0; CHR_WIDTH * height * 3];
+    for (tile, planes) in tiles.iter().enumerate() {
+        let (bank, table, index) = (tile / 512, tile / 256 % 2, tile % 256);
+        let x = table * PATTERN_TABLE_SIZE + (index % 16) * 8;
+        let y = bank * PATTERN_TABLE_SIZE + (index / 16) * 8;
+        draw_tile(&mut out, CHR_WIDTH, x, y, &tile_rgb(planes, &GRAYSCALE));
+    }
+    (CHR_WIDTH, height, out)
+}
+
+fn tile_planes(ppu: &PPU, pattern_table: u16, tile: u8) -> [u8; 16] {
+    let tile = pattern_table as usize / 16 + tile as usize;
+    ppu.cartridge.borrow().chr_at(tile)
+}
+
+// returns the colors of one of the background palettes, color 0 being the backdrop. PPUMASK's
+// grayscale and emphasis bits are ignored.
+fn colors(ppu: &PPU, palette: u8) -> [RGB; 4] {
+    let palette_ram = ppu.palette_ram();
+    let mut colors = [RGB::default(); 4];
+    for (i, color) in colors.iter_mut().enumerate() {
+        let index = if i == 0 { 0 } else { palette as usize * 4 + i };
+        *color = ppu.palette.color(palette_ram[index] as usize & 0x3F);
+    }
+    colors
+}
+
+// draws a tile into an image of the given width with its top left corner at x, y.
+fn draw_tile(out: &mut [u8], width: usize, x: usize, y: usize, pixels: &[RGB; 64]) {
+    for (i, pixel) in pixels.iter().enumerate() {
+        let offset = ((y + i / 8) * width + x + i % 8) * 3;
+        out[offset..offset + 3].copy_from_slice(&[pixel.r, pixel.g, pixel.b]);
+    }
+}
+
+#[test]
+fn test_tile_rgb() {
+    let colors = [
+        RGB { r: 0, g: 0, b: 0 },
+        RGB { r: 1, g: 0, b: 0 },
+        RGB { r: 0, g: 2, b: 0 },
+        RGB { r: 0, g: 0, b: 3 },
+    ];
+    // the first row uses all four colors, the rest of the tile is color 0.
+    let mut planes = [0; 16];
+    planes[0] = 0b0101_0101;
+    planes[8] = 0b0011_0011;
+    let pixels = tile_rgb(&planes, &colors);
+
+    let row: Vec<RGB> = [0, 1, 2, 3, 0, 1, 2, 3]
+        .iter()
+        .map(|&i| colors[i])
+        .collect();
+    assert_eq!(pixels[..8], row[..]);
+    assert!(pixels[8..].iter().all(|p| *p == colors[0]));
+}
+
+#[test]
+fn test_render_chr() {
+    // an NROM cartridge whose CHR ROM only has two tiles drawn: the first is solid color 3 and
+    // the second has a single row of color 1 on top.
+    let mut data = crate::cartridge::test_rom(0, 1, 1);
+    let chr = 16 + 0x4000;
+    data[chr..chr + 16].copy_from_slice(&[0xFF; 16]);
+    data[chr + 16] = 0xFF;
+    let cartridge = Cartridge::from_data(data).unwrap();
+
+    let (width, height, rgb) = render_chr(&cartridge);
+    assert_eq!((width, height), (256, 128));
+    assert_eq!(rgb.len(), width * height * 3);
+    let pixel = |x: usize, y: usize| {
+        let offset = (y * width + x) * 3;
+        &rgb[offset..offset + 3]
+    };
+    assert_eq!(pixel(0, 0), [255, 255, 255]);
+    assert_eq!(pixel(7, 7), [255, 255, 255]);
+    assert_eq!(pixel(8, 0), [85, 85, 85]);
+    assert_eq!(pixel(15, 1), [0, 0, 0]);
+    // the second pattern table starts halfway across.
+    assert_eq!(pixel(128, 0), [0, 0, 0]);
+
+    let png = crate::png::encode(width, height, &rgb);
+    assert_eq!(png[16..24], [0, 0, 1, 0, 0, 0, 0, 128]);
+}
+
+#[test]
+fn test_render_chr_banks() {
+    // a CNROM cartridge with 32kb of CHR ROM, the last tile of each 8kb bank is solid color 3.
+    let mut data = crate::cartridge::test_rom(3, 2, 4);
+    let chr = 16 + 0x8000;
+    for bank in 0..4 {
+        let tile = chr + bank * 0x2000 + 511 * 16;
+        data[tile..tile + 16].copy_from_slice(&[0xFF; 16]);
+    }
+    let cartridge = Cartridge::from_data(data).unwrap();
+
+    let (width, height, rgb) = render_chr(&cartridge);
+    assert_eq!((width, height), (256, 512));
+    let pixel = |x: usize, y: usize| {
+        let offset = (y * width + x) * 3;
+        &rgb[offset..offset + 3]
+    };
+    for bank in 0..4 {
+        assert_eq!(pixel(255, bank * 128 + 127), [255, 255, 255]);
+        assert_eq!(pixel(247, bank * 128 + 119), [0, 0, 0]);
+    }
+}
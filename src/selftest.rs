@@ -0,0 +1,314 @@
+use crate::cartridge;
+use crate::console::Console;
+use crate::png::crc32;
+
+// every micro test reports its result by writing to this zero page address: 0x01 means the test
+// passed, any other value is a failure code.
+const RESULT_ADDR: usize = 0x00F0;
+const PASSED: u8 = 0x01;
+// tests that haven't reported a result after this many frames are considered failed.
+const FRAME_LIMIT: usize = 10;
+
+pub struct SelfTest {
+    pub name: &'static str,
+    // the program is loaded at $8000, which is also where the reset vector points to.
+    program: &'static [u8],
+    // the CHR ROM contents, the rest of the 8kb bank is zeroed.
+    chr: &'static [u8],
+    // the CRC32 of the frame the test passed in, as rows of R, G, B bytes, which catches rendering
+    // bugs the test itself can't see.
+    frame: u32,
+    // known failures are tests for behaviour that is not emulated correctly yet.
+    pub known_failure: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed { code: u8, scanline: u16, cycle: u64 },
+    // the test passed, but its frame isn't the expected one.
+    WrongFrame { crc32: u32 },
+    TimedOut,
+}
+
+pub static TESTS: &[SelfTest] = &[
+    SelfTest {
+        name: "cpu_adc_sbc_flags",
+        program: &[
+            0x18, // CLC
+            0xA9, 0x7F, // LDA #$7F
+            0x69, 0x01, // ADC #$01
+            0x50, 0x12, // BVC fail
+            0x10, 0x10, // BPL fail
+            0xB0, 0x0E, // BCS fail
+            0x38, // SEC
+            0xE9, 0x01, // SBC #$01
+            0x50, 0x09, // BVC fail
+            0x90, 0x07, // BCC fail
+            0xA9, 0x01, // LDA #$01
+            0x85, 0xF0, // STA $F0
+            0x4C, 0x16, 0x80, // JMP *
+            0xA9, 0x02, // fail: LDA #$02
+            0x85, 0xF0, // STA $F0
+            0x4C, 0x1D, 0x80, // JMP *
+        ],
+        chr: &[],
+        frame: 0x2BE0_2AC6,
+        known_failure: false,
+    },
+    SelfTest {
+        name: "cpu_stack",
+        program: &[
+            0xA2, 0xFF, // LDX #$FF
+            0x9A, // TXS
+            0xA9, 0x42, // LDA #$42
+            0x48, // PHA
+            0xA9, 0x00, // LDA #$00
+            0x68, // PLA
+            0xC9, 0x42, // CMP #$42
+            0xD0, 0x10, // BNE fail
+            0xBA, // TSX
+            0xE0, 0xFF, // CPX #$FF
+            0xD0, 0x0B, // BNE fail
+            0x20, 0x1C, 0x80, // JSR $801C
+            0xA9, 0x01, // LDA #$01
+            0x85, 0xF0, // STA $F0
+            0x4C, 0x19, 0x80, // JMP *
+            0x60, // RTS
+            0xA9, 0x02, // fail: LDA #$02
+            0x85, 0xF0, // STA $F0
+            0x4C, 0x21, 0x80, // JMP *
+        ],
+        chr: &[],
+        frame: 0x2BE0_2AC6,
+        known_failure: false,
+    },
+    SelfTest {
+        name: "ppu_vblank_flag",
+        program: &[
+            0x2C, 0x02, 0x20, // BIT $2002
+            0x10, 0xFB, // BPL $8000
+            0xA9, 0x01, // LDA #$01
+            0x85, 0xF0, // STA $F0
+            0x4C, 0x09, 0x80, // JMP *
+        ],
+        chr: &[],
+        frame: 0x2BE0_2AC6,
+        known_failure: false,
+    },
+    SelfTest {
+        // all 64 sprites sit on the first scanlines, so the overflow flag must be set.
+        name: "ppu_sprite_overflow",
+        program: &[
+            0xA9, 0x18, // LDA #$18
+            0x8D, 0x01, 0x20, // STA $2001
+            0xA9, 0x20, // LDA #$20
+            0x2C, 0x02, 0x20, // BIT $2002
+            0xF0, 0xFB, // BEQ $8007
+            0xA9, 0x01, // LDA #$01
+            0x85, 0xF0, // STA $F0
+            0x4C, 0x10, 0x80, // JMP *
+        ],
+        chr: &[],
+        frame: 0x2BE0_2AC6,
+        known_failure: false,
+    },
+    SelfTest {
+        // sprite 0 uses a solid tile and sits over a solid background, the rest of the sprites
+        // are moved off screen. Both use the pattern table at $0000.
+        name: "ppu_sprite_zero_hit",
+        program: &[
+            0xA9, 0x00, // LDA #$00
+            0x8D, 0x00, 0x20, // STA $2000
+            0x8D, 0x03, 0x20, // STA $2003
+            0xA2, 0x00, // LDX #$00
+            0xA9, 0xFF, // LDA #$FF
+            0x8D, 0x04, 0x20, // STA $2004
+            0xE8, // INX
+            0xD0, 0xFA, // BNE $800C
+            0xA9, 0x20, // LDA #$20
+            0x8D, 0x04, 0x20, // STA $2004
+            0xA9, 0x01, // LDA #$01
+            0x8D, 0x04, 0x20, // STA $2004
+            0xA9, 0x00, // LDA #$00
+            0x8D, 0x04, 0x20, // STA $2004
+            0xA9, 0x20, // LDA #$20
+            0x8D, 0x04, 0x20, // STA $2004
+            0xA9, 0x1E, // LDA #$1E
+            0x8D, 0x01, 0x20, // STA $2001
+            0x2C, 0x02, 0x20, // BIT $2002
+            0x50, 0xFB, // BVC $802B
+            0xA9, 0x01, // LDA #$01
+            0x85, 0xF0, // STA $F0
+            0x4C, 0x34, 0x80, // JMP *
+        ],
+        chr: &[
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0, // tile 0
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0, // tile 1
+        ],
+        frame: 0x2BE0_2AC6,
+        known_failure: false,
+    },
+    SelfTest {
+        // a single solid tile in the second column of the first nametable, scrolled 4 pixels to
+        // the left. Only the frame tells whether it ends up at the right place.
+        name: "ppu_scroll",
+        program: &[
+            0x2C, 0x02, 0x20, // BIT $2002
+            0x10, 0xFB, // BPL $8000
+            0xA9, 0x3F, // LDA #$3F
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA9, 0x01, // LDA #$01
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA9, 0x30, // LDA #$30
+            0x8D, 0x07, 0x20, // STA $2007
+            0xA9, 0x20, // LDA #$20
+            0x8D, 0x06, 0x20, // STA $2006
+            0xA9, 0x01, // LDA #$01
+            0x8D, 0x06, 0x20, // STA $2006
+            0x8D, 0x07, 0x20, // STA $2007
+            0xA9, 0x04, // LDA #$04
+            0x8D, 0x05, 0x20, // STA $2005
+            0xA9, 0x00, // LDA #$00
+            0x8D, 0x05, 0x20, // STA $2005
+            0x8D, 0x00, 0x20, // STA $2000
+            0xA9, 0x0A, // LDA #$0A
+            0x8D, 0x01, 0x20, // STA $2001
+            // rendering was enabled partway through the first frame, so the test passes in the
+            // frame after it.
+            0x2C, 0x02, 0x20, // BIT $2002
+            0x10, 0xFB, // BPL $8033
+            0x2C, 0x02, 0x20, // BIT $2002
+            0x10, 0xFB, // BPL $8038
+            0xA9, 0x01, // LDA #$01
+            0x85, 0xF0, // STA $F0
+            0x4C, 0x41, 0x80, // JMP *
+        ],
+        chr: &[
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // tile 0
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0, // tile 1
+        ],
+        frame: 0xDE5F_622E,
+        known_failure: false,
+    },
+];
+
+// builds an NROM image with a single 16kb PRG bank holding the program at $8000.
+fn rom(program: &[u8], chr: &[u8]) -> Vec<u8> {
+    let mut data = cartridge::test_program(program);
+    data[16 + 0x4000..16 + 0x4000 + chr.len()].copy_from_slice(chr);
+    data
+}
+
+// runs a test headless until it reports a result or the frame limit is reached. Passing tests
+// also have the frame they passed in checked.
+pub fn run(test: &SelfTest) -> Outcome {
+    let mut console =
+        Console::load_rom(&rom(test.program, test.chr)).expect("invalid self test ROM");
+
+    let mut frames = 0;
+    while frames < FRAME_LIMIT {
+        if console.step() {
+            frames += 1;
+        }
+
+        match console.cpu.ram()[RESULT_ADDR] {
+            0x00 => {}
+            PASSED => {
+                let crc32 = crc32(console.step_frame());
+                if crc32 != test.frame {
+                    return Outcome::WrongFrame { crc32 };
+                }
+                return Outcome::Passed;
+            }
+            code => {
+                return Outcome::Failed {
+                    code,
+                    scanline: console.ppu.borrow().scanline(),
+                    cycle: console.cpu.cycles,
+                }
+            }
+        }
+    }
+
+    Outcome::TimedOut
+}
+
+// runs every bundled test and prints a summary, see run_tests.
+pub fn run_all() -> bool {
+    run_tests(TESTS)
+}
+
+// runs the given tests and prints a summary, returns false if any test other than the known
+// failures did not pass.
+pub fn run_tests(tests: &[SelfTest]) -> bool {
+    let mut ok = true;
+    println!("{:<24} result", "test");
+    for test in tests {
+        let outcome = run(test);
+        let result = match outcome {
+            Outcome::Passed => "pass".to_string(),
+            Outcome::Failed {
+                code,
+                scanline,
+                cycle,
+            } => format!(
+                "FAIL (code {:02X} at scanline {}, cycle {})",
+                code, scanline, cycle
+            ),
+            Outcome::WrongFrame { crc32 } => format!("FAIL (frame CRC32 {:08X})", crc32),
+            Outcome::TimedOut => format!("FAIL (no result after {} frames)", FRAME_LIMIT),
+        };
+
+        if test.known_failure {
+            println!("{:<24} {} [known failure]", test.name, result);
+        } else {
+            ok &= outcome == Outcome::Passed;
+            println!("{:<24} {}", test.name, result);
+        }
+    }
+
+    ok
+}
+
+#[test]
+fn test_selftest_outcomes() {
+    let find = |name| TESTS.iter().find(|t| t.name == name).unwrap();
+
+    assert_eq!(run(find("cpu_adc_sbc_flags")), Outcome::Passed);
+
+    // a test that passes with the wrong picture on screen still fails.
+    let scroll = find("ppu_scroll");
+    let unscrolled = SelfTest {
+        frame: 0x2BE0_2AC6,
+        ..*scroll
+    };
+    assert_eq!(
+        run(&unscrolled),
+        Outcome::WrongFrame {
+            crc32: scroll.frame
+        }
+    );
+
+    for test in TESTS.iter().filter(|t| !t.known_failure) {
+        assert_eq!(run(test), Outcome::Passed, "{}", test.name);
+    }
+}
+
+#[test]
+fn test_known_failures() {
+    let passing = TESTS.iter().find(|t| t.name == "cpu_stack").unwrap();
+    // a test that never reports a result.
+    let failing = |known_failure| SelfTest {
+        name: "failing",
+        program: &[0x4C, 0x00, 0x80], // JMP *
+        chr: &[],
+        frame: 0,
+        known_failure,
+    };
+    assert_eq!(run(&failing(true)), Outcome::TimedOut);
+
+    // known failures are reported, but don't fail the run.
+    assert!(run_tests(&[SelfTest { ..*passing }, failing(true)]));
+    assert!(!run_tests(&[SelfTest { ..*passing }, failing(false)]));
+}
@@ -0,0 +1,67 @@
+use crate::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// the photodiode keeps seeing a bright pixel for this many scanlines after the PPU drew it.
+const LIGHT_SCANLINES: u16 = 20;
+// pixels brighter than this, on average across their channels, are seen by the photodiode.
+const LIGHT_THRESHOLD: u32 = 0xC0;
+
+// Zapper is the light gun, plugged into the second port in place of a controller. Games flash
+// the targets white and check whether the photodiode sees the light while the PPU draws them. See
+// https://wiki.nesdev.com/w/index.php/Zapper.
+#[derive(Debug, Default)]
+pub struct Zapper {
+    // the pixel the gun points at, or None when pointing off screen.
+    pub target: Option<(usize, usize)>,
+    pub trigger: bool,
+}
+
+impl Zapper {
+    // state returns the bits of $4017 driven by the gun, given the frame drawn so far and the
+    // scanline the PPU is at. Bit 3 is clear while light is detected and bit 4 is set while the
+    // trigger is pulled.
+    pub fn state(&self, screen: &[u8], scanline: u16) -> u8 {
+        let light = match self.target {
+            Some((x, y)) if x < SCREEN_WIDTH && y < SCREEN_HEIGHT => {
+                let drawn = scanline >= y as u16 && scanline < y as u16 + LIGHT_SCANLINES;
+                let offset = (y * SCREEN_WIDTH + x) * 3;
+                let brightness: u32 = screen[offset..offset + 3].iter().map(|&c| c as u32).sum();
+                drawn && brightness >= LIGHT_THRESHOLD * 3
+            }
+            _ => false,
+        };
+
+        let mut state = 0;
+        if !light {
+            state |= 0x08;
+        }
+        if self.trigger {
+            state |= 0x10;
+        }
+        state
+    }
+}
+
+#[test]
+fn test_light_sense() {
+    let mut screen = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    let offset = (100 * SCREEN_WIDTH + 50) * 3;
+    screen[offset..offset + 3].copy_from_slice(&[252, 252, 252]);
+
+    let mut zapper = Zapper {
+        target: Some((50, 100)),
+        trigger: false,
+    };
+    assert_eq!(zapper.state(&screen, 105), 0x00);
+    // the light is only seen for a few scanlines after the pixel is drawn.
+    assert_eq!(zapper.state(&screen, 99), 0x08);
+    assert_eq!(zapper.state(&screen, 100 + LIGHT_SCANLINES), 0x08);
+
+    // dark pixels and pointing off screen don't light it up.
+    zapper.target = Some((51, 100));
+    assert_eq!(zapper.state(&screen, 105), 0x08);
+    zapper.target = None;
+    assert_eq!(zapper.state(&screen, 105), 0x08);
+
+    zapper.trigger = true;
+    assert_eq!(zapper.state(&screen, 105), 0x18);
+}